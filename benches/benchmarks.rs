@@ -5,12 +5,15 @@ use std::sync::Arc;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use snork::agents::{maxn, FloodHeuristic, MobilityAgent, TreeHeuristic};
+use snork::agents::{maxn, FloodHeuristic, MobilityAgent, NeuralHeuristic, TreeHeuristic};
+use snork::bitboard::BitboardGame;
+use snork::bitflood::BitFloodFill;
 use snork::env::*;
-use snork::floodfill::FloodFill;
+use snork::floodfill::{FloodFill, IncrementalFloodFill};
 use snork::game::{Game, Outcome, Snake};
 use snork::logging;
-use snork::search::{self, Heuristic};
+use snork::nn::ValueNetwork;
+use snork::search::{self, Algorithm, Heuristic, MoveOrdering};
 
 #[derive(Debug, Clone, Default)]
 struct TestH;
@@ -19,7 +22,7 @@ impl Heuristic for TestH {
     fn eval(&self, game: &Game) -> f64 {
         let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
         if game.snake_is_alive(0) {
-            flood_fill.flood_snakes(&game.grid, &game.snakes);
+            flood_fill.flood_snakes(game);
             flood_fill.count_space(0) as f64
         } else {
             0.0
@@ -105,6 +108,43 @@ fn game_step_random(c: &mut Criterion) {
     });
 }
 
+fn game_make_unmake_move(c: &mut Criterion) {
+    logging();
+    let mut game = Game::parse(
+        r#"
+            . . . . . . . o . . .
+            o . . . . . . . . o .
+            . . o 3 . . . . . . .
+            . . . . . . 0 o . . .
+            . . o . . . . . . . .
+            . . . . . o . . . o .
+            . o . . o . . . . . .
+            . . . . . . . 2 . o .
+            . . . 1 . . . o . . .
+            o . . o . . . . . . .
+            . . . . . o . . o . ."#,
+    )
+    .unwrap();
+    use Direction::*;
+    let moves = [Right, Up, Left, Down];
+
+    let mut group = c.benchmark_group("game_move");
+    group.bench_function("clone_step", |b| {
+        b.iter(|| {
+            let mut game = game.clone();
+            game.step(black_box(&moves));
+            game
+        })
+    });
+    group.bench_function("make_unmake", |b| {
+        b.iter(|| {
+            let delta = game.make_move(black_box(&moves));
+            game.unmake_move(delta);
+        })
+    });
+    group.finish();
+}
+
 fn normal_max_n(c: &mut Criterion) {
     logging();
     let snakes = vec![
@@ -116,8 +156,49 @@ fn normal_max_n(c: &mut Criterion) {
     let game = Game::new(0, 11, 11, snakes, &[], &[]);
 
     c.bench_function("normal_max_n", |b| {
-        b.iter(|| search::max_n(black_box(&game), 2, &TestH))
+        b.iter(|| search::max_n(black_box(&game), 2, &TestH, None, None))
+    });
+}
+
+fn multi_player(c: &mut Criterion) {
+    logging();
+    let snakes = vec![
+        Snake::new(vec![v2(0, 3), v2(1, 3), v2(2, 3), v2(3, 3)].into(), 100),
+        Snake::new(vec![v2(3, 7), v2(3, 6), v2(3, 5)].into(), 100),
+        Snake::new(vec![v2(10, 7), v2(10, 6), v2(10, 5)].into(), 100),
+        Snake::new(vec![v2(10, 0), v2(9, 0), v2(8, 0)].into(), 100),
+    ];
+    let game = Game::new(0, 11, 11, snakes, &[], &[]);
+
+    let mut group = c.benchmark_group("multi_player");
+    group.bench_function("max_n", |b| {
+        b.iter(|| search::max_n(black_box(&game), 2, &TestH, None, None))
+    });
+    group.bench_function("paranoid", |b| {
+        b.iter(|| {
+            search::paranoid(
+                black_box(&game),
+                2,
+                &TestH,
+                None,
+                None,
+                &mut MoveOrdering::new(),
+            )
+        })
+    });
+    group.bench_function("best_reply", |b| {
+        b.iter(|| {
+            search::best_reply(
+                black_box(&game),
+                2,
+                &TestH,
+                None,
+                None,
+                &mut MoveOrdering::new(),
+            )
+        })
     });
+    group.finish();
 }
 
 fn async_max_n(c: &mut Criterion) {
@@ -132,7 +213,7 @@ fn async_max_n(c: &mut Criterion) {
 
     c.bench_function("async_max_n", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| search::async_max_n(black_box(&game), 2, Arc::new(TestH)))
+            .iter(|| search::async_max_n(black_box(&game), 2, Arc::new(TestH), None, None))
     });
 }
 
@@ -145,7 +226,16 @@ fn normal_alphabeta(c: &mut Criterion) {
     let game = Game::new(0, 11, 11, snakes, &[], &[]);
 
     c.bench_function("normal_alphabeta", |b| {
-        b.iter(|| search::alphabeta(black_box(&game), 5, &TestH))
+        b.iter(|| {
+            search::alphabeta(
+                black_box(&game),
+                5,
+                &TestH,
+                None,
+                None,
+                &mut MoveOrdering::new(),
+            )
+        })
     });
 }
 
@@ -159,7 +249,7 @@ fn async_alphabeta(c: &mut Criterion) {
 
     c.bench_function("async_alphabeta", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| search::async_alphabeta(black_box(&game), 5, Arc::new(TestH)))
+            .iter(|| search::async_alphabeta(black_box(&game), 5, Arc::new(TestH), None, None))
     });
 }
 
@@ -176,6 +266,63 @@ fn floodfill_normal(c: &mut Criterion) {
     });
 }
 
+fn floodfill_reuse(c: &mut Criterion) {
+    use rand::seq::IteratorRandom;
+    logging();
+    let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"bcb8c2e8-4fb7-485b-9ade-9df947dd9623","ruleset":{"name":"standard","version":"v1.0.15"},"timeout":500},"turn":69,"board":{"height":11,"width":11,"food":[{"x":7,"y":9},{"x":1,"y":0}],"hazards":[],"snakes":[{"id":"gs_3MjqcwQJxYG7VrvjbbkRW9JB","name":"Nessegrev-flood","health":85,"body":[{"x":7,"y":10},{"x":8,"y":10},{"x":8,"y":9},{"x":9,"y":9},{"x":10,"y":9},{"x":10,"y":8},{"x":10,"y":7}],"shout":""},{"id":"gs_c9JrKQcQqHHPJFm43W47RKMd","name":"Rufio the Tenacious","health":80,"body":[{"x":5,"y":8},{"x":4,"y":8},{"x":4,"y":9},{"x":3,"y":9},{"x":2,"y":9},{"x":2,"y":8},{"x":2,"y":7}],"shout":""},{"id":"gs_ffjK7pqCwVXYGtwhWtk3vtJX","name":"marrrvin","health":89,"body":[{"x":8,"y":7},{"x":8,"y":8},{"x":7,"y":8},{"x":7,"y":7},{"x":7,"y":6},{"x":6,"y":6},{"x":5,"y":6},{"x":5,"y":5},{"x":6,"y":5}],"shout":""},{"id":"gs_Kr6BCBwbDpdGDpWbw9vMS6qV","name":"kostka","health":93,"body":[{"x":7,"y":2},{"x":7,"y":3},{"x":6,"y":3},{"x":5,"y":3},{"x":4,"y":3},{"x":3,"y":3}],"shout":""}]},"you":{"id":"gs_ffjK7pqCwVXYGtwhWtk3vtJX","name":"marrrvin","health":89,"body":[{"x":8,"y":7},{"x":8,"y":8},{"x":7,"y":8},{"x":7,"y":7},{"x":7,"y":6},{"x":6,"y":6},{"x":5,"y":6},{"x":5,"y":5},{"x":6,"y":5}],"shout":""}}"#
+        ).unwrap();
+
+    // Consecutive turns of a random game
+    let mut game = Game::from_request(&request);
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut turns = vec![game.clone()];
+    while turns.len() < 16 && game.outcome() == Outcome::None {
+        let moves: Vec<_> = (0..game.snakes.len())
+            .map(|i| (game.valid_moves(i as u8).choose(&mut rng)).unwrap_or(Direction::Up))
+            .collect();
+        game.step(&moves);
+        turns.push(game.clone());
+    }
+    let (width, height) = (game.grid.width, game.grid.height);
+
+    let mut group = c.benchmark_group("floodfill");
+    group.bench_function("flood_snakes", |b| {
+        b.iter(|| {
+            for game in &turns {
+                let mut flood_fill = FloodFill::new(width, height);
+                black_box(flood_fill.flood_snakes(black_box(game)));
+            }
+        })
+    });
+    group.bench_function("flood_snakes_reused", |b| {
+        let mut flood_fill = FloodFill::new(width, height);
+        b.iter(|| {
+            for game in &turns {
+                black_box(flood_fill.flood_snakes(black_box(game)));
+            }
+        })
+    });
+    group.bench_function("workspace", |b| {
+        b.iter(|| {
+            for game in &turns {
+                FloodFill::with_workspace(width, height, |flood_fill| {
+                    black_box(flood_fill.flood_snakes(black_box(game)))
+                });
+            }
+        })
+    });
+    group.bench_function("incremental", |b| {
+        let mut flood_fill = IncrementalFloodFill::new(width, height);
+        b.iter(|| {
+            for game in &turns {
+                black_box(flood_fill.update(black_box(game)));
+            }
+        })
+    });
+    group.finish();
+}
+
 fn tree_heuristic(c: &mut Criterion) {
     logging();
     let request: GameRequest = serde_json::from_str(
@@ -201,7 +348,18 @@ fn tree_search(c: &mut Criterion) {
 
     c.bench_function("tree_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 3))
+            .iter(|| async {
+                maxn::tree_search(
+                    heuristic.clone(),
+                    Algorithm::MaxN,
+                    black_box(&game),
+                    3,
+                    None,
+                    None,
+                    &mut MoveOrdering::new(),
+                )
+                .await
+            })
     });
 }
 
@@ -219,6 +377,24 @@ fn flood_heuristic(c: &mut Criterion) {
     });
 }
 
+fn neural_heuristic(c: &mut Criterion) {
+    logging();
+    let mut rng = SmallRng::seed_from_u64(0);
+    let game = snork::simulate::init_game(11, 11, 4, &mut rng);
+
+    let mut group = c.benchmark_group("neural_heuristic");
+    for (channels, hidden) in [(&[][..], &[16][..]), (&[8], &[16]), (&[16, 16], &[32])] {
+        let network = ValueNetwork::random(channels, hidden, &mut rng);
+        let heuristic = NeuralHeuristic::new(network, Algorithm::MaxN);
+        group.bench_with_input(
+            BenchmarkId::new("conv", format!("{channels:?}")),
+            &game,
+            |b, game| b.iter(|| heuristic.eval(black_box(game))),
+        );
+    }
+    group.finish();
+}
+
 fn flood_search(c: &mut Criterion) {
     logging();
     let request: GameRequest = serde_json::from_str(
@@ -230,7 +406,18 @@ fn flood_search(c: &mut Criterion) {
 
     c.bench_function("flood_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 3))
+            .iter(|| async {
+                maxn::tree_search(
+                    heuristic.clone(),
+                    Algorithm::MaxN,
+                    black_box(&game),
+                    3,
+                    None,
+                    None,
+                    &mut MoveOrdering::new(),
+                )
+                .await
+            })
     });
 }
 
@@ -245,7 +432,18 @@ fn flood_2_search(c: &mut Criterion) {
 
     c.bench_function("flood_2_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 6))
+            .iter(|| async {
+                maxn::tree_search(
+                    heuristic.clone(),
+                    Algorithm::MaxN,
+                    black_box(&game),
+                    6,
+                    None,
+                    None,
+                    &mut MoveOrdering::new(),
+                )
+                .await
+            })
     });
 }
 
@@ -276,6 +474,48 @@ impl Display for PF4 {
     }
 }
 
+fn bitboard(c: &mut Criterion) {
+    logging();
+    let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"17d30fe5-a90f-45c0-bb81-1f8bd54781e1","ruleset":{"damagePerTurn":"14","foodSpawnChance":"15","minimumFood":"1","name":"royale","shrinkEveryNTurns":"25"},"timeout":500},"turn":64,"board":{"width":11,"height":11,"food":[{"x":10,"y":7}],"hazards":[{"x":0,"y":0},{"x":0,"y":1},{"x":0,"y":2},{"x":0,"y":3},{"x":0,"y":4},{"x":0,"y":5},{"x":0,"y":6},{"x":0,"y":7},{"x":0,"y":8},{"x":0,"y":9},{"x":0,"y":10},{"x":1,"y":0},{"x":2,"y":0},{"x":3,"y":0},{"x":4,"y":0},{"x":5,"y":0},{"x":6,"y":0},{"x":7,"y":0},{"x":8,"y":0},{"x":9,"y":0},{"x":10,"y":0}],"snakes":[{"id":"gs_c6BKHbpSr47cqd76mmWTj7dB","name":"unsigned long long","body":[{"x":5,"y":7},{"x":5,"y":6},{"x":5,"y":5},{"x":4,"y":5},{"x":3,"y":5},{"x":2,"y":5}],"health":93,"latency":471,"head":{"x":5,"y":7},"length":6,"shout":"","squad":""},{"id":"gs_BWkm6pVmC6kTmYShrGTrRHfW","name":"marrrvin","body":[{"x":4,"y":4},{"x":3,"y":4},{"x":3,"y":3},{"x":2,"y":3},{"x":1,"y":3}],"health":56,"latency":25,"head":{"x":4,"y":4},"length":5,"shout":"","squad":""},{"id":"gs_Q6FcKJtmmFCC6YtvTM4RVqXM","name":"marrrvin","body":[{"x":7,"y":7},{"x":7,"y":6},{"x":7,"y":5},{"x":8,"y":5},{"x":9,"y":5},{"x":9,"y":4}],"health":86,"latency":26,"head":{"x":7,"y":7},"length":6,"shout":"","squad":""},{"id":"gs_kqMqF4c7rCppw9mSm7vT6Xvb","name":"marrrvin","body":[{"x":9,"y":3},{"x":9,"y":2},{"x":8,"y":2},{"x":7,"y":2},{"x":7,"y":1}],"health":72,"latency":29,"head":{"x":9,"y":3},"length":5,"shout":"","squad":""}]},"you":{"id":"gs_c6BKHbpSr47cqd76mmWTj7dB","name":"unsigned long long","body":[{"x":5,"y":7},{"x":5,"y":6},{"x":5,"y":5},{"x":4,"y":5},{"x":3,"y":5},{"x":2,"y":5}],"health":93,"latency":471,"head":{"x":5,"y":7},"length":6,"shout":"","squad":""}}"#
+        ).unwrap();
+
+    let mut game = Game::from_request(&request);
+    let mut bitboard = BitboardGame::<u128>::from_game(&game).unwrap();
+    let heuristic = FloodHeuristic::default();
+    use Direction::*;
+    let moves = [Up, Left, Up, Up];
+
+    let mut group = c.benchmark_group("bitboard");
+    group.bench_function("make_unmake", |b| {
+        b.iter(|| {
+            let delta = game.make_move(black_box(&moves));
+            game.unmake_move(delta);
+        })
+    });
+    group.bench_function("make_unmake_bitboard", |b| {
+        b.iter(|| {
+            let prev = bitboard.make_move(black_box(&moves));
+            bitboard.unmake_move(prev);
+        })
+    });
+    group.bench_function("floodfill", |b| {
+        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+        b.iter(|| flood_fill.flood_snakes(black_box(&game)))
+    });
+    group.bench_function("floodfill_bitboard", |b| {
+        let mut flood_fill = BitFloodFill::new();
+        b.iter(|| flood_fill.flood_snakes(black_box(&bitboard)))
+    });
+    group.bench_function("flood_max_n", |b| {
+        b.iter(|| search::max_n(black_box(&game), 2, &heuristic, None, None))
+    });
+    group.bench_function("flood_max_n_bitboard", |b| {
+        b.iter(|| search::max_n(black_box(&bitboard), 2, &heuristic, None, None))
+    });
+    group.finish();
+}
+
 fn math(c: &mut Criterion) {
     let mut group = c.benchmark_group("math");
     let mut rng = SmallRng::seed_from_u64(42);
@@ -296,14 +536,19 @@ criterion_group!(
     benches,
     game_step_circle,
     game_step_random,
+    game_make_unmake_move,
     async_max_n,
     normal_max_n,
+    multi_player,
     async_alphabeta,
     normal_alphabeta,
     floodfill_normal,
+    floodfill_reuse,
+    bitboard,
     tree_heuristic,
     tree_search,
     flood_heuristic,
+    neural_heuristic,
     flood_search,
     flood_2_search,
     mobility_agent,
@@ -140,6 +140,8 @@ def get_cs(agentlist=["Flood"]):
             "space_adv": {"min": -6, "max": 6, "default": 0.9, "log": False},
             "size_adv": {"min": -15, "max": 15, "default": 6.4, "log": False},
             "size_adv_decay": {"min": 1e-10, "max": 0.1, "default": 1e-10, "log": True},
+            "trap": {"min": -2, "max": 2, "default": 0.0, "log": False},
+            "trap_threat": {"min": -2, "max": 2, "default": 0.0, "log": False},
         }
 
         for hp in flood_hps:
@@ -0,0 +1,993 @@
+//! # Bitboards
+//!
+//! A compact game representation that stores the board as bitsets.
+//!
+//! The whole game state is `Copy`, which makes it cheap to store, and the
+//! moves of the tree searches are reverted with small [BitDelta]s. The default [BitboardGame] fits boards of up to
+//! 11x11 cells ([u128]), the [WideBitboardGame] up to 19x19 ([U384]).
+
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Not, Shl, Shr};
+
+use crate::env::{Direction, Vec2D};
+use crate::game::{Game, GameSettings, Outcome, Snake, MAX_SNAKES};
+use crate::grid::CellT;
+use crate::rules::GameMode;
+use crate::zobrist;
+
+/// A fixed size set of cells, where the cell `x + y * width` is represented
+/// by the bit with the same index.
+pub trait BitSet:
+    Copy
+    + Default
+    + Eq
+    + fmt::Debug
+    + Send
+    + Sync
+    + 'static
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + BitAndAssign
+    + BitOrAssign
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+{
+    /// Number of cells this set can store.
+    const BITS: u32;
+
+    /// Returns a set that only contains `i`.
+    fn bit(i: u16) -> Self;
+    /// Returns if `i` is part of the set.
+    fn has(self, i: u16) -> bool;
+    /// Returns the number of cells in the set.
+    fn count(self) -> u32;
+    /// Returns the smallest cell of the set.
+    fn first(self) -> Option<u16>;
+    /// Returns the `game` as one of the supported bitboard sizes.
+    fn sized(game: &BitboardGame<Self>) -> SizedBitboard<'_>;
+
+    fn is_empty(self) -> bool {
+        self == Self::default()
+    }
+
+    /// Returns the set with `i` added or removed.
+    fn with(self, i: u16, value: bool) -> Self {
+        if value {
+            self | Self::bit(i)
+        } else {
+            self & !Self::bit(i)
+        }
+    }
+
+    /// Iterates over all cells of the set in ascending order.
+    fn iter(self) -> BitIter<Self> {
+        BitIter(self)
+    }
+}
+
+/// Iterator over the cells of a [BitSet].
+pub struct BitIter<B>(B);
+
+impl<B: BitSet> Iterator for BitIter<B> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        let i = self.0.first()?;
+        self.0 &= !B::bit(i);
+        Some(i)
+    }
+}
+
+impl BitSet for u128 {
+    const BITS: u32 = 128;
+
+    fn sized(game: &BitboardGame<Self>) -> SizedBitboard<'_> {
+        SizedBitboard::Default(game)
+    }
+
+    #[inline]
+    fn bit(i: u16) -> Self {
+        1 << i
+    }
+    #[inline]
+    fn has(self, i: u16) -> bool {
+        (self >> i) & 1 != 0
+    }
+    #[inline]
+    fn count(self) -> u32 {
+        self.count_ones()
+    }
+    #[inline]
+    fn first(self) -> Option<u16> {
+        (self != 0).then(|| self.trailing_zeros() as u16)
+    }
+}
+
+/// Bitset with 384 bits for boards of up to 19x19 cells.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U384(pub [u64; 6]);
+
+impl fmt::Debug for U384 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "U384(")?;
+        for w in self.0.iter().rev() {
+            write!(f, "{w:016x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+macro_rules! u384_op {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $o:tt) => {
+        impl $Op for U384 {
+            type Output = Self;
+            #[inline]
+            fn $op(self, rhs: Self) -> Self {
+                Self(std::array::from_fn(|i| self.0[i] $o rhs.0[i]))
+            }
+        }
+        impl $OpAssign for U384 {
+            #[inline]
+            fn $op_assign(&mut self, rhs: Self) {
+                *self = *self $o rhs;
+            }
+        }
+    };
+}
+u384_op!(BitAnd, bitand, BitAndAssign, bitand_assign, &);
+u384_op!(BitOr, bitor, BitOrAssign, bitor_assign, |);
+
+impl BitXor for U384 {
+    type Output = Self;
+    #[inline]
+    fn bitxor(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] ^ rhs.0[i]))
+    }
+}
+
+impl Not for U384 {
+    type Output = Self;
+    #[inline]
+    fn not(self) -> Self {
+        Self(self.0.map(|w| !w))
+    }
+}
+
+impl Shl<u32> for U384 {
+    type Output = Self;
+    fn shl(self, n: u32) -> Self {
+        let (q, r) = ((n / 64) as usize, n % 64);
+        let mut out = [0; 6];
+        for (i, out) in out.iter_mut().enumerate().skip(q) {
+            *out = self.0[i - q] << r;
+            if r > 0 && i > q {
+                *out |= self.0[i - q - 1] >> (64 - r);
+            }
+        }
+        Self(out)
+    }
+}
+
+impl Shr<u32> for U384 {
+    type Output = Self;
+    fn shr(self, n: u32) -> Self {
+        let (q, r) = ((n / 64) as usize, n % 64);
+        let mut out = [0; 6];
+        for (i, out) in out.iter_mut().enumerate().take(6usize.saturating_sub(q)) {
+            *out = self.0[i + q] >> r;
+            if r > 0 && i + q + 1 < 6 {
+                *out |= self.0[i + q + 1] << (64 - r);
+            }
+        }
+        Self(out)
+    }
+}
+
+impl BitSet for U384 {
+    const BITS: u32 = 384;
+
+    fn sized(game: &BitboardGame<Self>) -> SizedBitboard<'_> {
+        SizedBitboard::Wide(game)
+    }
+
+    #[inline]
+    fn bit(i: u16) -> Self {
+        let mut out = [0; 6];
+        out[i as usize / 64] = 1 << (i % 64);
+        Self(out)
+    }
+    #[inline]
+    fn has(self, i: u16) -> bool {
+        (self.0[i as usize / 64] >> (i % 64)) & 1 != 0
+    }
+    #[inline]
+    fn count(self) -> u32 {
+        self.0.iter().map(|w| w.count_ones()).sum()
+    }
+    #[inline]
+    fn first(self) -> Option<u16> {
+        let i = self.0.iter().position(|&w| w != 0)?;
+        Some((i * 64) as u16 + self.0[i].trailing_zeros() as u16)
+    }
+}
+
+/// A snake on the bitboard.
+///
+/// Instead of storing the segments in order, every body cell stores the
+/// direction to the next segment towards the head in two bit planes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BitSnake<B: BitSet> {
+    /// All cells occupied by the snake, including the head.
+    pub body: B,
+    dirs: [B; 2],
+    pub head: u16,
+    pub tail: u16,
+    /// Number of additional segments on the tail cell (after eating).
+    pub stacked: u16,
+    /// Number of segments, including the stacked ones.
+    pub len: u16,
+    pub health: u8,
+}
+
+impl<B: BitSet> BitSnake<B> {
+    pub fn alive(&self) -> bool {
+        self.health > 0
+    }
+
+    /// Returns the direction from a body cell to the next segment.
+    fn dir(&self, i: u16) -> Direction {
+        Direction::from(self.dirs[0].has(i) as u8 | (self.dirs[1].has(i) as u8) << 1)
+    }
+
+    fn set_dir(&mut self, i: u16, d: Direction) {
+        let d = d as u8;
+        self.dirs[0] = self.dirs[0].with(i, d & 1 != 0);
+        self.dirs[1] = self.dirs[1].with(i, d & 2 != 0);
+    }
+}
+
+/// The state of a snake before a [BitboardGame::make_move].
+#[derive(Clone, Copy, Default, Debug)]
+struct SnakeDelta {
+    head: u16,
+    tail: u16,
+    stacked: u16,
+    len: u16,
+    /// Zero if the snake was already dead
+    health: u8,
+    /// Directions of the tail (lower two bits) and the head
+    dirs: u8,
+}
+
+/// The changes of [BitboardGame::make_move], which are needed to revert it.
+///
+/// Only the changed words are stored instead of the whole game:
+/// the ends of the moved snakes, the eaten food and the bodies of the
+/// eliminated snakes, which do not overlap and are thus merged.
+#[derive(Clone, Copy, Debug)]
+pub struct BitDelta<B: BitSet> {
+    snakes: [SnakeDelta; MAX_SNAKES],
+    food: B,
+    /// Bodies and the two direction planes of the eliminated snakes
+    eliminated: [B; 3],
+}
+
+/// Game state that stores the board as bitsets.
+///
+/// It implements the standard rules, including the topology and growth of
+/// the other game modes, and is convertible from and to [Game].
+/// Dead snakes are reset to their default with zero health.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BitboardGame<B: BitSet = u128> {
+    pub turn: usize,
+    pub width: u16,
+    pub height: u16,
+    pub food: B,
+    pub hazards: B,
+    pub snakes: [BitSnake<B>; MAX_SNAKES],
+    pub snake_count: u8,
+    pub mode: GameMode,
+    pub settings: GameSettings,
+    wrapped: bool,
+    /// All cells of the board
+    full: B,
+    /// Cells of the left and right columns
+    left: B,
+    right: B,
+}
+
+/// Bitboard for boards of up to 19x19 cells.
+pub type WideBitboardGame = BitboardGame<U384>;
+
+/// Reference to a bitboard of one of the supported sizes, see
+/// [BitSet::sized].
+#[derive(Clone, Copy, Debug)]
+pub enum SizedBitboard<'a> {
+    /// Boards of up to 128 cells
+    Default(&'a BitboardGame),
+    /// Boards of up to 19x19 cells
+    Wide(&'a WideBitboardGame),
+}
+
+impl<B: BitSet> BitboardGame<B> {
+    /// Converts the game, if the board and number of snakes fits and there
+    /// are no obstacles besides the snakes.
+    pub fn from_game(game: &Game) -> Option<Self> {
+        let (width, height) = (game.grid.width, game.grid.height);
+        if width * height > B::BITS as usize
+            || game.snakes.len() > MAX_SNAKES
+            || !game.obstacles.is_empty()
+        {
+            return None;
+        }
+
+        let mut full = B::default();
+        let mut left = B::default();
+        let mut right = B::default();
+        for y in 0..height {
+            left |= B::bit((y * width) as u16);
+            right |= B::bit((y * width + width - 1) as u16);
+            for x in 0..width {
+                full |= B::bit((y * width + x) as u16);
+            }
+        }
+
+        let mut bitboard = Self {
+            turn: game.turn,
+            width: width as _,
+            height: height as _,
+            food: B::default(),
+            hazards: B::default(),
+            snakes: [BitSnake::default(); MAX_SNAKES],
+            snake_count: game.snakes.len() as _,
+            mode: game.mode,
+            settings: game.settings,
+            wrapped: game.grid.wrapped,
+            full,
+            left,
+            right,
+        };
+
+        for (i, cell) in game.grid.cells.iter().enumerate() {
+            bitboard.food = bitboard.food.with(i as _, cell.t == CellT::Food);
+            bitboard.hazards = bitboard.hazards.with(i as _, cell.hazard);
+        }
+
+        for (i, snake) in game.snakes.iter().enumerate() {
+            if snake.alive() && !snake.eliminated() {
+                bitboard.snakes[i] = bitboard.snake_from(snake);
+            }
+        }
+
+        // Obstacles that are not part of a snake are not supported
+        let owned = game
+            .grid
+            .cells
+            .iter()
+            .filter(|c| c.t == CellT::Owned)
+            .count();
+        let bodies = (0..bitboard.snake_count as usize)
+            .map(|i| bitboard.snakes[i].body.count())
+            .sum::<u32>();
+        if owned != bodies as usize {
+            return None;
+        }
+        Some(bitboard)
+    }
+
+    fn snake_from(&self, snake: &Snake) -> BitSnake<B> {
+        let mut bitsnake = BitSnake {
+            tail: self.index(snake.body[0]),
+            head: self.index(snake.head()),
+            len: snake.body.len() as _,
+            health: snake.health,
+            ..Default::default()
+        };
+        bitsnake.stacked = snake
+            .body
+            .iter()
+            .skip(1)
+            .take_while(|&&p| p == snake.body[0])
+            .count() as _;
+
+        let mut last = snake.body[0];
+        bitsnake.body = B::bit(self.index(last));
+        for &p in snake.body.iter().skip(1) {
+            if p != last {
+                let i = self.index(last);
+                bitsnake.set_dir(i, self.direction(last, p));
+                bitsnake.body |= B::bit(self.index(p));
+                last = p;
+            }
+        }
+        bitsnake
+    }
+
+    /// Converts the bitboard back into a game.
+    pub fn to_game(&self) -> Game {
+        let snakes = self.snakes[..self.snake_count as usize]
+            .iter()
+            .map(|s| {
+                let mut body = std::collections::VecDeque::with_capacity(s.len as _);
+                if s.alive() {
+                    body.extend(std::iter::repeat_n(self.pos(s.tail), s.stacked as usize));
+                    body.extend(self.body(s).map(|i| self.pos(i)));
+                }
+                Snake::new(body, s.health)
+            })
+            .collect();
+
+        let food: Vec<Vec2D> = self.food.iter().map(|i| self.pos(i)).collect();
+        let hazards: Vec<Vec2D> = self.hazards.iter().map(|i| self.pos(i)).collect();
+        let mut game = Game::new(
+            self.turn,
+            self.width as _,
+            self.height as _,
+            snakes,
+            &food,
+            &hazards,
+        );
+        game.set_mode(self.mode);
+        game.settings = self.settings;
+        game
+    }
+
+    /// Iterates over the cells of the snake from tail to head, skipping the
+    /// stacked segments.
+    pub fn body<'a>(&'a self, snake: &'a BitSnake<B>) -> impl Iterator<Item = u16> + 'a {
+        let mut next = snake.alive().then_some(snake.tail);
+        std::iter::from_fn(move || {
+            let i = next?;
+            next = (i != snake.head).then(|| self.apply(i, snake.dir(i)).unwrap());
+            Some(i)
+        })
+    }
+
+    /// Returns the cell index of `p`.
+    #[inline]
+    pub fn index(&self, p: Vec2D) -> u16 {
+        p.x as u16 + p.y as u16 * self.width
+    }
+
+    /// Returns the position of the cell index `i`.
+    #[inline]
+    pub fn pos(&self, i: u16) -> Vec2D {
+        Vec2D::new((i % self.width) as _, (i / self.width) as _)
+    }
+
+    /// Returns the neighbor of `i` in the direction `d`, or `None` if this
+    /// is outside of the board.
+    #[inline]
+    pub fn apply(&self, i: u16, d: Direction) -> Option<u16> {
+        let (x, y) = (i % self.width, i / self.width);
+        let (w, h) = (self.width, self.height);
+        match d {
+            Direction::Up if y + 1 < h => Some(i + w),
+            Direction::Up if self.wrapped => Some(x),
+            Direction::Right if x + 1 < w => Some(i + 1),
+            Direction::Right if self.wrapped => Some(i + 1 - w),
+            Direction::Down if y > 0 => Some(i - w),
+            Direction::Down if self.wrapped => Some(i + w * (h - 1)),
+            Direction::Left if x > 0 => Some(i - 1),
+            Direction::Left if self.wrapped => Some(i + w - 1),
+            _ => None,
+        }
+    }
+
+    /// Returns the direction from `p` to its neighbor `next`.
+    fn direction(&self, p: Vec2D, next: Vec2D) -> Direction {
+        Direction::all()
+            .into_iter()
+            .find(|&d| self.apply(self.index(p), d) == Some(self.index(next)))
+            .unwrap_or_else(|| Direction::from(next - p))
+    }
+
+    /// Returns all cells that are adjacent to the `cells`.
+    #[inline]
+    pub fn neighbors(&self, cells: B) -> B {
+        let w = self.width as u32;
+        let shift = w * (self.height as u32 - 1);
+        let mut n = ((cells << w) & self.full)
+            | (cells >> w)
+            | ((cells << 1) & !self.left & self.full)
+            | ((cells >> 1) & !self.right);
+        if self.wrapped {
+            n |= (cells >> shift)
+                | ((cells << shift) & self.full)
+                | ((cells & self.right) >> (w - 1))
+                | ((cells & self.left) << (w - 1));
+        }
+        n
+    }
+
+    /// Returns all cells of the board.
+    pub fn cells(&self) -> B {
+        self.full
+    }
+
+    /// Returns the cells occupied by the living snakes.
+    pub fn occupied(&self) -> B {
+        self.snakes()
+            .iter()
+            .fold(B::default(), |acc, s| acc | s.body)
+    }
+
+    /// Returns the snakes of this game.
+    pub fn snakes(&self) -> &[BitSnake<B>] {
+        &self.snakes[..self.snake_count as usize]
+    }
+
+    pub fn snake_is_alive(&self, snake: u8) -> bool {
+        (snake as usize) < self.snakes().len() && self.snakes[snake as usize].alive()
+    }
+
+    /// Returns if the game has ended and which snake is the winner or if
+    /// the game was a draw.
+    pub fn outcome(&self) -> Outcome {
+        let mut living_snakes = 0;
+        let mut survivor = 0;
+        for (i, snake) in self.snakes().iter().enumerate() {
+            if snake.alive() {
+                living_snakes += 1;
+                survivor = i as u8;
+            }
+        }
+        match (self.mode, living_snakes) {
+            (GameMode::Solo, 0) => Outcome::Match,
+            (GameMode::Solo, _) => Outcome::None,
+            (_, 0) => Outcome::Match,
+            (_, 1) => Outcome::Winner(survivor),
+            _ => Outcome::None,
+        }
+    }
+
+    /// Returns if a move will not immediately kill the snake.
+    /// Head to head collisions are not considered.
+    pub fn move_is_valid(&self, snake: u8, dir: Direction) -> bool {
+        if !self.snake_is_alive(snake) {
+            return false;
+        }
+        let Some(p) = self.apply(self.snakes[snake as usize].head, dir) else {
+            return false;
+        };
+        // Free or occupied by tail (free in the next turn)
+        !self.occupied().has(p)
+            || self
+                .snakes()
+                .iter()
+                .any(|s| s.alive() && s.tail == p && s.stacked == 0)
+    }
+
+    /// Returns the set of valid moves for the snake.
+    pub fn valid_moves(&self, snake: u8) -> impl Iterator<Item = Direction> + '_ {
+        Direction::all()
+            .into_iter()
+            .filter(move |&d| self.move_is_valid(snake, d))
+    }
+
+    /// Executes the provided moves for each living agent like [Game::step].
+    pub fn step(&mut self, moves: &[Direction]) {
+        self.advance(moves);
+    }
+
+    /// Executes the moves and returns the bodies and directions of the
+    /// eliminated snakes, as they were after their move.
+    fn advance(&mut self, moves: &[Direction]) -> [B; 3] {
+        assert!(moves.len() >= self.snakes().len());
+
+        let grow = self.mode.ruleset().snakes_grow();
+        let count = self.snakes().len();
+
+        // Move, reduce health, damage hazards and feed
+        let mut heads = [None; MAX_SNAKES];
+        let mut eaten = B::default();
+        for i in 0..count {
+            let mut snake = self.snakes[i];
+            if snake.alive() {
+                heads[i] = self.move_snake(&mut snake, moves[i], grow, &mut eaten);
+                self.snakes[i] = snake;
+            }
+        }
+        self.food &= !eaten;
+
+        // Starved or out of bounds
+        let mut eliminated = [false; MAX_SNAKES];
+        let mut removed = [B::default(); 3];
+        for i in 0..count {
+            let snake = &mut self.snakes[i];
+            if snake.alive() && heads[i].is_none() {
+                snake.health = 0;
+            }
+            if !snake.alive() && !snake.body.is_empty() {
+                eliminated[i] = true;
+                removed[0] |= snake.body;
+                removed[1] |= snake.dirs[0] & snake.body;
+                removed[2] |= snake.dirs[1] & snake.body;
+                snake.body = B::default();
+            }
+        }
+
+        // Simultaneous collisions (the new heads are not yet part of the bodies)
+        let occupied = self.occupied();
+        let mut collided = [false; MAX_SNAKES];
+        for (i, snake) in self.snakes().iter().enumerate() {
+            if !snake.alive() {
+                continue;
+            }
+            collided[i] = occupied.has(snake.head)
+                || self.snakes().iter().enumerate().any(|(j, s)| {
+                    j != i && s.alive() && s.head == snake.head && snake.len <= s.len
+                });
+        }
+
+        // Remove eliminated snakes and occupy the new heads
+        for i in 0..count {
+            let snake = &mut self.snakes[i];
+            if collided[i] {
+                removed[0] |= snake.body;
+                removed[1] |= snake.dirs[0] & snake.body;
+                removed[2] |= snake.dirs[1] & snake.body;
+            }
+            if collided[i] || eliminated[i] {
+                *snake = BitSnake::default();
+            } else if snake.alive() {
+                snake.body |= B::bit(snake.head);
+            }
+        }
+        // Food is also covered by snakes that do not eat (constrictor)
+        self.food &= !self.occupied();
+
+        self.turn += 1;
+        removed
+    }
+
+    /// Moves the snake, reduces its health and feeds it.
+    /// Returns the new head or `None` if it left the board.
+    fn move_snake(
+        &self,
+        snake: &mut BitSnake<B>,
+        dir: Direction,
+        grow: bool,
+        eaten: &mut B,
+    ) -> Option<u16> {
+        let next = self.apply(snake.head, dir);
+
+        snake.set_dir(snake.head, dir);
+        if snake.stacked > 0 {
+            snake.stacked -= 1;
+        } else if snake.tail != snake.head {
+            let tail = snake.tail;
+            snake.tail = self.apply(tail, snake.dir(tail)).unwrap();
+            snake.body &= !B::bit(tail);
+            snake.dirs = snake.dirs.map(|d| d & !B::bit(tail));
+        }
+
+        snake.health = snake.health.saturating_sub(1);
+        let head = next?;
+        snake.head = head;
+        if snake.len == 1 {
+            snake.tail = head;
+        }
+
+        if self.hazards.has(head) && !self.food.has(head) {
+            snake.health = snake.health.saturating_sub(self.settings.hazard_damage);
+            if snake.health == 0 {
+                return next;
+            }
+        }
+
+        if grow {
+            snake.health = 100;
+            if snake.stacked == 0 {
+                snake.stacked += 1;
+                snake.len += 1;
+            }
+        } else if self.food.has(head) {
+            snake.health = 100;
+            snake.stacked += 1;
+            snake.len += 1;
+            *eaten |= B::bit(head);
+        }
+        next
+    }
+
+    /// Computes the Zobrist hash, which is the same as for the [Game].
+    pub fn zobrist(&self) -> u64 {
+        let mut hash = zobrist::turn(self.turn);
+        for (id, snake) in self.snakes().iter().enumerate() {
+            if snake.alive() {
+                let id = id as u8;
+                hash ^= zobrist::head(id, self.pos(snake.head)) ^ zobrist::health(id, snake.health);
+                for i in self.body(snake) {
+                    hash ^= zobrist::body(id, self.pos(i));
+                }
+                if snake.stacked % 2 == 1 {
+                    hash ^= zobrist::body(id, self.pos(snake.tail));
+                }
+            }
+        }
+        for i in self.food.iter() {
+            hash ^= zobrist::food(self.pos(i));
+        }
+        for i in self.hazards.iter() {
+            hash ^= zobrist::hazard(self.pos(i));
+        }
+        hash
+    }
+
+    /// Executes the moves like [BitboardGame::step] and returns the changes,
+    /// which can be reverted with [BitboardGame::unmake_move].
+    #[must_use]
+    pub fn make_move(&mut self, moves: &[Direction]) -> BitDelta<B> {
+        let mut snakes = [SnakeDelta::default(); MAX_SNAKES];
+        for (delta, snake) in snakes.iter_mut().zip(self.snakes()) {
+            if snake.alive() {
+                *delta = SnakeDelta {
+                    head: snake.head,
+                    tail: snake.tail,
+                    stacked: snake.stacked,
+                    len: snake.len,
+                    health: snake.health,
+                    dirs: snake.dir(snake.tail) as u8 | (snake.dir(snake.head) as u8) << 2,
+                };
+            }
+        }
+        let food = self.food;
+        let eliminated = self.advance(moves);
+        BitDelta {
+            snakes,
+            food: food & !self.food,
+            eliminated,
+        }
+    }
+
+    /// Reverts the changes of the last [BitboardGame::make_move].
+    pub fn unmake_move(&mut self, delta: BitDelta<B>) {
+        for (id, prev) in delta.snakes.into_iter().enumerate() {
+            if prev.health == 0 {
+                continue;
+            }
+            let mut snake = self.snakes[id];
+
+            if snake.alive() {
+                snake.body &= !B::bit(snake.head);
+            } else {
+                // Restore the moved body from the eliminated cells
+                let mut i = if prev.stacked == 0 && prev.tail != prev.head {
+                    self.apply(prev.tail, Direction::from(prev.dirs & 3))
+                        .unwrap()
+                } else {
+                    prev.tail
+                };
+                loop {
+                    snake.body |= B::bit(i);
+                    snake.dirs[0] |= delta.eliminated[1] & B::bit(i);
+                    snake.dirs[1] |= delta.eliminated[2] & B::bit(i);
+                    if i == prev.head {
+                        break;
+                    }
+                    let d = Direction::from(
+                        delta.eliminated[1].has(i) as u8 | (delta.eliminated[2].has(i) as u8) << 1,
+                    );
+                    i = self.apply(i, d).unwrap();
+                }
+            }
+
+            snake.body |= B::bit(prev.tail);
+            snake.set_dir(prev.tail, Direction::from(prev.dirs & 3));
+            snake.set_dir(prev.head, Direction::from(prev.dirs >> 2));
+            snake.head = prev.head;
+            snake.tail = prev.tail;
+            snake.stacked = prev.stacked;
+            snake.len = prev.len;
+            snake.health = prev.health;
+            self.snakes[id] = snake;
+        }
+        self.food |= delta.food;
+        self.turn -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::logging;
+
+    #[test]
+    fn bitboard_conversion() {
+        use super::*;
+        use rand::SeedableRng;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . o . . .
+            o . . . . . . . . o .
+            . . o 3 . . . . . . .
+            . . . ^ . . 0 o . . .
+            . . . ^ o . ^ . . . .
+            . . . . . o ^ . . o .
+            . o . . o . . . . . .
+            . . . . . . . 2 . o .
+            . . v 1 . . . ^ . . .
+            o . > ^ . . . ^ . . .
+            . . . . . o . ^ o . ."#,
+        )
+        .unwrap();
+        game.grid
+            .add_hazards(&[Vec2D::new(0, 0), Vec2D::new(10, 10)]);
+        game.snakes[1].health = 42;
+
+        let bitboard = BitboardGame::<u128>::from_game(&game).unwrap();
+        info!("{bitboard:?}");
+        let converted = bitboard.to_game();
+        info!("{converted:?}");
+        assert_eq!(converted.snakes, game.snakes);
+        assert!(converted.grid.cells == game.grid.cells);
+
+        let wide = WideBitboardGame::from_game(&game).unwrap();
+        let converted = wide.to_game();
+        assert_eq!(converted.snakes, game.snakes);
+        assert!(converted.grid.cells == game.grid.cells);
+
+        let large =
+            crate::simulate::init_game(19, 19, 4, &mut rand::rngs::SmallRng::seed_from_u64(0));
+        assert!(BitboardGame::<u128>::from_game(&large).is_none());
+        let wide = WideBitboardGame::from_game(&large).unwrap();
+        assert_eq!(wide.to_game().snakes, large.snakes);
+    }
+
+    #[test]
+    fn bitboard_step() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . o . . .
+            o . . . . . . . . o .
+            . . o 3 . . . . . . .
+            . . . . . . 0 o . . .
+            . . o . . . . . . . .
+            . . . . . o . . . o .
+            . o . . o . . . . . .
+            . . . . . . . 2 . o .
+            . . . 1 . . . o . . .
+            o . . o . . . . . . .
+            . . . . . o . . o . ."#,
+        )
+        .unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Constrictor, GameMode::Wrapped] {
+            for _ in 0..100 {
+                let mut game = game.clone();
+                game.set_mode(mode);
+                game.grid
+                    .add_hazards(&[Vec2D::new(3, 3), Vec2D::new(3, 4), Vec2D::new(4, 4)]);
+                let mut bitboard = BitboardGame::<u128>::from_game(&game).unwrap();
+                let mut wide = WideBitboardGame::from_game(&game).unwrap();
+
+                while game.outcome() == Outcome::None {
+                    // Mostly valid moves, but also some suicidal ones
+                    let moves: [Direction; 4] = std::array::from_fn(|i| {
+                        let valid = game.valid_moves(i as u8).collect::<Vec<_>>();
+                        assert!(valid.iter().copied().eq(bitboard.valid_moves(i as u8)));
+                        if valid.is_empty() || rng.gen_bool(0.1) {
+                            Direction::from(rng.gen_range(0..4))
+                        } else {
+                            valid[rng.gen_range(0..valid.len())]
+                        }
+                    });
+                    game.step(&moves);
+                    bitboard.step(&moves);
+                    wide.step(&moves);
+
+                    let expected = BitboardGame::from_game(&game).unwrap();
+                    assert_eq!(bitboard, expected, "{game:?}");
+                    assert_eq!(wide, WideBitboardGame::from_game(&game).unwrap());
+                    assert_eq!(bitboard.outcome(), game.outcome());
+                    assert_eq!(bitboard.zobrist(), game.compute_zobrist());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bitboard_make_unmake() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        fn check<B: BitSet>(bitboard: &mut BitboardGame<B>, moves: &[Direction]) {
+            let prev = *bitboard;
+            let mut expected = prev;
+            expected.step(moves);
+
+            let delta = bitboard.make_move(moves);
+            assert_eq!(*bitboard, expected);
+            bitboard.unmake_move(delta);
+            assert_eq!(*bitboard, prev);
+            bitboard.step(moves);
+        }
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Constrictor, GameMode::Wrapped] {
+            for snakes in 1..=MAX_SNAKES {
+                for _ in 0..20 {
+                    let mut game = crate::simulate::init_game(11, 11, snakes, &mut rng);
+                    game.set_mode(mode);
+                    game.grid
+                        .add_hazards(&[Vec2D::new(3, 3), Vec2D::new(3, 4), Vec2D::new(4, 4)]);
+                    game.settings.hazard_damage = 50;
+                    let mut bitboard = BitboardGame::<u128>::from_game(&game).unwrap();
+                    let mut wide = WideBitboardGame::from_game(&game).unwrap();
+
+                    while bitboard.snakes().iter().any(|s| s.alive()) {
+                        // Mostly valid moves, but also some suicidal ones
+                        let moves: [Direction; MAX_SNAKES] = std::array::from_fn(|i| {
+                            let valid = bitboard.valid_moves(i as u8).collect::<Vec<_>>();
+                            if valid.is_empty() || rng.gen_bool(0.1) {
+                                Direction::from(rng.gen_range(0..4))
+                            } else {
+                                valid[rng.gen_range(0..valid.len())]
+                            }
+                        });
+                        check(&mut bitboard, &moves);
+                        check(&mut wide, &moves);
+
+                        game.step(&moves);
+                        assert_eq!(bitboard, BitboardGame::from_game(&game).unwrap());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bitboard_neighbors() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        fn check<B: BitSet>(game: &Game, rng: &mut SmallRng) {
+            let bitboard = BitboardGame::<B>::from_game(game).unwrap();
+            let mut cells = B::default();
+            for i in 0..(game.grid.width * game.grid.height) as u16 {
+                cells = cells.with(i, rng.gen_bool(0.1));
+            }
+
+            let mut expected = B::default();
+            for i in cells.iter() {
+                for d in Direction::all() {
+                    let p = game.grid.apply(bitboard.pos(i), d);
+                    if game.grid.has(p) {
+                        expected |= B::bit(bitboard.index(p));
+                    }
+                }
+            }
+            assert_eq!(bitboard.neighbors(cells), expected);
+        }
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Wrapped] {
+            for (width, height) in [(11, 11), (7, 11), (19, 19)] {
+                let mut game = Game::new(0, width, height, Vec::new(), &[], &[]);
+                game.set_mode(mode);
+                for _ in 0..10 {
+                    if width * height <= 128 {
+                        check::<u128>(&game, &mut rng);
+                    }
+                    check::<U384>(&game, &mut rng);
+                }
+            }
+        }
+    }
+}
@@ -11,8 +11,6 @@ use std::ops::{Add, Neg, Sub};
 
 pub const API_VERSION: &str = "1";
 
-pub const HAZARD_DAMAGE: u8 = 15;
-
 /// Position in the a 2D grid.
 #[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Vec2D {
@@ -45,6 +43,14 @@ impl Vec2D {
     pub fn within(self, width: usize, height: usize) -> bool {
         self.x >= 0 && self.x < width as _ && self.y >= 0 && self.y < height as _
     }
+
+    /// Wraps the vector around into a rectangle from (0,0) to (width-1,height-1)
+    pub fn wrap(self, width: usize, height: usize) -> Vec2D {
+        Vec2D::new(
+            self.x.rem_euclid(width as _),
+            self.y.rem_euclid(height as _),
+        )
+    }
 }
 
 impl From<(i16, i16)> for Vec2D {
@@ -106,7 +112,7 @@ impl Neg for Vec2D {
 /// The Direction is returned as part of a `MoveResponse`.
 ///
 /// The Y-Axis is positive in the up direction, and X-Axis is positive to the right.
-#[derive(Serialize, Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Direction {
     /// Positive Y
@@ -120,9 +126,6 @@ pub enum Direction {
     Left,
 }
 
-#[cfg(feature = "mocats")]
-impl mocats::GameAction for Direction {}
-
 impl fmt::Display for Direction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         <Self as Debug>::fmt(self, f)
@@ -202,7 +205,10 @@ pub struct Ruleset {
 
 /// A collection of specific settings being used by the current game
 /// that control how the rules are applied.
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+///
+/// If the request has no settings, the defaults of the official game server
+/// are used.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Settings {
     /// Percentage chance of spawning a new food every round.
@@ -216,13 +222,30 @@ pub struct Settings {
     pub royale: Royale,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            food_spawn_chance: 15,
+            minimum_food: 1,
+            hazard_damage: 14,
+            royale: Royale::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Royale {
     /// In Royale mode, the number of turns between generating new hazards (shrinking the safe board space).
     #[serde(alias = "shrinkEveryNTurns")]
     pub shrink: usize,
 }
 
+impl Default for Royale {
+    fn default() -> Self {
+        Self { shrink: 25 }
+    }
+}
+
 /// Object describing a snake.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Battlesnake {
@@ -1,13 +1,18 @@
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, VecDeque};
+use std::collections::VecDeque;
 use std::fmt::{self, Debug};
 
 use owo_colors::{AnsiColors, OwoColorize};
 use serde::{Deserialize, Serialize};
 
-use crate::env::{Battlesnake, Direction, GameRequest, Vec2D, HAZARD_DAMAGE};
+use crate::env::{Battlesnake, Direction, GameRequest, Settings, Vec2D};
 use crate::grid::{Cell, CellT, Grid};
-use crate::util::OrdPair;
+use crate::relevance::OpponentPruning;
+use crate::rules::{GameMode, Ruleset};
+use crate::util::FixedVec;
+use crate::zobrist;
+
+/// The maximum number of snakes of a game.
+pub const MAX_SNAKES: usize = 8;
 
 /// The outcome of a simulated game.
 /// If the game did not end the outcome is `None`.
@@ -18,16 +23,66 @@ pub enum Outcome {
     Winner(u8),
 }
 
+/// The reason why a snake has been eliminated.
+///
+/// See: https://github.com/BattlesnakeOfficial/rules/blob/main/constants.go
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum EliminationCause {
+    /// Moved outside of the board.
+    OutOfBounds,
+    /// Starved to death.
+    OutOfHealth,
+    /// Lost its remaining health in a hazard.
+    Hazard,
+    /// Moved into its own body.
+    SelfCollision,
+    /// Moved into the body of the given snake.
+    Collision(u8),
+    /// Lost a head to head against the given snake.
+    HeadToHead(u8),
+    /// Moved into an obstacle, like the body of a snake that is not
+    /// simulated (see [crate::relevance]).
+    Obstacle,
+}
+
+impl EliminationCause {
+    /// Returns if the snake has collided with a snake (including itself).
+    pub fn is_collision(self) -> bool {
+        matches!(
+            self,
+            Self::SelfCollision | Self::Collision(_) | Self::HeadToHead(_) | Self::Obstacle
+        )
+    }
+}
+
+/// Something that happened during a [Game::step_with_events].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum Event {
+    /// The snake lost health in a hazard, in addition to the regular damage.
+    HazardDamage { snake: u8, damage: u8 },
+    /// The snake ate the food.
+    Ate { snake: u8, food: Vec2D },
+    /// The snake has been eliminated.
+    Died { snake: u8, cause: EliminationCause },
+}
+
 /// Reduced representation of a snake.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Snake {
     /// tail to head
     pub body: VecDeque<Vec2D>,
     pub health: u8,
+    /// Why the snake has been eliminated, if it died during a [Game::step].
+    #[serde(default)]
+    pub elimination: Option<EliminationCause>,
 }
 impl Snake {
     pub fn new(body: VecDeque<Vec2D>, health: u8) -> Self {
-        Self { body, health }
+        Self {
+            body,
+            health,
+            elimination: None,
+        }
     }
 
     #[must_use]
@@ -39,9 +94,53 @@ impl Snake {
         self.health > 0
     }
 
+    /// Returns if the snake has been removed from the board.
+    ///
+    /// Other than [Snake::alive] this is also correct while the moves are
+    /// executed, where the health might temporarily drop to zero.
+    pub fn eliminated(&self) -> bool {
+        self.elimination.is_some() || self.body.is_empty()
+    }
+
     pub fn head(&self) -> Vec2D {
         *self.body.back().unwrap()
     }
+
+    /// Returns if `p` is part of the body, excluding the head.
+    pub(crate) fn body_collides(&self, p: Vec2D) -> bool {
+        self.body.iter().rev().skip(1).any(|&b| b == p)
+    }
+}
+
+/// The settings that control how the rules are applied.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct GameSettings {
+    /// Additional health a snake loses when ending its turn in a hazard.
+    pub hazard_damage: u8,
+    /// Percentage chance of spawning a new food every turn.
+    pub food_spawn_chance: u8,
+    /// Minimum food to keep on the board every turn.
+    pub minimum_food: usize,
+    /// Number of turns between shrinking the safe area in royale mode.
+    pub shrink_turns: usize,
+}
+
+impl Default for GameSettings {
+    /// The defaults of the official game server.
+    fn default() -> Self {
+        Self::from(&Settings::default())
+    }
+}
+
+impl From<&Settings> for GameSettings {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            hazard_damage: settings.hazard_damage.min(u8::MAX as _) as _,
+            food_spawn_chance: settings.food_spawn_chance.min(100) as _,
+            minimum_food: settings.minimum_food,
+            shrink_turns: settings.royale.shrink,
+        }
+    }
 }
 
 /// Game represents holds the complete game state.
@@ -53,6 +152,19 @@ pub struct Game {
     /// All snakes. Dead ones have health = 0 and no body.
     /// The ids have to be the same as the indices!
     pub snakes: Vec<Snake>,
+    /// The rules this game is played with.
+    #[serde(default)]
+    pub mode: GameMode,
+    /// The settings of the rules.
+    #[serde(default)]
+    pub settings: GameSettings,
+    /// Cells of the snakes that are not simulated, see [OpponentPruning].
+    /// They stay [CellT::Owned], even if a simulated snake leaves them.
+    #[serde(default)]
+    pub obstacles: Vec<Vec2D>,
+    /// Zobrist hash of the game state.
+    #[serde(skip)]
+    zobrist: u64,
 }
 
 impl Game {
@@ -74,92 +186,129 @@ impl Game {
             grid.add_snake(snake.body.iter().copied());
         }
 
-        Self { turn, snakes, grid }
+        let mut game = Self {
+            turn,
+            snakes,
+            grid,
+            mode: GameMode::Standard,
+            settings: GameSettings::default(),
+            obstacles: Vec::new(),
+            zobrist: 0,
+        };
+        game.rehash();
+        game
     }
 
-    /// Loads the game state from the provided request.
+    /// Loads the game state from the provided request, with the default
+    /// [OpponentPruning].
     #[must_use]
     pub fn from_request(request: &GameRequest) -> Self {
-        let mut snakes = Vec::with_capacity(4);
-        snakes.push(Snake::from(&request.you));
+        Self::from_request_with(request, OpponentPruning::default())
+    }
 
-        // Only look at the nearest four snakes
-        if request.board.snakes.len() > 4 {
-            let mut queue = BinaryHeap::new();
-            for snake in request
-                .board
-                .snakes
-                .iter()
-                .filter(|s| s.id != request.you.id)
-                .map(Snake::from)
-            {
-                let body_dist = snake
-                    .body
-                    .iter()
-                    .map(|&p| (p - snakes[0].head()).manhattan())
-                    .min()
-                    .unwrap_or_default();
-                queue.push(OrdPair(Reverse(body_dist), snake));
-            }
+    /// Loads the game state from the provided request, keeping only the
+    /// opponents that are selected by the `pruning` policy.
+    ///
+    /// Our snake always has the id 0, the other snakes keep the order of the
+    /// request. The bodies of the pruned snakes stay on the grid as obstacles.
+    #[must_use]
+    pub fn from_request_with(request: &GameRequest, pruning: OpponentPruning) -> Self {
+        Self::from_request_ids(request, pruning).0
+    }
 
-            for _ in 1..3 {
-                if let Some(OrdPair(_, snake)) = queue.pop() {
-                    snakes.push(snake);
-                }
-            }
-        } else {
-            snakes.extend(
-                request
-                    .board
-                    .snakes
-                    .iter()
-                    .filter(|s| s.id != request.you.id)
-                    .map(Snake::from),
-            );
-        }
+    /// Like [Game::from_request_with], but also returns the request ids of
+    /// the remaining snakes, in the order of [Game::snakes].
+    #[must_use]
+    pub fn from_request_ids(
+        request: &GameRequest,
+        pruning: OpponentPruning,
+    ) -> (Self, Vec<String>) {
+        let opponents = request
+            .board
+            .snakes
+            .iter()
+            .filter(|s| s.id != request.you.id);
+        let mut snakes = Vec::with_capacity(request.board.snakes.len());
+        snakes.push(Snake::from(&request.you));
+        snakes.extend(opponents.clone().map(Snake::from));
+        let ids: Vec<&String> = std::iter::once(&request.you.id)
+            .chain(opponents.map(|s| &s.id))
+            .collect();
 
-        if request.game.ruleset.name == "constrictor" {
-            // Constrictor games just have food everywhere
-            let food = (0..request.board.height)
-                .flat_map(|y| (0..request.board.width).map(move |x| Vec2D::new(x as _, y as _)))
-                .collect::<Vec<_>>();
+        let mut game = Self::new(
+            request.turn,
+            request.board.width,
+            request.board.height,
+            snakes,
+            &request.board.food,
+            &request.board.hazards,
+        );
+        game.set_mode(GameMode::from_name(&request.game.ruleset.name));
+        game.settings = GameSettings::from(&request.game.ruleset.settings);
+        let kept = pruning.apply(&mut game);
+        (game, kept.into_iter().map(|i| ids[i].clone()).collect())
+    }
 
-            Self::new(
-                request.turn,
-                request.board.width,
-                request.board.height,
-                snakes,
-                &food,
-                &request.board.hazards,
-            )
-        } else {
-            Self::new(
-                request.turn,
-                request.board.width,
-                request.board.height,
-                snakes,
-                &request.board.food,
-                &request.board.hazards,
-            )
-        }
+    /// Changes the game mode and the topology of the grid accordingly.
+    pub fn set_mode(&mut self, mode: GameMode) {
+        self.mode = mode;
+        self.grid.wrapped = mode.ruleset().wrapped();
+    }
+
+    /// Returns the rules of the current game mode.
+    pub fn rules(&self) -> &'static dyn Ruleset {
+        self.mode.ruleset()
     }
 
     /// Returns if the game has ended and which snake is the winner or if the
     /// game was a match.
     pub fn outcome(&self) -> Outcome {
-        let mut living_snakes = 0;
-        let mut survivor = 0;
-        for (i, snake) in self.snakes.iter().enumerate() {
+        self.rules().outcome(self)
+    }
+
+    /// Returns the Zobrist hash of the game state.
+    ///
+    /// It is updated incrementally by [Game::make_move], but has to be
+    /// recomputed with [Game::rehash] if the game is modified otherwise.
+    pub fn zobrist(&self) -> u64 {
+        self.zobrist
+    }
+
+    /// Recomputes the Zobrist hash of the game state.
+    pub fn rehash(&mut self) {
+        self.zobrist = self.compute_zobrist();
+    }
+
+    /// Computes the Zobrist hash from scratch.
+    ///
+    /// See the [zobrist] module for the hashed features.
+    pub fn compute_zobrist(&self) -> u64 {
+        let mut hash = zobrist::turn(self.turn);
+        for (id, snake) in self.snakes.iter().enumerate() {
             if snake.alive() {
-                living_snakes += 1;
-                survivor = i as u8;
+                let id = id as u8;
+                hash ^= zobrist::head(id, snake.head()) ^ zobrist::health(id, snake.health);
+                for &p in &snake.body {
+                    hash ^= zobrist::body(id, p);
+                }
+            }
+        }
+        for y in 0..self.grid.height {
+            for x in 0..self.grid.width {
+                let p = Vec2D::new(x as _, y as _);
+                let cell = self.grid[p];
+                if cell.t == CellT::Food {
+                    hash ^= zobrist::food(p);
+                }
+                if cell.hazard {
+                    hash ^= zobrist::hazard(p);
+                }
             }
         }
-        match living_snakes {
-            0 => Outcome::Match,
-            1 => Outcome::Winner(survivor),
-            _ => Outcome::None,
+        for &p in &self.obstacles {
+            hash ^= zobrist::obstacle(p);
         }
+        hash
     }
 
     /// Returns if a snake is alive.
@@ -170,7 +319,7 @@ impl Game {
     /// Returns all valid moves that do not immediately kill the snake.
     /// Head to head collisions are not considered.
     #[inline]
-    pub fn valid_moves(&self, snake: u8) -> ValidMoves {
+    pub fn valid_moves(&self, snake: u8) -> ValidMoves<'_> {
         if self.snake_is_alive(snake) {
             ValidMoves::new(self, &self.snakes[snake as usize])
         } else {
@@ -186,7 +335,7 @@ impl Game {
 
     #[inline]
     fn snake_move_is_valid(&self, snake: &Snake, dir: Direction) -> bool {
-        let p = snake.head().apply(dir);
+        let p = self.grid.apply(snake.head(), dir);
         // Free or occupied by tail (free in the next turn)
         self.grid.has(p)
             && (self.grid[p].t != CellT::Owned
@@ -197,90 +346,229 @@ impl Game {
                     .any(|s| p == s.body[0] && p != s.body[1]))
     }
 
-    /// Executed the provided moves for each living agent.
-    /// This method also checks for eating and collision with walls or other snakes.
+    /// Executes the provided moves for each living agent.
+    ///
+    /// The phases of the [Ruleset] are applied in the following order:
+    /// 1. Move the snakes
+    /// 2. Reduce their health
+    /// 3. Apply hazard damage
+    /// 4. Feed snakes that reached food
+    /// 5. Eliminate snakes that starved, left the board or collided
+    ///
+    /// The reason of the eliminations is stored in [Snake::elimination].
     pub fn step(&mut self, moves: &[Direction]) {
-        assert!(moves.len() >= self.snakes.len());
+        let _ = self.make_move(moves);
+    }
 
-        // Pop tail
-        for snake in &mut self.snakes {
-            if snake.alive() {
-                let tail = snake.body.pop_front().unwrap();
-                let new_tail = snake.body[0];
-                if tail != new_tail {
-                    self.grid[tail].t = CellT::Free;
+    /// Executes the moves like [Game::step] and returns what happened,
+    /// ordered by the phases in which it happened.
+    pub fn step_with_events(&mut self, moves: &[Direction]) -> Vec<Event> {
+        let delta = self.make_move(moves);
+
+        let mut events = Vec::new();
+        let mut food = Vec::new();
+        let mut deaths = Vec::new();
+        for (id, (snake, delta)) in self.snakes.iter().zip(delta.snakes.iter()).enumerate() {
+            if !delta.moved {
+                continue;
+            }
+            let id = id as u8;
+            let head = if snake.elimination.is_some() {
+                delta.body.back().copied()
+            } else {
+                snake.body.back().copied()
+            };
+
+            if let Some(p) = delta.food {
+                food.push(Event::Ate { snake: id, food: p });
+            } else if head.is_some_and(|p| self.grid.is_hazardous(p)) {
+                // The regular damage is applied first
+                let damage = self
+                    .settings
+                    .hazard_damage
+                    .min(delta.health.saturating_sub(1));
+                if damage > 0 {
+                    events.push(Event::HazardDamage { snake: id, damage });
                 }
             }
+            if let Some(cause) = snake.elimination {
+                deaths.push(Event::Died { snake: id, cause });
+            }
         }
+        events.extend(food);
+        events.extend(deaths);
+        events
+    }
 
-        // Move head & eat
-        for (id, snake) in self.snakes.iter_mut().enumerate() {
-            if snake.alive() {
-                let dir = moves[id];
-                let head = snake.head().apply(dir);
+    /// Executes the moves like [Game::step] and returns the changes, which
+    /// can be reverted with [Game::unmake_move].
+    ///
+    /// This allows the tree searches to traverse the game tree in place
+    /// without cloning the game for every node.
+    #[must_use]
+    pub fn make_move(&mut self, moves: &[Direction]) -> Delta {
+        assert!(moves.len() >= self.snakes.len());
 
-                if !self.grid.has(head) {
-                    snake.health = 0;
-                    continue;
-                }
+        let mut delta = Delta {
+            zobrist: self.zobrist,
+            ..Default::default()
+        };
+        // Remove the features that change from the hash
+        let mut hash = self.zobrist ^ zobrist::turn(self.turn) ^ zobrist::turn(self.turn + 1);
+        for (id, snake) in self.snakes.iter().enumerate() {
+            if snake.alive() && !snake.eliminated() {
+                hash ^=
+                    zobrist::head(id as _, snake.head()) ^ zobrist::health(id as _, snake.health);
+            }
+        }
 
-                snake.body.push_back(head);
+        for (snake, &dir) in self.snakes.iter().zip(moves) {
+            let moved = !snake.eliminated();
+            let food = if moved {
+                let next = self.grid.apply(snake.head(), dir);
+                (self.grid.has(next) && self.grid[next].t == CellT::Food).then_some(next)
+            } else {
+                None
+            };
+            let pushed = delta.snakes.push(SnakeDelta {
+                moved,
+                tail: snake.body.front().copied().unwrap_or_default(),
+                len: snake.body.len() as _,
+                health: snake.health,
+                elimination: snake.elimination,
+                food,
+                body: VecDeque::new(),
+            });
+            assert!(pushed, "Too many snakes");
+        }
 
-                let g_cell = self.grid[head];
-                if g_cell.t == CellT::Owned {
-                    snake.health = 0;
-                    continue;
-                }
+        let rules = self.rules();
+        rules.move_snakes(self, moves);
+        rules.reduce_health(self);
+        rules.damage_hazards(self);
+        rules.feed_snakes(self);
+        rules.eliminate_snakes(self);
 
-                snake.health = if g_cell.t == CellT::Food {
-                    snake.body.push_front(*snake.body.front().unwrap());
-                    100
-                } else {
-                    snake
-                        .health
-                        .saturating_sub(if g_cell.hazard { HAZARD_DAMAGE } else { 1 })
-                };
+        // Remove eliminated snakes and occupy the new heads
+        let grid = &mut self.grid;
+        for (snake, delta) in self.snakes.iter_mut().zip(delta.snakes.iter_mut()) {
+            if snake.elimination.is_some() {
+                snake.health = 0;
+                delta.body = std::mem::take(&mut snake.body);
+            } else if !snake.body.is_empty() {
+                grid[snake.head()].t = CellT::Owned;
             }
         }
 
-        // Check head to head
-        // Warning: This is only accurate for head to head on two snakes but not more
-        for i in 0..self.snakes.len() - 1 {
-            if self.snakes[i].alive() {
-                for j in i + 1..self.snakes.len() {
-                    if self.snakes[j].alive() && self.snakes[i].head() == self.snakes[j].head() {
-                        use std::cmp::Ordering;
-                        match self.snakes[i].body.len().cmp(&self.snakes[j].body.len()) {
-                            Ordering::Less => self.snakes[i].health = 0,
-                            Ordering::Greater => self.snakes[j].health = 0,
-                            Ordering::Equal => {
-                                self.snakes[i].health = 0;
-                                self.snakes[j].health = 0;
-                            }
-                        }
-                    }
+        // Add the changed features to the hash
+        for (id, (snake, snake_delta)) in self.snakes.iter().zip(delta.snakes.iter()).enumerate() {
+            if !snake_delta.moved {
+                continue;
+            }
+            let id = id as u8;
+            let body = if snake.elimination.is_some() {
+                &snake_delta.body
+            } else {
+                hash ^= zobrist::head(id, snake.head()) ^ zobrist::health(id, snake.health);
+                &snake.body
+            };
+            // The tail moved forward or stayed in place if the snake has grown
+            hash ^= zobrist::body(id, snake_delta.tail) ^ zobrist::body(id, *body.back().unwrap());
+            if body.len() > snake_delta.len as usize {
+                hash ^= zobrist::body(id, body[0]);
+            }
+            if snake.elimination.is_some() {
+                for &p in body {
+                    hash ^= zobrist::body(id, p);
                 }
             }
         }
+        // Multiple snakes might have eaten the same food
+        for (i, food) in delta
+            .snakes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((i, s.food?)))
+        {
+            if self.grid[food].t != CellT::Food
+                && !delta.snakes[..i].iter().any(|s| s.food == Some(food))
+            {
+                hash ^= zobrist::food(food);
+            }
+        }
+        self.zobrist = hash;
 
-        // Clear died snakes
-        let grid = &mut self.grid;
-        for snake in &mut self.snakes {
-            if snake.alive() {
-                let head_cell = &mut grid[snake.head()];
-                head_cell.t = CellT::Owned;
-            } else {
+        self.turn += 1;
+        delta
+    }
+
+    /// Reverts the changes of the last [Game::make_move].
+    ///
+    /// The deltas have to be reverted in the reverse order they were made.
+    pub fn unmake_move(&mut self, mut delta: Delta) {
+        self.turn -= 1;
+        self.zobrist = delta.zobrist;
+
+        // Free the new heads and restore the eaten food
+        for (snake, delta) in self.snakes.iter_mut().zip(delta.snakes.iter_mut()) {
+            if !delta.moved {
+                continue;
+            }
+            if snake.elimination.is_some() {
+                snake.body = std::mem::take(&mut delta.body);
+            }
+            let head = snake.body.pop_back().unwrap();
+            if let Some(food) = delta.food {
+                self.grid[food].t = CellT::Food;
+            } else if snake.elimination.is_none() {
+                self.grid[head].t = CellT::Free;
+            }
+        }
+
+        // Restore the bodies, which might have been freed in the meantime
+        for (snake, delta) in self.snakes.iter_mut().zip(delta.snakes.iter()) {
+            if !delta.moved {
+                continue;
+            }
+            while snake.body.len() >= delta.len as usize {
+                snake.body.pop_front();
+            }
+            snake.body.push_front(delta.tail);
+            if snake.elimination.is_some() {
                 for &p in &snake.body {
-                    grid[p].t = CellT::Free;
+                    self.grid[p].t = CellT::Owned;
                 }
-                snake.body.clear();
+            } else {
+                self.grid[delta.tail].t = CellT::Owned;
             }
+            snake.health = delta.health;
+            snake.elimination = delta.elimination;
         }
-
-        self.turn += 1;
     }
 }
 
+/// The state of a snake before a move.
+#[derive(Debug)]
+struct SnakeDelta {
+    /// Whether the snake was alive and moved.
+    moved: bool,
+    tail: Vec2D,
+    len: u16,
+    health: u8,
+    elimination: Option<EliminationCause>,
+    /// The food the snake has moved onto.
+    food: Option<Vec2D>,
+    /// The body of a snake that has been eliminated by the move.
+    body: VecDeque<Vec2D>,
+}
+
+/// The changes of [Game::make_move], which are needed to revert it.
+#[derive(Debug, Default)]
+pub struct Delta {
+    snakes: FixedVec<SnakeDelta, MAX_SNAKES>,
+    zobrist: u64,
+}
+
 impl Game {
     /// Parses textual human readable board representation used in test.
     #[must_use]
@@ -313,7 +601,7 @@ impl Game {
             .collect();
         let height = txt.lines().count();
 
-        if raw_cells.len() % height != 0 {
+        if !raw_cells.len().is_multiple_of(height) {
             return None;
         }
         let width = raw_cells.len() / height;
@@ -352,11 +640,17 @@ impl Game {
             }
         }
 
-        Some(Self {
+        let mut game = Self {
             turn: 0,
             grid,
             snakes,
-        })
+            mode: GameMode::Standard,
+            settings: GameSettings::default(),
+            obstacles: Vec::new(),
+            zobrist: 0,
+        };
+        game.rehash();
+        Some(game)
     }
 }
 
@@ -368,6 +662,7 @@ impl Debug for Game {
             Food,
             Tail(Direction, u8),
             Head(u8),
+            Obstacle,
         }
         fn id_color(id: u8) -> AnsiColors {
             match id {
@@ -390,6 +685,7 @@ impl Debug for Game {
                         Direction::Left => write!(f, "{}", "◀".color(id_color(id))),
                     },
                     FmtCell::Head(id) => write!(f, "{}", id.color(id_color(id))),
+                    FmtCell::Obstacle => write!(f, "{}", "■".bright_black()),
                 }
             }
         }
@@ -400,10 +696,11 @@ impl Debug for Game {
             for x in 0..self.grid.width {
                 let cell = &mut cells[y * self.grid.width + x];
                 let g_cell = self.grid[Vec2D::new(x as _, y as _)];
-                cell.0 = if g_cell.t == CellT::Food {
-                    FmtCell::Food
-                } else {
-                    FmtCell::Free
+                // Owned cells that are not overwritten by a snake below
+                cell.0 = match g_cell.t {
+                    CellT::Food => FmtCell::Food,
+                    CellT::Owned => FmtCell::Obstacle,
+                    CellT::Free => FmtCell::Free,
                 };
                 cell.1 = g_cell.hazard;
             }
@@ -418,7 +715,7 @@ impl Debug for Game {
 
             for next_body in snake.body.iter().skip(1).copied() {
                 cells[last_body.y as usize * self.grid.width + last_body.x as usize].0 =
-                    FmtCell::Tail(Direction::from(next_body - last_body), id as _);
+                    FmtCell::Tail(self.grid.direction(last_body, next_body), id as _);
 
                 last_body = next_body;
             }
@@ -600,6 +897,10 @@ mod test {
             game.step(&[Right, Right]);
             info!("{game:?}");
             assert!(!game.snake_is_alive(0));
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::Collision(1))
+            );
             assert!(game.grid[Vec2D::new(5, 8)].t != CellT::Owned);
             assert!(game.snake_is_alive(1));
             assert!(game.grid[Vec2D::new(8, 8)].t == CellT::Owned);
@@ -611,6 +912,426 @@ mod test {
             info!("{game:?}");
             assert!(!game.snake_is_alive(0));
             assert!(!game.snake_is_alive(1));
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::HeadToHead(1))
+            );
+            assert_eq!(
+                game.snakes[1].elimination,
+                Some(EliminationCause::HeadToHead(0))
+            );
+        }
+    }
+
+    #[test]
+    fn game_step_head_to_head() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . v . . . . .
+            . . . . . v . . . . .
+            . . . . . 2 . . . . .
+            . . . . 0 . 1 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        {
+            // The longest snake wins against all others
+            let mut game = game.clone();
+            game.step(&[Right, Left, Down]);
+            info!("{game:?}");
+            assert!(game.snake_is_alive(0));
+            assert!(!game.snake_is_alive(1));
+            assert!(!game.snake_is_alive(2));
+            assert_eq!(
+                game.snakes[1].elimination,
+                Some(EliminationCause::HeadToHead(0))
+            );
+            assert_eq!(
+                game.snakes[2].elimination,
+                Some(EliminationCause::HeadToHead(0))
+            );
+            assert!(game.grid[Vec2D::new(5, 6)].t == CellT::Owned);
+            assert_eq!(game.outcome(), Outcome::Winner(0));
+        }
+        {
+            // Two equally long snakes eliminate each other and the shorter one
+            let mut game = game.clone();
+            let tail = game.snakes[1].body[0];
+            game.snakes[1].body.push_front(tail);
+            game.step(&[Right, Left, Down]);
+            info!("{game:?}");
+            assert!(!game.snake_is_alive(0));
+            assert!(!game.snake_is_alive(1));
+            assert!(!game.snake_is_alive(2));
+            // The first of the longest snakes is the cause
+            assert_eq!(
+                game.snakes[2].elimination,
+                Some(EliminationCause::HeadToHead(0))
+            );
+            assert!(game.grid[Vec2D::new(5, 6)].t == CellT::Free);
+            assert_eq!(game.outcome(), Outcome::Match);
+        }
+        {
+            // The longest snake is the cause, not the first one
+            let mut game = game.clone();
+            let tail = game.snakes[1].body[0];
+            game.snakes[1].body.push_front(tail);
+            game.snakes[1].body.push_front(tail);
+            game.step(&[Right, Left, Down]);
+            info!("{game:?}");
+            assert!(game.snake_is_alive(1));
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::HeadToHead(1))
+            );
+            assert_eq!(
+                game.snakes[2].elimination,
+                Some(EliminationCause::HeadToHead(1))
+            );
+        }
+        {
+            // Body collisions are checked before head to heads
+            let mut game = Game::parse(
+                r#"
+                . . . . .
+                . . . . .
+                . 0 2 1 .
+                . ^ ^ ^ .
+                . ^ ^ ^ ."#,
+            )
+            .unwrap();
+            game.step(&[Right, Left, Up]);
+            info!("{game:?}");
+            assert!(game.snake_is_alive(2));
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::Collision(2))
+            );
+            assert_eq!(
+                game.snakes[1].elimination,
+                Some(EliminationCause::Collision(2))
+            );
+        }
+    }
+
+    #[test]
+    fn game_step_eliminations() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . v . . . . . . . . .
+            . v . . . . . . . . .
+            . 1 o . . . . . . . .
+            0 < < . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        {
+            // Out of bounds
+            let mut game = game.clone();
+            game.step(&[Left, Left]);
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::OutOfBounds)
+            );
+            assert!(game.snakes[0].body.is_empty());
+            assert!(game.grid[Vec2D::new(1, 2)].t == CellT::Free);
+            assert!(game.snake_is_alive(1));
+        }
+        {
+            // Body collision
+            let mut game = game.clone();
+            game.step(&[Up, Down]);
+            assert!(game.snake_is_alive(0));
+            assert_eq!(
+                game.snakes[1].elimination,
+                Some(EliminationCause::Collision(0))
+            );
+        }
+        {
+            // Starving snakes do not block others
+            let mut game = game.clone();
+            game.snakes[0].health = 1;
+            game.step(&[Up, Down]);
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::OutOfHealth)
+            );
+            assert!(game.snake_is_alive(1));
+            assert!(game.grid[Vec2D::new(1, 2)].t == CellT::Owned);
+        }
+        {
+            // Food on the last health
+            let mut game = game.clone();
+            game.snakes[1].health = 1;
+            game.step(&[Up, Right]);
+            assert!(game.snake_is_alive(1));
+            assert_eq!(game.snakes[1].health, 100);
+            assert_eq!(game.snakes[1].body.len(), 4);
+            assert!(game.grid[Vec2D::new(2, 3)].t == CellT::Owned);
+        }
+        {
+            // Self collision
+            let mut game = game.clone();
+            game.step(&[Right, Left]);
+            assert_eq!(
+                game.snakes[0].elimination,
+                Some(EliminationCause::SelfCollision)
+            );
+            assert!(game.snake_is_alive(1));
+        }
+        {
+            // Hazards do not damage if they contain food
+            let mut game = game.clone();
+            game.grid
+                .add_hazards(&[Vec2D::new(0, 3), Vec2D::new(0, 4), Vec2D::new(2, 3)]);
+            game.snakes[0].health = game.settings.hazard_damage + 2;
+            game.step(&[Up, Right]);
+            assert_eq!(game.snakes[0].health, 1);
+            assert_eq!(game.snakes[1].health, 100);
+
+            game.step(&[Up, Right]);
+            assert_eq!(game.snakes[0].elimination, Some(EliminationCause::Hazard));
+            assert_eq!(game.snakes[1].health, 99);
+        }
+        {
+            // Hazard damage from the settings
+            let mut game = game.clone();
+            game.settings.hazard_damage = 100;
+            game.grid.add_hazards(&[Vec2D::new(0, 4)]);
+            game.step(&[Up, Right]);
+            assert!(game.snake_is_alive(0));
+            game.step(&[Up, Right]);
+            assert_eq!(game.snakes[0].elimination, Some(EliminationCause::Hazard));
+        }
+    }
+
+    #[test]
+    fn game_step_events() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . .
+            . . . . . .
+            . v . . . .
+            . v . . . .
+            . 1 o . . .
+            0 < < . . .
+            . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        {
+            let mut game = game.clone();
+            game.grid.add_hazards(&[Vec2D::new(0, 2), Vec2D::new(0, 3)]);
+            let events = game.step_with_events(&[Up, Right]);
+            assert_eq!(
+                events,
+                [
+                    Event::HazardDamage {
+                        snake: 0,
+                        damage: 14
+                    },
+                    Event::Ate {
+                        snake: 1,
+                        food: Vec2D::new(2, 2)
+                    },
+                ]
+            );
+            // Only the remaining health is lost
+            game.snakes[0].health = 5;
+            let events = game.step_with_events(&[Up, Right]);
+            assert_eq!(
+                events,
+                [
+                    Event::HazardDamage {
+                        snake: 0,
+                        damage: 4
+                    },
+                    Event::Died {
+                        snake: 0,
+                        cause: EliminationCause::Hazard
+                    },
+                ]
+            );
+            // Dead snakes do not cause events
+            assert!(game.step_with_events(&[Up, Right]).is_empty());
+        }
+        {
+            let mut game = game.clone();
+            let events = game.step_with_events(&[Left, Up]);
+            assert_eq!(
+                events,
+                [
+                    Event::Died {
+                        snake: 0,
+                        cause: EliminationCause::OutOfBounds
+                    },
+                    Event::Died {
+                        snake: 1,
+                        cause: EliminationCause::SelfCollision
+                    },
+                ]
+            );
+        }
+        {
+            let mut game = game.clone();
+            let events = game.step_with_events(&[Up, Left]);
+            assert_eq!(
+                events,
+                [
+                    Event::Died {
+                        snake: 0,
+                        cause: EliminationCause::HeadToHead(1)
+                    },
+                    Event::Died {
+                        snake: 1,
+                        cause: EliminationCause::HeadToHead(0)
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn game_make_unmake_move() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . o . . .
+            o . . . . . . . . o .
+            . . o 3 . . . . . . .
+            . . . . . . 0 o . . .
+            . . o . . . . . . . .
+            . . . . . o . . . o .
+            . o . . o . . . . . .
+            . . . . . . . 2 . o .
+            . . . 1 . . . o . . .
+            o . . o . . . . . . .
+            . . . . . o . . o . ."#,
+        )
+        .unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Constrictor, GameMode::Wrapped] {
+            for _ in 0..100 {
+                let mut game = game.clone();
+                game.set_mode(mode);
+                game.grid
+                    .add_hazards(&[Vec2D::new(3, 3), Vec2D::new(3, 4), Vec2D::new(4, 4)]);
+
+                let mut history = Vec::new();
+                while game.outcome() == Outcome::None {
+                    // Mostly valid moves, but also some suicidal ones
+                    let moves: [Direction; 4] = std::array::from_fn(|i| {
+                        let valid = game.valid_moves(i as u8).collect::<Vec<_>>();
+                        if valid.is_empty() || rng.gen_bool(0.1) {
+                            Direction::from(rng.gen_range(0..4))
+                        } else {
+                            valid[rng.gen_range(0..valid.len())]
+                        }
+                    });
+                    let before = game.clone();
+                    let delta = game.make_move(&moves);
+                    history.push((before, delta));
+                }
+
+                while let Some((before, delta)) = history.pop() {
+                    game.unmake_move(delta);
+                    assert_eq!(game.turn, before.turn);
+                    assert_eq!(game.snakes, before.snakes);
+                    assert!(game.grid.cells == before.grid.cells, "{game:?}\n{before:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn game_zobrist() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . o . . .
+            o . . . . . . . . o .
+            . . o 3 . . . . . . .
+            . . . . . . 0 o . . .
+            . . o . . . . . . . .
+            . . . . . o . . . o .
+            . o . . o . . . . . .
+            . . . . . . . 2 . o .
+            . . . 1 . . . o . . .
+            o . . o . . . . . . .
+            . . . . . o . . o . ."#,
+        )
+        .unwrap();
+        assert_eq!(game.zobrist(), game.compute_zobrist());
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Constrictor, GameMode::Wrapped] {
+            for _ in 0..100 {
+                let mut game = game.clone();
+                game.set_mode(mode);
+                game.grid
+                    .add_hazards(&[Vec2D::new(3, 3), Vec2D::new(3, 4), Vec2D::new(4, 4)]);
+                assert_ne!(game.zobrist(), game.compute_zobrist());
+                game.rehash();
+
+                let mut history = Vec::new();
+                while game.outcome() == Outcome::None {
+                    let moves: [Direction; 4] = std::array::from_fn(|i| {
+                        let valid = game.valid_moves(i as u8).collect::<Vec<_>>();
+                        if valid.is_empty() || rng.gen_bool(0.1) {
+                            Direction::from(rng.gen_range(0..4))
+                        } else {
+                            valid[rng.gen_range(0..valid.len())]
+                        }
+                    });
+                    let before = game.zobrist();
+                    let delta = game.make_move(&moves);
+                    assert_eq!(game.zobrist(), game.compute_zobrist(), "{game:?}");
+                    history.push((before, delta));
+                }
+
+                while let Some((before, delta)) = history.pop() {
+                    game.unmake_move(delta);
+                    assert_eq!(game.zobrist(), before);
+                }
+            }
         }
     }
 
@@ -648,4 +1369,110 @@ mod test {
         info!("{game:?}");
         assert!([Right].iter().cloned().eq(game.valid_moves(0)));
     }
+
+    #[test]
+    fn from_request_settings() {
+        use super::*;
+        logging();
+
+        let request = |ruleset: &str| -> GameRequest {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "game": {{ "id": "", "ruleset": {ruleset}, "timeout": 500 }},
+                    "turn": 0,
+                    "board": {{ "height": 7, "width": 7, "food": [], "hazards": [], "snakes": [] }},
+                    "you": {{ "id": "you", "name": "", "health": 100, "body": [{{ "x": 1, "y": 1 }}] }}
+                }}"#
+            ))
+            .unwrap()
+        };
+
+        // Without settings, the defaults of the official game server are used
+        let game = Game::from_request(&request(r#"{ "name": "royale" }"#));
+        assert_eq!(game.settings, GameSettings::default());
+
+        let game = Game::from_request(&request(
+            r#"{
+                "name": "royale",
+                "settings": {
+                    "foodSpawnChance": 25,
+                    "minimumFood": 2,
+                    "hazardDamagePerTurn": 100,
+                    "royale": { "shrinkEveryNTurns": 10 }
+                }
+            }"#,
+        ));
+        assert_eq!(
+            game.settings,
+            GameSettings {
+                hazard_damage: 100,
+                food_spawn_chance: 25,
+                minimum_food: 2,
+                shrink_turns: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn from_request_pruning() {
+        use super::*;
+        use crate::env::{Board, GameData};
+        logging();
+
+        let snake = |id: &str, body: &[(i16, i16)]| Battlesnake {
+            id: id.into(),
+            name: id.into(),
+            health: 100,
+            body: body.iter().map(|&(x, y)| Vec2D::new(x, y)).collect(),
+            shout: String::new(),
+        };
+        let you = snake("you", &[(5, 5), (5, 4), (5, 3)]);
+        let request = GameRequest {
+            game: GameData::default(),
+            turn: 0,
+            board: Board {
+                height: 11,
+                width: 11,
+                food: Vec::new(),
+                hazards: Vec::new(),
+                snakes: vec![
+                    snake("a", &[(0, 10), (1, 10), (2, 10)]),
+                    snake("b", &[(5, 7), (6, 7), (7, 7)]),
+                    you.clone(),
+                    snake("c", &[(10, 0), (10, 1), (10, 2)]),
+                    snake("d", &[(9, 9), (8, 9), (7, 9)]),
+                    snake("e", &[(2, 5), (2, 4), (2, 3)]),
+                ],
+            },
+            you,
+        };
+        let v = Vec2D::new;
+        let heads = |game: &Game| -> Vec<Vec2D> { game.snakes.iter().map(|s| s.head()).collect() };
+
+        // By default, only the three most threatening opponents are kept,
+        // which are the nearest ones (b, d, e), as all have the same length
+        let game = Game::from_request(&request);
+        info!("{game:?}");
+        assert_eq!(heads(&game), vec![v(5, 5), v(5, 7), v(9, 9), v(2, 5)]);
+        assert_eq!(game.obstacles.len(), 6);
+        assert!(game.obstacles.contains(&v(0, 10)));
+        assert!(game.obstacles.contains(&v(10, 0)));
+
+        let game = Game::from_request_with(&request, OpponentPruning::Nearest(3));
+        info!("{game:?}");
+        assert_eq!(heads(&game), vec![v(5, 5), v(5, 7), v(9, 9), v(2, 5)]);
+        // The pruned snakes stay on the grid as obstacles
+        assert!(game.grid[v(0, 10)].t == CellT::Owned);
+        assert!(game.grid[v(10, 0)].t == CellT::Owned);
+
+        let game = Game::from_request_with(&request, OpponentPruning::All);
+        assert_eq!(
+            heads(&game),
+            vec![v(5, 5), v(0, 10), v(5, 7), v(10, 0), v(9, 9), v(2, 5)]
+        );
+
+        let (game, ids) = Game::from_request_ids(&request, OpponentPruning::Nearest(1));
+        assert_eq!(heads(&game), vec![v(5, 5), v(5, 7)]);
+        assert_eq!(ids, vec![request.you.id.clone(), "b".to_string()]);
+    }
 }
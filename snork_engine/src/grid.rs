@@ -1,13 +1,10 @@
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap};
 use std::mem::size_of;
 use std::ops::{Index, IndexMut};
-use std::{f64, usize};
 
 use serde::{Deserialize, Serialize};
 
-use crate::env::{Direction, Vec2D, HAZARD_DAMAGE};
-use crate::util::OrdPair;
+use crate::env::{Direction, Vec2D};
+use crate::pathfinding::Pathfinder;
 
 #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum CellT {
@@ -57,6 +54,9 @@ pub struct Grid {
     pub width: usize,
     pub height: usize,
     pub cells: Vec<Cell>,
+    /// Whether the edges of the grid are connected to the opposite side.
+    #[serde(default)]
+    pub wrapped: bool,
 }
 
 impl Grid {
@@ -67,6 +67,7 @@ impl Grid {
             width,
             height,
             cells: vec![Cell::new(CellT::Free, false); width * height],
+            wrapped: false,
         }
     }
 
@@ -81,6 +82,7 @@ impl Grid {
             width,
             height,
             cells,
+            wrapped: false,
         }
     }
 
@@ -129,61 +131,61 @@ impl Grid {
         p.within(self.width, self.height)
     }
 
+    /// Returns the neighbor of `p` in the direction `d`.
+    /// On wrapped grids, this wraps around to the opposite side.
+    #[inline]
+    pub fn apply(&self, p: Vec2D, d: Direction) -> Vec2D {
+        let p = p.apply(d);
+        if self.wrapped {
+            p.wrap(self.width, self.height)
+        } else {
+            p
+        }
+    }
+
+    /// Returns the manhattan distance between `a` and `b`.
+    /// On wrapped grids, this is the shortest distance across the edges.
+    pub fn distance(&self, a: Vec2D, b: Vec2D) -> u64 {
+        if self.wrapped {
+            let dx = (a.x - b.x).unsigned_abs() as u64;
+            let dy = (a.y - b.y).unsigned_abs() as u64;
+            dx.min(self.width as u64 - dx) + dy.min(self.height as u64 - dy)
+        } else {
+            (a - b).manhattan()
+        }
+    }
+
+    /// Returns the direction from `p` to its neighbor `next`.
+    pub fn direction(&self, p: Vec2D, next: Vec2D) -> Direction {
+        let d = next - p;
+        if self.wrapped && d.manhattan() > 1 {
+            // Moved across the edge
+            Direction::from(-d)
+        } else {
+            Direction::from(d)
+        }
+    }
+
     /// Performes an A* search that applies the `first_move_heuristic` as
     /// additional costs for the first move.
+    /// Hazards cost `hazard_damage` in addition to the regular move costs.
+    ///
+    /// Repeated searches should reuse a [Pathfinder].
     #[must_use]
     pub fn a_star(
         &self,
         start: Vec2D,
         target: Vec2D,
+        hazard_damage: u8,
         first_move_heuristic: &[f64; 4],
     ) -> Option<Vec<Vec2D>> {
-        fn make_path(data: &HashMap<Vec2D, (Vec2D, f64)>, target: Vec2D) -> Vec<Vec2D> {
-            let mut path = Vec::new();
-            let mut p = target;
-            while p.x >= 0 {
-                path.push(p);
-                p = data.get(&p).unwrap().0;
-            }
-            path.reverse();
-            path
-        }
-
-        let mut queue = BinaryHeap::new();
-        let mut data: HashMap<Vec2D, (Vec2D, f64)> = HashMap::new();
-        data.insert(start, (Vec2D::new(-1, -1), 0.0));
-
-        queue.push(OrdPair(Reverse(0), start));
-        while let Some(OrdPair(_, front)) = queue.pop() {
-            let cost = data.get(&front).unwrap().1;
-
-            if front == target {
-                return Some(make_path(&data, target));
-            }
-
-            for d in Direction::all() {
-                let neighbor = front.apply(d);
-                let mut neighbor_cost = cost + 1.0;
-                if self.is_hazardous(neighbor) {
-                    neighbor_cost += HAZARD_DAMAGE as f64;
-                }
-                if front == start {
-                    neighbor_cost += first_move_heuristic[d as usize];
-                }
-
-                if self.has(neighbor) && self[neighbor].t != CellT::Owned {
-                    let cost_so_far = data.get(&neighbor).map_or(f64::MAX, |(_, c)| *c);
-                    if neighbor_cost < cost_so_far {
-                        data.insert(neighbor, (front, neighbor_cost));
-                        // queue does not accept float
-                        let estimated_cost = neighbor_cost + (neighbor - start).manhattan() as f64;
-                        queue.push(OrdPair(Reverse((estimated_cost * 10.0) as usize), neighbor));
-                    }
-                }
-            }
-        }
-
-        None
+        Pathfinder::new(self.width, self.height).a_star(
+            self,
+            start,
+            target,
+            hazard_damage,
+            first_move_heuristic,
+        )
     }
 }
 
@@ -230,7 +232,12 @@ mod test {
         let grid = Grid::new(11, 11);
 
         let path = grid
-            .a_star(Vec2D::new(0, 0), Vec2D::new(1, 1), &[1.0, 0.0, 0.0, 0.0])
+            .a_star(
+                Vec2D::new(0, 0),
+                Vec2D::new(1, 1),
+                14,
+                &[1.0, 0.0, 0.0, 0.0],
+            )
             .unwrap();
         info!("{:?}", path);
         assert_eq!(path.len(), 3);
@@ -250,11 +257,66 @@ mod test {
             Vec2D::new(2, 3),
         ]);
         let path = grid
-            .a_star(Vec2D::new(0, 2), Vec2D::new(4, 2), &[1.0, 1.0, 1.0, 1.0])
+            .a_star(
+                Vec2D::new(0, 2),
+                Vec2D::new(4, 2),
+                14,
+                &[1.0, 1.0, 1.0, 1.0],
+            )
             .unwrap();
         info!("{:?}", path);
         assert_eq!(path.len(), 9);
         assert_eq!(path[0], Vec2D::new(0, 2));
         assert_eq!(path[path.len() - 1], Vec2D::new(4, 2));
     }
+
+    #[test]
+    fn grid_a_star_wrapped() {
+        use super::*;
+        logging();
+        let mut grid = Grid::new(11, 11);
+        grid.wrapped = true;
+
+        let path = grid
+            .a_star(
+                Vec2D::new(0, 0),
+                Vec2D::new(10, 10),
+                14,
+                &[1.0, 0.0, 0.0, 0.0],
+            )
+            .unwrap();
+        info!("{:?}", path);
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[0], Vec2D::new(0, 0));
+        assert_eq!(path[2], Vec2D::new(10, 10));
+        assert_eq!(grid.direction(path[0], path[1]), Direction::Down);
+        assert_eq!(grid.distance(path[0], path[2]), 2);
+    }
+
+    #[test]
+    fn grid_a_star_hazards_wrapped() {
+        use super::*;
+        logging();
+        let mut grid = Grid::new(5, 5);
+        grid.wrapped = true;
+        grid.add_hazards(&[
+            Vec2D::new(2, 0),
+            Vec2D::new(2, 1),
+            Vec2D::new(2, 2),
+            Vec2D::new(2, 3),
+        ]);
+        let path = grid
+            .a_star(
+                Vec2D::new(0, 2),
+                Vec2D::new(4, 2),
+                14,
+                &[1.0, 1.0, 1.0, 1.0],
+            )
+            .unwrap();
+        info!("{:?}", path);
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[0], Vec2D::new(0, 2));
+        assert_eq!(path[1], Vec2D::new(4, 2));
+        assert_eq!(grid.direction(path[0], path[1]), Direction::Left);
+    }
 }
@@ -2,11 +2,16 @@ use env_logger::fmt::Formatter;
 use owo_colors::{AnsiColors, OwoColorize};
 use std::io::Write;
 
+pub mod bitboard;
 pub mod env;
 pub mod game;
 pub mod grid;
+pub mod pathfinding;
+pub mod relevance;
+pub mod rules;
 pub mod simulate;
 pub mod util;
+pub mod zobrist;
 
 pub fn logging() {
     let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
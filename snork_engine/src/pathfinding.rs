@@ -0,0 +1,687 @@
+//! # Pathfinding
+//!
+//! Shortest paths on the [Grid], avoiding all [CellT::Owned] cells.
+//!
+//! Every move costs one, moves onto hazards cost the `hazard_damage`
+//! in addition, and the first move from a start cell costs the
+//! `first_move_costs` of its direction in addition.
+//! The first move costs must not be negative.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::env::{Direction, Vec2D};
+use crate::game::Game;
+use crate::grid::{CellT, Grid};
+
+/// Parent of the start cells and unreached cells.
+const NONE: u32 = u32::MAX;
+
+/// Entry of the priority queue, which is ordered by the lowest estimate.
+///
+/// Ties prefer the cell that is closer to the target and then the cell that
+/// has been pushed first, so that the neighbors are searched in the order
+/// of [Direction::all].
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    estimate: f64,
+    cost: f64,
+    sequence: u32,
+    index: u32,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then(self.cost.total_cmp(&other.cost))
+            .then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+/// Pathfinder with flat buffers that are reused by all searches.
+///
+/// After a search, the pathfinder contains the costs and paths from the
+/// start cells to all cells that have been reached.
+/// [Pathfinder::distances] searches the whole grid, so that the result can
+/// be kept as distance field and queried for any cell.
+#[derive(Debug, Clone)]
+pub struct Pathfinder {
+    /// Cost from the nearest start cell, infinite if unreached
+    costs: Vec<f64>,
+    /// Index of the previous cell on the path
+    parents: Vec<u32>,
+    queue: BinaryHeap<Entry>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Pathfinder {
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            costs: vec![f64::INFINITY; width * height],
+            parents: vec![NONE; width * height],
+            queue: BinaryHeap::with_capacity(width * height),
+            width,
+            height,
+        }
+    }
+
+    /// Performs an A* search from `start` to `target` and returns the path,
+    /// including both.
+    #[must_use]
+    pub fn a_star(
+        &mut self,
+        grid: &Grid,
+        start: Vec2D,
+        target: Vec2D,
+        hazard_damage: u8,
+        first_move_costs: &[f64; 4],
+    ) -> Option<Vec<Vec2D>> {
+        // Admissible, as every move costs at least one
+        let found = self.search(
+            grid,
+            &[start],
+            hazard_damage,
+            first_move_costs,
+            |p| p == target,
+            |p| grid.distance(p, target) as f64,
+        )?;
+        self.path(found)
+    }
+
+    /// Searches the path from the nearest of the `starts` to the nearest of
+    /// the `targets` in a single pass (Dijkstra).
+    #[must_use]
+    pub fn nearest(
+        &mut self,
+        grid: &Grid,
+        starts: &[Vec2D],
+        targets: &[Vec2D],
+        hazard_damage: u8,
+        first_move_costs: &[f64; 4],
+    ) -> Option<Vec<Vec2D>> {
+        let found = self.search(
+            grid,
+            starts,
+            hazard_damage,
+            first_move_costs,
+            |p| targets.contains(&p),
+            |_| 0.0,
+        )?;
+        self.path(found)
+    }
+
+    /// Computes the costs from the nearest of the `starts` to all cells,
+    /// which can be queried with [Pathfinder::cost] and [Pathfinder::path].
+    pub fn distances(
+        &mut self,
+        grid: &Grid,
+        starts: &[Vec2D],
+        hazard_damage: u8,
+        first_move_costs: &[f64; 4],
+    ) {
+        self.search(
+            grid,
+            starts,
+            hazard_damage,
+            first_move_costs,
+            |_| false,
+            |_| 0.0,
+        );
+    }
+
+    /// Returns the cost from the nearest start cell to `p`.
+    ///
+    /// This is only the shortest cost for all cells after
+    /// [Pathfinder::distances].
+    pub fn cost(&self, p: Vec2D) -> Option<f64> {
+        if !p.within(self.width, self.height) {
+            return None;
+        }
+        let cost = self.costs[self.index(p)];
+        (cost < f64::INFINITY).then_some(cost)
+    }
+
+    /// Returns the path from the nearest start cell to `p`, including both.
+    pub fn path(&self, p: Vec2D) -> Option<Vec<Vec2D>> {
+        self.cost(p)?;
+        let mut path = vec![p];
+        let mut i = self.parents[self.index(p)];
+        while i != NONE {
+            path.push(self.position(i as usize));
+            i = self.parents[i as usize];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    fn index(&self, p: Vec2D) -> usize {
+        p.x as usize + p.y as usize * self.width
+    }
+
+    fn position(&self, i: usize) -> Vec2D {
+        Vec2D::new((i % self.width) as _, (i / self.width) as _)
+    }
+
+    /// Searches from the `starts` until a cell that is accepted by `goal`
+    /// is reached, which is returned.
+    ///
+    /// The `estimate` of the remaining cost has to be consistent.
+    fn search(
+        &mut self,
+        grid: &Grid,
+        starts: &[Vec2D],
+        hazard_damage: u8,
+        first_move_costs: &[f64; 4],
+        goal: impl Fn(Vec2D) -> bool,
+        estimate: impl Fn(Vec2D) -> f64,
+    ) -> Option<Vec2D> {
+        debug_assert!(grid.width == self.width && grid.height == self.height);
+        self.costs.fill(f64::INFINITY);
+        self.parents.fill(NONE);
+        self.queue.clear();
+        let mut sequence = 0;
+
+        for &p in starts {
+            if grid.has(p) {
+                let index = self.index(p);
+                self.costs[index] = 0.0;
+                self.queue.push(Entry {
+                    estimate: estimate(p),
+                    cost: 0.0,
+                    sequence,
+                    index: index as u32,
+                });
+                sequence += 1;
+            }
+        }
+
+        while let Some(Entry { cost, index, .. }) = self.queue.pop() {
+            // Outdated entry
+            if cost > self.costs[index as usize] {
+                continue;
+            }
+            let front = self.position(index as usize);
+            if goal(front) {
+                return Some(front);
+            }
+            let is_start = self.parents[index as usize] == NONE;
+
+            for d in Direction::all() {
+                let neighbor = grid.apply(front, d);
+                if !grid.has(neighbor) || grid[neighbor].t == CellT::Owned {
+                    continue;
+                }
+
+                let mut neighbor_cost = cost + 1.0;
+                if grid[neighbor].hazard {
+                    neighbor_cost += hazard_damage as f64;
+                }
+                if is_start {
+                    neighbor_cost += first_move_costs[d as usize];
+                }
+
+                let i = self.index(neighbor);
+                if neighbor_cost < self.costs[i] {
+                    self.costs[i] = neighbor_cost;
+                    self.parents[i] = index;
+                    self.queue.push(Entry {
+                        estimate: neighbor_cost + estimate(neighbor),
+                        cost: neighbor_cost,
+                        sequence,
+                        index: i as u32,
+                    });
+                    sequence += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Label of a state of the [SpaceTimePathfinder].
+#[derive(Debug, Clone, Copy)]
+struct Label {
+    cost: f64,
+    health: u8,
+    /// Food eaten on the way
+    food: u16,
+    parent: u32,
+}
+
+impl Default for Label {
+    fn default() -> Self {
+        Self {
+            cost: f64::INFINITY,
+            health: 0,
+            food: 0,
+            parent: NONE,
+        }
+    }
+}
+
+/// Pathfinder on the time-expanded grid, which knows when the body cells
+/// become free.
+///
+/// The states are the cells after a number of moves, which have the same
+/// costs as the [Pathfinder].
+/// Body cells are free as soon as the tail has passed them, which is
+/// delayed by the food that the snake eats on the way.
+/// The other snakes are assumed not to eat, and in constrictor games the
+/// bodies are never freed, just like obstacles of snakes that are not
+/// simulated.
+/// Paths that starve the snake or run into its own new body are rejected.
+///
+/// Only the cheapest path to each state is kept, even if a more expensive
+/// one would have more health.
+#[derive(Debug, Clone)]
+pub struct SpaceTimePathfinder {
+    /// Labels of the states, indexed by the move and then the cell
+    labels: Vec<Label>,
+    /// Cheapest state of every cell
+    best: Vec<u32>,
+    /// Number of moves after which the cell is free, zero if it is free
+    free: Vec<u16>,
+    /// Whether the cell is part of the searching snake
+    own: Vec<bool>,
+    queue: BinaryHeap<Entry>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl SpaceTimePathfinder {
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            labels: Vec::new(),
+            best: vec![NONE; width * height],
+            free: vec![0; width * height],
+            own: vec![false; width * height],
+            queue: BinaryHeap::new(),
+            width,
+            height,
+        }
+    }
+
+    /// Searches the paths of the `snake` with up to `max_moves` moves to all
+    /// cells, which can be queried with [SpaceTimePathfinder::cost] and
+    /// [SpaceTimePathfinder::path].
+    ///
+    /// The cells that are `blocked` after a number of moves are avoided,
+    /// which can be used for the moves of the other snakes.
+    pub fn search(
+        &mut self,
+        game: &Game,
+        snake: u8,
+        max_moves: usize,
+        first_move_costs: &[f64; 4],
+        blocked: impl Fn(Vec2D, usize) -> bool,
+    ) {
+        debug_assert!(game.grid.width == self.width && game.grid.height == self.height);
+        let area = self.width * self.height;
+        self.labels.clear();
+        self.labels.resize(area * (max_moves + 1), Label::default());
+        self.best.fill(NONE);
+        self.free.fill(0);
+        self.own.fill(false);
+        self.queue.clear();
+
+        let Some(you) = game.snakes.get(snake as usize).filter(|s| s.alive()) else {
+            return;
+        };
+
+        // Owned cells that are not part of a snake are obstacles
+        for (free, cell) in self.free.iter_mut().zip(&game.grid.cells) {
+            if cell.t == CellT::Owned {
+                *free = u16::MAX;
+            }
+        }
+        // Snakes that grow every turn never free their bodies
+        let persistent = game.rules().snakes_grow();
+        for (id, s) in game.snakes.iter().enumerate() {
+            for (i, &p) in s.body.iter().enumerate() {
+                if game.grid.has(p) {
+                    let index = self.index(p);
+                    self.free[index] = if persistent { u16::MAX } else { i as u16 + 1 };
+                    self.own[index] = id == snake as usize;
+                }
+            }
+        }
+
+        let start = self.index(you.head());
+        self.labels[start] = Label {
+            cost: 0.0,
+            health: you.health,
+            food: 0,
+            parent: NONE,
+        };
+        self.queue.push(Entry {
+            estimate: 0.0,
+            cost: 0.0,
+            sequence: 0,
+            index: start as u32,
+        });
+        let mut sequence = 1;
+
+        while let Some(Entry { cost, index, .. }) = self.queue.pop() {
+            let label = self.labels[index as usize];
+            // Outdated entry
+            if cost > label.cost {
+                continue;
+            }
+            let (moves, cell) = (index as usize / area, index as usize % area);
+            if self.best[cell] == NONE {
+                self.best[cell] = index;
+            }
+            if moves >= max_moves {
+                continue;
+            }
+            let front = self.position(cell);
+            let len = you.body.len() + label.food as usize;
+
+            for d in Direction::all() {
+                let neighbor = game.grid.apply(front, d);
+                if !game.grid.has(neighbor) || blocked(neighbor, moves + 1) {
+                    continue;
+                }
+                let n_cell = self.index(neighbor);
+
+                // Freed by the tail in time?
+                let delay = if self.own[n_cell] { label.food } else { 0 };
+                if self.free[n_cell] as usize + delay as usize > moves + 1 {
+                    continue;
+                }
+                if self.on_path(index, neighbor, if persistent { moves } else { len - 1 }) {
+                    continue;
+                }
+
+                let g_cell = game.grid[neighbor];
+                let mut neighbor_cost = cost + 1.0;
+                let (health, food) = if g_cell.t == CellT::Food {
+                    (100, label.food + 1)
+                } else {
+                    let mut damage = 1;
+                    if g_cell.hazard {
+                        neighbor_cost += game.settings.hazard_damage as f64;
+                        damage += game.settings.hazard_damage;
+                    }
+                    (label.health.saturating_sub(damage), label.food)
+                };
+                if health == 0 {
+                    continue;
+                }
+                if moves == 0 {
+                    neighbor_cost += first_move_costs[d as usize];
+                }
+
+                let i = (moves + 1) * area + n_cell;
+                if neighbor_cost < self.labels[i].cost {
+                    self.labels[i] = Label {
+                        cost: neighbor_cost,
+                        health,
+                        food,
+                        parent: index,
+                    };
+                    self.queue.push(Entry {
+                        estimate: neighbor_cost,
+                        cost: neighbor_cost,
+                        sequence,
+                        index: i as u32,
+                    });
+                    sequence += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the cost of the cheapest path to `p`.
+    pub fn cost(&self, p: Vec2D) -> Option<f64> {
+        if !p.within(self.width, self.height) {
+            return None;
+        }
+        let best = self.best[self.index(p)];
+        (best != NONE).then(|| self.labels[best as usize].cost)
+    }
+
+    /// Returns the cheapest path to `p`, including the head and `p`.
+    pub fn path(&self, p: Vec2D) -> Option<Vec<Vec2D>> {
+        if !p.within(self.width, self.height) {
+            return None;
+        }
+        let area = self.width * self.height;
+        let mut i = self.best[self.index(p)];
+        let mut path = Vec::new();
+        while i != NONE {
+            path.push(self.position(i as usize % area));
+            i = self.labels[i as usize].parent;
+        }
+        path.reverse();
+        (!path.is_empty()).then_some(path)
+    }
+
+    /// Returns if `p` is one of the last `count` cells on the path to the
+    /// state `index`, which are occupied by the new body.
+    fn on_path(&self, mut index: u32, p: Vec2D, count: usize) -> bool {
+        let area = self.width * self.height;
+        for _ in 0..count {
+            if index == NONE {
+                break;
+            }
+            // The start is part of the old body
+            let label = self.labels[index as usize];
+            if label.parent != NONE && self.position(index as usize % area) == p {
+                return true;
+            }
+            index = label.parent;
+        }
+        false
+    }
+
+    fn index(&self, p: Vec2D) -> usize {
+        p.x as usize + p.y as usize * self.width
+    }
+
+    fn position(&self, i: usize) -> Vec2D {
+        Vec2D::new((i % self.width) as _, (i / self.width) as _)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn a_star_target_heuristic() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        // The estimate has to point to the target, not back to the start
+        let mut grid = Grid::new(11, 11);
+        grid.add_snake((0..10).map(|y| v2(5, y)));
+        let mut pathfinder = Pathfinder::new(11, 11);
+        let path = pathfinder
+            .a_star(&grid, v2(0, 0), v2(10, 0), 0, &[0.0; 4])
+            .unwrap();
+        info!("{path:?}");
+        assert_eq!(path.len(), 31);
+        assert_eq!(pathfinder.cost(v2(10, 0)), Some(30.0));
+
+        // Fractional first move costs are not quantized
+        let path = pathfinder
+            .a_star(&grid, v2(2, 2), v2(2, 4), 0, &[0.05, 0.0, 0.0, 0.0])
+            .unwrap();
+        assert_eq!(path, vec![v2(2, 2), v2(2, 3), v2(2, 4)]);
+        assert!((pathfinder.cost(v2(2, 4)).unwrap() - 2.05).abs() < 1e-9);
+
+        // Unreachable
+        grid.add_snake((0..11).map(|y| v2(5, y)));
+        assert!(pathfinder
+            .a_star(&grid, v2(0, 0), v2(10, 0), 0, &[0.0; 4])
+            .is_none());
+    }
+
+    #[test]
+    fn nearest() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        let mut grid = Grid::new(7, 7);
+        grid.add_hazards(&[v2(2, 1), v2(4, 1), v2(3, 2), v2(3, 0)]);
+        let mut pathfinder = Pathfinder::new(7, 7);
+
+        let targets = [v2(0, 0), v2(6, 5), v2(3, 1)];
+        let path = pathfinder
+            .nearest(&grid, &[v2(3, 3)], &targets, 14, &[0.0; 4])
+            .unwrap();
+        info!("{path:?}");
+        // The closest target is behind a hazard
+        assert_eq!(path.first(), Some(&v2(3, 3)));
+        assert_eq!(path.len(), 6);
+        assert_eq!(path.last(), Some(&v2(6, 5)));
+
+        // Multiple start cells
+        let path = pathfinder
+            .nearest(&grid, &[v2(0, 6), v2(6, 0)], &targets, 14, &[0.0; 4])
+            .unwrap();
+        assert_eq!(
+            path,
+            vec![v2(6, 0), v2(6, 1), v2(6, 2), v2(6, 3), v2(6, 4), v2(6, 5)]
+        );
+    }
+
+    #[test]
+    fn distances() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        let mut grid = Grid::new(5, 5);
+        grid.wrapped = true;
+        grid.add_hazards(&[v2(1, 0)]);
+        let mut pathfinder = Pathfinder::new(5, 5);
+        pathfinder.distances(&grid, &[v2(0, 0)], 14, &[1.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(pathfinder.cost(v2(0, 0)), Some(0.0));
+        assert_eq!(pathfinder.cost(v2(0, 1)), Some(2.0));
+        assert_eq!(pathfinder.cost(v2(4, 0)), Some(1.0));
+        assert_eq!(pathfinder.cost(v2(1, 0)), Some(15.0));
+        // Across the edge instead of through the hazard
+        assert_eq!(pathfinder.cost(v2(2, 0)), Some(3.0));
+        assert_eq!(
+            pathfinder.path(v2(2, 0)).unwrap(),
+            vec![v2(0, 0), v2(4, 0), v2(3, 0), v2(2, 0)]
+        );
+        assert_eq!(pathfinder.cost(v2(2, 2)), Some(5.0));
+
+        // Kept as distance field
+        let field = pathfinder.clone();
+        pathfinder.distances(&grid, &[v2(2, 2)], 14, &[0.0; 4]);
+        assert_eq!(field.cost(v2(4, 4)), Some(2.0));
+        assert_eq!(pathfinder.cost(v2(4, 4)), Some(4.0));
+    }
+
+    #[test]
+    fn space_time() {
+        use super::*;
+        use crate::env::v2;
+        use crate::rules::GameMode;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            o . . . .
+            > > > > 1
+            0 < < . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        // The wall is permanent for the regular pathfinder
+        let mut pathfinder = Pathfinder::new(5, 3);
+        assert!(pathfinder
+            .a_star(&game.grid, v2(0, 0), v2(0, 2), 0, &[0.0; 4])
+            .is_none());
+
+        // But the tail of the enemy is moving away
+        let mut pathfinder = SpaceTimePathfinder::new(5, 3);
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+        let path = pathfinder.path(v2(0, 2)).unwrap();
+        info!("{path:?}");
+        assert_eq!(path, vec![v2(0, 0), v2(0, 1), v2(0, 2)]);
+        assert_eq!(pathfinder.cost(v2(0, 2)), Some(2.0));
+        // The enemy head moves after all other segments
+        assert_eq!(pathfinder.path(v2(4, 2)).unwrap().len(), 7);
+
+        // Blocked after the first move
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |p, moves| {
+            moves == 1 && p == v2(0, 1)
+        });
+        assert!(pathfinder.cost(v2(0, 2)).is_none());
+
+        // Starving before reaching the food
+        game.snakes[0].health = 1;
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+        assert!(pathfinder.cost(v2(0, 2)).is_none());
+        game.snakes[0].health = 2;
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+        assert_eq!(pathfinder.cost(v2(0, 2)), Some(2.0));
+
+        // Hazard damage
+        game.grid[v2(0, 1)].hazard = true;
+        game.snakes[0].health = 10;
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+        assert!(pathfinder.cost(v2(0, 2)).is_none());
+
+        // Bodies are permanent walls
+        game.grid[v2(0, 1)].hazard = false;
+        game.set_mode(GameMode::Constrictor);
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+        assert!(pathfinder.cost(v2(0, 2)).is_none());
+    }
+
+    #[test]
+    fn space_time_growth() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        // The own tail stays for another turn after eating
+        let game = Game::parse(
+            r#"
+            . . . .
+            o . . .
+            0 < < <"#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        let mut pathfinder = SpaceTimePathfinder::new(4, 3);
+        pathfinder.search(&game, 0, 8, &[0.0; 4], |_, _| false);
+
+        assert_eq!(pathfinder.cost(v2(3, 1)), Some(4.0));
+        // The first segment after the head is free after four instead of
+        // three moves, so that a detour is needed
+        let path = pathfinder.path(v2(1, 0)).unwrap();
+        info!("{path:?}");
+        assert_eq!(path.len(), 6);
+    }
+}
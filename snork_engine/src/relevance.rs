@@ -0,0 +1,388 @@
+//! # Opponent Relevance
+//!
+//! Selects the opponents that are simulated by the searches in games with
+//! many snakes.
+//!
+//! The other snakes are removed from the game, but their bodies stay on the
+//! grid as obstacles, which are never freed (see [Obstacles]).
+//! They still block cells, but the searches no longer branch over their
+//! moves.
+
+use serde::{Deserialize, Serialize};
+
+use crate::env::Direction;
+use crate::game::{Game, MAX_SNAKES};
+use crate::grid::CellT;
+use crate::pathfinding::Pathfinder;
+
+/// Policy that selects the opponents, which are simulated by the searches,
+/// see [OpponentPruning::apply].
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum OpponentPruning {
+    /// Keeps all opponents, up to the `MAX_SNAKES - 1` nearest ones.
+    All,
+    /// Keeps the given number of opponents, whose bodies are nearest to our head.
+    Nearest(usize),
+    /// Keeps the most threatening opponents.
+    Relevance(Relevance),
+}
+
+impl Default for OpponentPruning {
+    fn default() -> Self {
+        Self::Relevance(Relevance::default())
+    }
+}
+
+impl OpponentPruning {
+    /// Returns the maximum number of opponents that are kept.
+    pub fn max_opponents(&self) -> usize {
+        let max = match self {
+            Self::All => MAX_SNAKES - 1,
+            Self::Nearest(n) => *n,
+            Self::Relevance(relevance) => relevance.opponents,
+        };
+        max.min(MAX_SNAKES - 1)
+    }
+
+    /// Returns the ids of the living opponents that are kept, in ascending
+    /// order.
+    ///
+    /// Our snake (id 0) is always kept and not part of the result.
+    pub fn select(&self, game: &Game) -> Vec<usize> {
+        let mut opponents: Vec<usize> = (1..game.snakes.len())
+            .filter(|&id| game.snake_is_alive(id as u8))
+            .collect();
+
+        let max_opponents = self.max_opponents();
+        if opponents.len() > max_opponents {
+            match self {
+                Self::Relevance(relevance) => {
+                    let threats = relevance.threats(game);
+                    opponents.sort_by(|&a, &b| threats[b].total_cmp(&threats[a]));
+                }
+                _ => {
+                    let head = game.snakes[0].head();
+                    opponents.sort_by_key(|&id| {
+                        game.snakes[id]
+                            .body
+                            .iter()
+                            .map(|&p| (p - head).manhattan())
+                            .min()
+                            .unwrap_or_default()
+                    });
+                }
+            }
+            opponents.truncate(max_opponents);
+            // Keep the order of the ids
+            opponents.sort_unstable();
+        }
+        opponents
+    }
+
+    /// Removes the opponents that are not selected from the `game`.
+    ///
+    /// Their bodies stay on the grid as obstacles, which are added to
+    /// [Game::obstacles].
+    /// Returns the previous ids of the remaining snakes, in ascending order.
+    pub fn apply(&self, game: &mut Game) -> Vec<usize> {
+        let Some(you) = game.snakes.first().filter(|s| s.alive()).cloned() else {
+            return (0..game.snakes.len()).collect();
+        };
+        let mut kept = self.select(game);
+        if kept.len() + 1 == game.snakes.len() {
+            return (0..game.snakes.len()).collect();
+        }
+
+        let mut snakes = vec![you];
+        let mut pruned = Vec::new();
+        for (id, snake) in std::mem::take(&mut game.snakes)
+            .into_iter()
+            .enumerate()
+            .skip(1)
+        {
+            if kept.contains(&id) {
+                snakes.push(snake);
+            } else if snake.alive() {
+                pruned.push(snake);
+            }
+        }
+        game.snakes = snakes;
+
+        for snake in &pruned {
+            for &p in &snake.body {
+                if !game.obstacles.contains(&p) {
+                    game.obstacles.push(p);
+                }
+            }
+        }
+
+        let obstacles = match self {
+            Self::Relevance(relevance) => relevance.obstacles,
+            _ => Obstacles::Static,
+        };
+        if obstacles == Obstacles::Predicted {
+            for snake in &pruned {
+                for d in Direction::all() {
+                    let p = game.grid.apply(snake.head(), d);
+                    if !game.grid.has(p) || game.obstacles.contains(&p) {
+                        continue;
+                    }
+                    // Free cells and food are blocked immediately, the bodies of
+                    // the simulated snakes as soon as they leave the cell
+                    game.grid[p].t = CellT::Owned;
+                    game.obstacles.push(p);
+                }
+            }
+        }
+        game.rehash();
+        kept.insert(0, 0);
+        kept
+    }
+}
+
+/// How the opponents that are not simulated stay on the grid.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum Obstacles {
+    /// Only the current body
+    #[default]
+    Static,
+    /// The body and all cells the snake might move to next, including food
+    Predicted,
+}
+
+/// Ranks the opponents by how threatening they are for our snake.
+///
+/// The threat is a weighted sum of how near the opponent is, how long it is
+/// compared to us, and how much of the board it reaches before us.
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Relevance {
+    /// Number of opponents that are kept
+    pub opponents: usize,
+    /// Weight of `1 / (1 + moves)` until we reach a cell next to the
+    /// opponent's head, zero if it is unreachable
+    pub distance: f64,
+    /// Weight of the opponent's length divided by ours
+    pub length: f64,
+    /// Weight of the share of the cells, which the opponent reaches before
+    /// or at the same time as we do
+    pub territory: f64,
+    /// How the pruned opponents stay on the grid
+    pub obstacles: Obstacles,
+}
+
+impl Default for Relevance {
+    fn default() -> Self {
+        Self {
+            opponents: 3,
+            distance: 1.0,
+            length: 0.5,
+            territory: 1.0,
+            obstacles: Obstacles::Static,
+        }
+    }
+}
+
+impl Relevance {
+    /// Returns the threat of every snake, which is zero for our snake (id 0)
+    /// and the dead ones.
+    ///
+    /// The distances are searched around the bodies, hazards are more
+    /// expensive like in [Pathfinder].
+    pub fn threats(&self, game: &Game) -> Vec<f64> {
+        let mut threats = vec![0.0; game.snakes.len()];
+        let Some(you) = game.snakes.first().filter(|s| s.alive()) else {
+            return threats;
+        };
+
+        let grid = &game.grid;
+        let hazard_damage = game.settings.hazard_damage;
+        let mut own = Pathfinder::new(grid.width, grid.height);
+        own.distances(grid, &[you.head()], hazard_damage, &[0.0; 4]);
+        let mut enemy = Pathfinder::new(grid.width, grid.height);
+
+        let area = (grid.width * grid.height) as f64;
+        for (id, snake) in game.snakes.iter().enumerate().skip(1) {
+            if !snake.alive() {
+                continue;
+            }
+            enemy.distances(grid, &[snake.head()], hazard_damage, &[0.0; 4]);
+
+            let meet = Direction::all()
+                .into_iter()
+                .filter_map(|d| own.cost(grid.apply(snake.head(), d)))
+                .min_by(f64::total_cmp);
+            let distance = meet.map_or(0.0, |moves| 1.0 / (1.0 + moves));
+
+            let length = snake.body.len() as f64 / you.body.len() as f64;
+
+            let mut reached = 0;
+            for y in 0..grid.height {
+                for x in 0..grid.width {
+                    let p = (x, y).into();
+                    if let Some(cost) = enemy.cost(p) {
+                        if own.cost(p).is_none_or(|own| cost <= own) {
+                            reached += 1;
+                        }
+                    }
+                }
+            }
+            let territory = reached as f64 / area;
+
+            threats[id] =
+                self.distance * distance + self.length * length + self.territory * territory;
+        }
+        threats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::logging;
+
+    #[test]
+    fn threats() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            1 < < < < < < . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . 0 . . . . .
+            . . . . . ^ . 2 . . .
+            . . . . . ^ . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        let threats = Relevance::default().threats(&game);
+        info!("{threats:?}");
+        assert_eq!(threats[0], 0.0);
+        // Being longer outweighs being nearer
+        assert!(threats[1] > threats[2]);
+
+        let pruning = OpponentPruning::Relevance(Relevance {
+            opponents: 1,
+            ..Relevance::default()
+        });
+        assert_eq!(pruning.select(&game), vec![1]);
+        assert_eq!(OpponentPruning::Nearest(1).select(&game), vec![2]);
+        assert_eq!(OpponentPruning::All.select(&game), vec![1, 2]);
+    }
+
+    #[test]
+    fn obstacles() {
+        use super::*;
+        use crate::env::v2;
+        use crate::game::EliminationCause;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . 0 . . . 2 .
+            . ^ . . . ^ .
+            . ^ . 1 . ^ .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+
+        let mut pruned = game.clone();
+        assert_eq!(OpponentPruning::Nearest(1).apply(&mut pruned), vec![0, 1]);
+        info!("{pruned:?}");
+        assert_eq!(pruned.snakes.len(), 2);
+        assert_eq!(pruned.snakes[1].head(), v2(3, 2));
+        // The body of the pruned snake stays on the grid
+        for p in [v2(5, 4), v2(5, 3), v2(5, 2)] {
+            assert!(pruned.grid[p].t == CellT::Owned);
+        }
+        assert!(pruned.grid[v2(5, 5)].t == CellT::Free);
+        assert_eq!(pruned.obstacles.len(), 3);
+
+        // The obstacles are part of the hash
+        let mut removed = game.clone();
+        removed.snakes.truncate(2);
+        removed.rehash();
+        assert_ne!(pruned.zobrist(), removed.zobrist());
+
+        // Moving into the obstacle is deadly
+        let mut next = pruned.clone();
+        next.step(&[Direction::Up, Direction::Right]);
+        next.step(&[Direction::Up, Direction::Right]);
+        assert_eq!(next.snakes[1].elimination, Some(EliminationCause::Obstacle));
+        // Obstacles are never freed
+        assert!(next.grid[v2(5, 2)].t == CellT::Owned);
+
+        let mut predicted = game.clone();
+        OpponentPruning::Relevance(Relevance {
+            opponents: 1,
+            distance: 1.0,
+            length: 0.0,
+            territory: 0.0,
+            obstacles: Obstacles::Predicted,
+        })
+        .apply(&mut predicted);
+        info!("{predicted:?}");
+        assert_eq!(predicted.snakes.len(), 2);
+        // The cells next to the head are blocked as well
+        for p in [v2(4, 4), v2(6, 4), v2(5, 5)] {
+            assert!(predicted.grid[p].t == CellT::Owned);
+        }
+    }
+
+    #[test]
+    fn predicted_obstacles() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . . o .
+            . ^ 1 < < 2 .
+            . . . . . ^ .
+            . . . . . ^ ."#,
+        )
+        .unwrap();
+        OpponentPruning::Relevance(Relevance {
+            opponents: 1,
+            distance: 1.0,
+            length: 0.0,
+            territory: 0.0,
+            obstacles: Obstacles::Predicted,
+        })
+        .apply(&mut game);
+        info!("{game:?}");
+        assert_eq!(game.snakes.len(), 2);
+        assert_eq!(game.snakes[1].head(), v2(2, 2));
+
+        // Free cells and food next to the head are blocked
+        assert!(game.grid[v2(6, 2)].t == CellT::Owned);
+        assert!(game.grid[v2(5, 3)].t == CellT::Owned);
+        assert_eq!(game.zobrist(), game.compute_zobrist());
+
+        // The tail of the simulated snake does not free the predicted cell
+        assert!(game.obstacles.contains(&v2(4, 2)));
+        game.step(&[Direction::Up, Direction::Down]);
+        assert!(game.snakes.iter().all(|s| s.alive()));
+        assert!(game.grid[v2(4, 2)].t == CellT::Owned);
+        assert!(game.grid[v2(3, 2)].t == CellT::Owned);
+        game.step(&[Direction::Up, Direction::Down]);
+        assert!(game.grid[v2(4, 2)].t == CellT::Owned);
+        assert!(game.grid[v2(3, 2)].t == CellT::Free);
+    }
+}
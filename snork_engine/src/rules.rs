@@ -0,0 +1,536 @@
+//! # Rulesets
+//!
+//! The rules of the different game modes, which define how a turn is executed
+//! and how the board changes between turns.
+//!
+//! The standard phases are ported from the official rules and are reused by
+//! the other game modes, which only override the phases that differ.
+//!
+//! See: https://github.com/BattlesnakeOfficial/rules
+
+use std::fmt::Debug;
+
+use rand::seq::IteratorRandom;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::env::{Direction, Vec2D};
+use crate::game::{EliminationCause, Game, Outcome, Snake};
+use crate::grid::CellT;
+
+/// The game mode, which selects the [Ruleset] a game is played with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum GameMode {
+    #[default]
+    Standard,
+    Royale,
+    Constrictor,
+    Wrapped,
+    Solo,
+}
+
+impl GameMode {
+    /// Selects the game mode from the ruleset name of a request.
+    /// Unknown rulesets are played with the standard rules.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "royale" => Self::Royale,
+            "constrictor" => Self::Constrictor,
+            "wrapped" => Self::Wrapped,
+            "solo" => Self::Solo,
+            _ => Self::Standard,
+        }
+    }
+
+    /// Returns the rules of this game mode.
+    pub fn ruleset(self) -> &'static dyn Ruleset {
+        match self {
+            Self::Standard => &Standard,
+            Self::Royale => &Royale,
+            Self::Constrictor => &Constrictor,
+            Self::Wrapped => &Wrapped,
+            Self::Solo => &Solo,
+        }
+    }
+}
+
+/// The phases of a game mode.
+///
+/// [Game::step] executes the phases in the order they are declared here.
+/// The default implementations follow the official standard ruleset.
+/// Snakes that are eliminated during a phase have to be marked with an
+/// [EliminationCause] and are removed from the board by [Game::step].
+pub trait Ruleset: Debug + Send + Sync {
+    /// Moves the head of all snakes and pops their tails.
+    fn move_snakes(&self, game: &mut Game, moves: &[Direction]) {
+        move_snakes_standard(game, moves)
+    }
+
+    /// Reduces the health of all snakes.
+    fn reduce_health(&self, game: &mut Game) {
+        reduce_health_standard(game)
+    }
+
+    /// Applies the additional damage of hazards.
+    fn damage_hazards(&self, game: &mut Game) {
+        damage_hazards_standard(game)
+    }
+
+    /// Lets snakes consume the food they have reached.
+    fn feed_snakes(&self, game: &mut Game) {
+        feed_snakes_standard(game)
+    }
+
+    /// Eliminates snakes that have starved, left the board or collided.
+    fn eliminate_snakes(&self, game: &mut Game) {
+        eliminate_snakes_standard(game)
+    }
+
+    /// Returns if the game has ended and who has won.
+    fn outcome(&self, game: &Game) -> Outcome {
+        outcome_standard(game)
+    }
+
+    /// Returns if snakes grow every turn, so that their tails never move.
+    fn snakes_grow(&self) -> bool {
+        false
+    }
+
+    /// Returns if the board wraps around at the edges.
+    ///
+    /// This configures the topology of the [crate::grid::Grid] of a game.
+    fn wrapped(&self) -> bool {
+        false
+    }
+
+    /// Updates the board between turns, like spawning food.
+    ///
+    /// This is random and thus only used by the simulator and not by the
+    /// tree searches.
+    fn update_board(&self, game: &mut Game, rng: &mut dyn RngCore) {
+        spawn_food_standard(game, rng)
+    }
+}
+
+/// The standard rules.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Standard;
+
+impl Ruleset for Standard {}
+
+/// Standard rules, but the board is shrinking with hazards.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Royale;
+
+impl Ruleset for Royale {
+    fn update_board(&self, game: &mut Game, rng: &mut dyn RngCore) {
+        spawn_food_standard(game, rng);
+        let shrink = game.settings.shrink_turns;
+        if shrink > 0 && game.turn.is_multiple_of(shrink) {
+            shrink_royale(game, rng);
+        }
+    }
+}
+
+/// There is no food, but the snakes grow every turn and never starve.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Constrictor;
+
+impl Ruleset for Constrictor {
+    fn feed_snakes(&self, game: &mut Game) {
+        for snake in &mut game.snakes {
+            if !snake.eliminated() {
+                snake.health = 100;
+                if snake.body[0] != snake.body[1] {
+                    snake.body.push_front(snake.body[0]);
+                }
+            }
+        }
+    }
+
+    fn snakes_grow(&self) -> bool {
+        true
+    }
+
+    fn update_board(&self, _game: &mut Game, _rng: &mut dyn RngCore) {}
+}
+
+/// Snakes leaving the board reappear on the opposite side.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wrapped;
+
+impl Ruleset for Wrapped {
+    fn wrapped(&self) -> bool {
+        true
+    }
+}
+
+/// A single snake tries to survive as long as possible.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Solo;
+
+impl Ruleset for Solo {
+    fn outcome(&self, game: &Game) -> Outcome {
+        if game.snakes.iter().any(Snake::alive) {
+            Outcome::None
+        } else {
+            Outcome::Match
+        }
+    }
+}
+
+pub fn move_snakes_standard(game: &mut Game, moves: &[Direction]) {
+    for (id, snake) in game.snakes.iter_mut().enumerate() {
+        if !snake.eliminated() {
+            let tail = snake.body.pop_front().unwrap();
+            if tail != snake.body[0] && !game.obstacles.contains(&tail) {
+                game.grid[tail].t = CellT::Free;
+            }
+            let head = game.grid.apply(snake.head(), moves[id]);
+            snake.body.push_back(head);
+        }
+    }
+}
+
+pub fn reduce_health_standard(game: &mut Game) {
+    for snake in &mut game.snakes {
+        if !snake.eliminated() {
+            snake.health = snake.health.saturating_sub(1);
+        }
+    }
+}
+
+/// Hazards do not damage snakes if they contain food.
+pub fn damage_hazards_standard(game: &mut Game) {
+    for snake in &mut game.snakes {
+        if !snake.eliminated() {
+            let head = snake.head();
+            if game.grid.has(head) && game.grid[head].hazard && game.grid[head].t != CellT::Food {
+                // The regular damage has already been applied
+                snake.health = snake.health.saturating_sub(game.settings.hazard_damage);
+                if snake.health == 0 {
+                    snake.elimination = Some(EliminationCause::Hazard);
+                }
+            }
+        }
+    }
+}
+
+/// Multiple snakes can consume the same food.
+pub fn feed_snakes_standard(game: &mut Game) {
+    for snake in &mut game.snakes {
+        if !snake.eliminated() {
+            let head = snake.head();
+            if game.grid.has(head) && game.grid[head].t == CellT::Food {
+                snake.health = 100;
+                snake.body.push_front(snake.body[0]);
+            }
+        }
+    }
+    for snake in &game.snakes {
+        if !snake.eliminated() && game.grid.has(snake.head()) {
+            let cell = &mut game.grid[snake.head()];
+            if cell.t == CellT::Food {
+                cell.t = CellT::Free;
+            }
+        }
+    }
+}
+
+/// Starvation and leaving the board are checked first.
+/// Then the collisions are evaluated simultaneously for all remaining snakes.
+///
+/// Like in the official rules, self collisions are checked before collisions
+/// with other bodies and head to heads are checked last.
+/// If a snake loses multiple head to heads, the longest opponent is reported.
+pub fn eliminate_snakes_standard(game: &mut Game) {
+    for snake in &mut game.snakes {
+        if !snake.eliminated() {
+            if snake.health == 0 {
+                snake.elimination = Some(EliminationCause::OutOfHealth);
+            } else if !game.grid.has(snake.head()) {
+                snake.elimination = Some(EliminationCause::OutOfBounds);
+            }
+        }
+    }
+    // These snakes are removed before checking for collisions
+    clear_eliminated_bodies(game, |c| !c.is_collision());
+
+    let blocking =
+        |s: &Snake| !s.body.is_empty() && s.elimination.is_none_or(EliminationCause::is_collision);
+    for i in 0..game.snakes.len() {
+        let snake = &game.snakes[i];
+        if snake.eliminated() {
+            continue;
+        }
+        let head = snake.head();
+
+        let cause = if game.grid[head].t == CellT::Owned {
+            if snake.body_collides(head) {
+                Some(EliminationCause::SelfCollision)
+            } else {
+                let collision = game
+                    .snakes
+                    .iter()
+                    .position(|s| blocking(s) && s.body_collides(head));
+                match collision {
+                    Some(j) => Some(EliminationCause::Collision(j as _)),
+                    // Owned by a snake that is not simulated
+                    None if !owned_by_snakes(game, head) => Some(EliminationCause::Obstacle),
+                    None => None,
+                }
+            }
+        } else {
+            // Lose against longer or equally long snakes
+            game.snakes
+                .iter()
+                .enumerate()
+                .filter(|&(j, s)| {
+                    j != i && blocking(s) && s.head() == head && snake.body.len() <= s.body.len()
+                })
+                .min_by_key(|(_, s)| std::cmp::Reverse(s.body.len()))
+                .map(|(j, _)| EliminationCause::HeadToHead(j as _))
+        };
+        game.snakes[i].elimination = cause;
+    }
+    clear_eliminated_bodies(game, EliminationCause::is_collision);
+}
+
+/// Returns if `p` belongs to the body of any snake without the new heads,
+/// including the ones that collided during this step.
+fn owned_by_snakes(game: &Game, p: Vec2D) -> bool {
+    game.snakes
+        .iter()
+        .any(|s| s.body.iter().rev().skip(1).any(|&b| b == p))
+}
+
+/// Frees the cells of snakes that have been eliminated during this step
+/// by one of the `causes`.
+///
+/// The head is not freed, as it might be outside the board or inside
+/// another snake. Neither are the obstacles.
+fn clear_eliminated_bodies(game: &mut Game, causes: impl Fn(EliminationCause) -> bool) {
+    for snake in &game.snakes {
+        if snake.elimination.is_some_and(&causes) {
+            for &p in snake.body.iter().rev().skip(1) {
+                if !game.obstacles.contains(&p) {
+                    game.grid[p].t = CellT::Free;
+                }
+            }
+        }
+    }
+}
+
+/// The game ends if at most one snake is alive.
+pub fn outcome_standard(game: &Game) -> Outcome {
+    let mut living_snakes = 0;
+    let mut survivor = 0;
+    for (i, snake) in game.snakes.iter().enumerate() {
+        if snake.alive() {
+            living_snakes += 1;
+            survivor = i as u8;
+        }
+    }
+    match living_snakes {
+        0 => Outcome::Match,
+        1 => Outcome::Winner(survivor),
+        _ => Outcome::None,
+    }
+}
+
+/// Tops up the food to the minimum or spawns a food with the given chance.
+/// Food is only spawned on free cells outside of hazards.
+pub fn spawn_food_standard(game: &mut Game, rng: &mut dyn RngCore) {
+    let food = game
+        .grid
+        .cells
+        .iter()
+        .filter(|c| c.t == CellT::Food)
+        .count();
+    let settings = game.settings;
+    let amount = if food < settings.minimum_food {
+        settings.minimum_food - food
+    } else if settings.food_spawn_chance > 0 && rng.gen_range(0..100) < settings.food_spawn_chance {
+        1
+    } else {
+        0
+    };
+
+    for cell in game
+        .grid
+        .cells
+        .iter_mut()
+        .filter(|c| c.t == CellT::Free && !c.hazard)
+        .choose_multiple(rng, amount)
+    {
+        cell.t = CellT::Food;
+    }
+}
+
+/// Adds hazards to a random side of the remaining safe area.
+/// The last row or column of the safe area is never filled.
+pub fn shrink_royale(game: &mut Game, rng: &mut dyn RngCore) {
+    let grid = &mut game.grid;
+
+    // Bounding box of the safe area
+    let (mut min, mut max) = (Vec2D::new(i16::MAX, i16::MAX), Vec2D::new(-1, -1));
+    for y in 0..grid.height as i16 {
+        for x in 0..grid.width as i16 {
+            if !grid[Vec2D::new(x, y)].hazard {
+                min = Vec2D::new(min.x.min(x), min.y.min(y));
+                max = Vec2D::new(max.x.max(x), max.y.max(y));
+            }
+        }
+    }
+    if max.x < 0 {
+        return;
+    }
+
+    let (width, height) = (grid.width as i16, grid.height as i16);
+    match rng.gen_range(0..4) {
+        0 if min.y < max.y => (0..width).for_each(|x| grid[Vec2D::new(x, min.y)].hazard = true),
+        1 if min.x < max.x => (0..height).for_each(|y| grid[Vec2D::new(min.x, y)].hazard = true),
+        2 if min.y < max.y => (0..width).for_each(|x| grid[Vec2D::new(x, max.y)].hazard = true),
+        3 if min.x < max.x => (0..height).for_each(|y| grid[Vec2D::new(max.x, y)].hazard = true),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::logging;
+
+    #[test]
+    fn constrictor() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            . . 0 . .
+            . . ^ . .
+            . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Constrictor);
+        game.snakes[0].health = 50;
+
+        game.step(&[Right]);
+        game.step(&[Down]);
+        info!("{game:?}");
+        assert_eq!(game.snakes[0].health, 100);
+        assert_eq!(game.snakes[0].body.len(), 5);
+
+        // The tail never moves
+        game.step(&[Left]);
+        assert!(!game.snake_is_alive(0));
+        assert_eq!(
+            game.snakes[0].elimination,
+            Some(EliminationCause::SelfCollision)
+        );
+    }
+
+    #[test]
+    fn wrapped() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            . . . . .
+            . . . . .
+            < < 0 . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Wrapped);
+        assert!(game.move_is_valid(0, Down));
+
+        game.step(&[Down]);
+        game.step(&[Left]);
+        game.step(&[Left]);
+        game.step(&[Left]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert_eq!(game.snakes[0].head(), Vec2D::new(4, 4));
+        assert!(game.grid[Vec2D::new(4, 4)].t == CellT::Owned);
+    }
+
+    #[test]
+    fn solo() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            . . 0 . .
+            . . ^ . .
+            . . ^ . ."#,
+        )
+        .unwrap();
+        assert_eq!(game.outcome(), Outcome::Winner(0));
+        game.set_mode(GameMode::Solo);
+        assert_eq!(game.outcome(), Outcome::None);
+
+        game.step(&[Up]);
+        game.step(&[Up]);
+        game.step(&[Up]);
+        assert_eq!(game.outcome(), Outcome::Match);
+    }
+
+    #[test]
+    fn update_board() {
+        use super::*;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            . . 0 . .
+            . . ^ . .
+            . . ^ . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Royale);
+        game.settings = crate::game::GameSettings {
+            food_spawn_chance: 0,
+            minimum_food: 2,
+            shrink_turns: 1,
+            ..Default::default()
+        };
+
+        game.rules().update_board(&mut game, &mut rng);
+        game.rules().update_board(&mut game, &mut rng);
+        info!("{game:?}");
+        let food = game
+            .grid
+            .cells
+            .iter()
+            .filter(|c| c.t == CellT::Food)
+            .count();
+        assert_eq!(food, 2);
+        let hazards = game.grid.cells.iter().filter(|c| c.hazard).count();
+        assert!(hazards >= 5);
+
+        // The board never shrinks entirely
+        for _ in 0..20 {
+            game.rules().update_board(&mut game, &mut rng);
+        }
+        let hazards = game.grid.cells.iter().filter(|c| c.hazard).count();
+        assert_eq!(hazards, 24);
+    }
+}
@@ -4,38 +4,46 @@ use rand::{seq::IteratorRandom, Rng};
 
 use crate::{
     env::v2,
-    game::{Game, Snake},
+    game::{Game, Snake, MAX_SNAKES},
     grid::CellT,
 };
 
 pub fn init_game<R: RngCore>(width: usize, height: usize, num_agents: usize, rng: &mut R) -> Game {
-    if width % 2 == 0 || height % 2 == 0 {
+    assert!(
+        num_agents <= MAX_SNAKES,
+        "Only up to {MAX_SNAKES} snakes are supported"
+    );
+    if width.is_multiple_of(2) || height.is_multiple_of(2) {
         warn!("If the dimension are even, the initial board configuration is unfair!");
     }
     if width != height {
         warn!("If width != height, the initial board configuration is unfair!");
     }
 
+    let corners = [
+        v2(1, 1),
+        v2((width - 2) as _, 1),
+        v2((width - 2) as _, (height - 2) as _),
+        v2(1, (height - 2) as _),
+    ];
+    let edges = [
+        v2((width / 2) as _, 1),
+        v2((width - 2) as _, (height / 2) as _),
+        v2((width / 2) as _, (height - 2) as _),
+        v2(1, (height / 2) as _),
+    ];
+
     // Either start in the corners or in the middle of the edges
-    let mut start_positions = if rng.gen() {
-        // Corners
-        [
-            v2(1, 1),
-            v2((width - 2) as _, 1),
-            v2((width - 2) as _, (height - 2) as _),
-            v2(1, (height - 2) as _),
-        ]
-    } else {
-        // Edges
-        [
-            v2((width / 2) as _, 1),
-            v2((width - 2) as _, (height / 2) as _),
-            v2((width / 2) as _, (height - 2) as _),
-            v2(1, (height / 2) as _),
-        ]
+    let mut start_positions = if rng.gen() { corners } else { edges }
+        .into_iter()
+        .choose_multiple(rng, num_agents);
+    // Larger games use both
+    if num_agents > start_positions.len() {
+        start_positions = corners
+            .into_iter()
+            .chain(edges)
+            .choose_multiple(rng, num_agents);
     }
-    .into_iter()
-    .choose_multiple(rng, num_agents);
 
     start_positions.shuffle(rng);
 
@@ -67,5 +75,6 @@ pub fn init_game<R: RngCore>(width: usize, height: usize, num_agents: usize, rng
         }
     }
 
+    game.rehash();
     game
 }
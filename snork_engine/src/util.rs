@@ -82,6 +82,9 @@ impl<T, const N: usize> FixedVec<T, N> {
     pub fn len(&self) -> usize {
         self.len
     }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
     pub const fn capacity(&self) -> usize {
         N
     }
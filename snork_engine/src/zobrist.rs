@@ -0,0 +1,97 @@
+//! # Zobrist Hashing
+//!
+//! Every feature of a game state (a body segment of a snake, its head and
+//! health, food, hazards, obstacles and the turn parity) has a pseudo random
+//! key.
+//! The hash of a game is the xor of the keys of all its features, which
+//! can be updated incrementally if a feature is added or removed.
+//!
+//! Instead of storing the keys in large tables, they are derived from the
+//! feature with the splitmix64 finalizer.
+
+use crate::env::{Direction, Vec2D};
+
+/// Number of health points that share the same key.
+pub const HEALTH_BUCKET: u8 = 10;
+
+const BODY: u64 = 1;
+const HEAD: u64 = 2;
+const HEALTH: u64 = 3;
+const FOOD: u64 = 4;
+const HAZARD: u64 = 5;
+const TURN: u64 = 6;
+const ACTION: u64 = 7;
+const OBSTACLE: u64 = 8;
+
+#[inline]
+const fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[inline]
+const fn key(kind: u64, id: u8, value: u64) -> u64 {
+    splitmix64(kind << 56 | (id as u64) << 48 | value)
+}
+
+#[inline]
+const fn pos(p: Vec2D) -> u64 {
+    (p.x as u16 as u64) << 16 | p.y as u16 as u64
+}
+
+/// Key of a body segment (including the head) of the snake `id`.
+#[inline]
+pub const fn body(id: u8, p: Vec2D) -> u64 {
+    key(BODY, id, pos(p))
+}
+
+/// Key of the head of the snake `id`.
+#[inline]
+pub const fn head(id: u8, p: Vec2D) -> u64 {
+    key(HEAD, id, pos(p))
+}
+
+/// Key of the health of the snake `id`, which is grouped into buckets.
+#[inline]
+pub const fn health(id: u8, health: u8) -> u64 {
+    key(HEALTH, id, (health / HEALTH_BUCKET) as u64)
+}
+
+/// Key of a food cell.
+#[inline]
+pub const fn food(p: Vec2D) -> u64 {
+    key(FOOD, 0, pos(p))
+}
+
+/// Key of a hazard cell.
+#[inline]
+pub const fn hazard(p: Vec2D) -> u64 {
+    key(HAZARD, 0, pos(p))
+}
+
+/// Key of an obstacle cell, which does not belong to a simulated snake.
+#[inline]
+pub const fn obstacle(p: Vec2D) -> u64 {
+    key(OBSTACLE, 0, pos(p))
+}
+
+/// Key of the turn, which only depends on its parity.
+#[inline]
+pub const fn turn(turn: usize) -> u64 {
+    if turn % 2 == 1 {
+        key(TURN, 0, 0)
+    } else {
+        0
+    }
+}
+
+/// Key of the move a snake has chosen within a turn.
+///
+/// This is used to distinguish the nodes of the tree searches, where only
+/// some snakes have chosen their moves.
+#[inline]
+pub const fn action(id: u8, dir: Direction) -> u64 {
+    key(ACTION, id, dir as u64)
+}
@@ -1,6 +1,11 @@
+use crate::bitboard::{BitSet, BitboardGame, WideBitboardGame};
+use crate::bitflood::BitFloodFill;
 use crate::floodfill::FloodFill;
 use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::nn::PolicyModel;
+use crate::relevance::OpponentPruning;
+use crate::search::{self, Algorithm, Heuristic, Policy};
+use crate::trap::TrapReport;
 
 /// The new floodfill agent for royale games
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -12,6 +17,17 @@ pub struct FloodHeuristic {
     space_adv: f64,
     size_adv: f64,
     size_adv_decay: f64,
+    /// Trapped enemies minus being trapped, see [TrapReport::features]
+    trap: f64,
+    /// Enemies we can trap minus enemies that can trap us
+    trap_threat: f64,
+    /// Multi-player search algorithm
+    pub search: Algorithm,
+    /// Opponents that are searched in games with many snakes
+    pub pruning: OpponentPruning,
+    /// Policy that orders the moves of the search, see [Heuristic::policy]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyModel>,
 }
 
 impl Default for FloodHeuristic {
@@ -23,60 +39,158 @@ impl Default for FloodHeuristic {
             space_adv: 0.108,
             size_adv: 7.049,
             size_adv_decay: 0.041,
+            trap: 0.0,
+            trap_threat: 0.0,
+            search: Algorithm::MaxN,
+            pruning: OpponentPruning::default(),
+            policy: None,
         }
     }
 }
 
-impl Heuristic for FloodHeuristic {
-    fn eval(&self, game: &Game) -> f64 {
-        if game.snake_is_alive(0) {
-            let own_len = game.snakes[0].body.len() as f64;
-            let area = (game.grid.width * game.grid.height) as f64;
+impl FloodHeuristic {
+    /// Combines the results of the floodfill into the final evaluation.
+    ///
+    /// `longest_enemy` contains the id and length of the longest living enemy
+    /// and `count_health` returns the health sum of the cells owned by a snake.
+    fn score(
+        &self,
+        turn: usize,
+        area: f64,
+        (own_len, own_health): (usize, u8),
+        longest_enemy: Option<(u8, usize)>,
+        food_distances: &[u16],
+        count_health: impl Fn(u8) -> usize,
+    ) -> f64 {
+        let own_len = own_len as f64;
+
+        // Health is more important if we have not much
+        let health = (own_health as f64 / 100.0).sqrt();
+
+        // Space advantage becomes increasingly better when higher
+        let space = count_health(0) as f64;
+
+        let (size_adv, space_adv) = if let Some((i, enemy_len)) = longest_enemy {
+            // Distance to the nearest four food cells
+            let food_distance: f64 = food_distances
+                .iter()
+                .map(|&d| (area - d as f64) / area)
+                .sum();
+            let enemy_len = enemy_len as f64;
+            // Sqrt because if we are larger we do not have to as grow much anymore.
+            let size_adv = ((own_len + food_distance * self.food_distance) / enemy_len).sqrt();
 
-            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-            let food_distances = flood_fill.flood_snakes(&game.grid, &game.snakes);
+            let enemy_space = count_health(i) as f64;
+            let space_adv = if space > 0.0 {
+                // x^3 so that the effect is stronger when the value is higher.
+                (space / (enemy_space + space)).powi(3)
+            } else {
+                0.0
+            };
+            (size_adv, space_adv)
+        } else {
+            (0.0, 0.0)
+        };
 
-            // Health is more important if we have not much
-            let health = (game.snakes[0].health as f64 / 100.0).sqrt();
+        let space = (space / (area * 100.0)).sqrt();
 
-            // Space advantage becomes increasingly better when higher
-            let space = flood_fill.count_health(0) as f64;
+        self.health * health
+            + self.space_adv * space_adv
+            + self.space * space
+            + self.size_adv * size_adv * (-(turn as f64) * self.size_adv_decay).exp2()
+    }
 
-            let (size_adv, space_adv) = if let Some((i, longest_enemy)) = game
+    /// The trap detection is skipped if its weights are zero.
+    fn traps_enabled(&self) -> bool {
+        self.trap != 0.0 || self.trap_threat != 0.0
+    }
+
+    /// Detects the traps of the floodfill, see [TrapReport::features].
+    fn trap_score(&self, flood_fill: &FloodFill, game: &Game) -> f64 {
+        if self.traps_enabled() {
+            let (traps, trap_threats) = TrapReport::new(flood_fill, game).features(0);
+            self.trap * traps + self.trap_threat * trap_threats
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Heuristic for FloodHeuristic {
+    fn eval(&self, game: &Game) -> f64 {
+        if game.snake_is_alive(0) {
+            let longest_enemy = game
                 .snakes
                 .iter()
                 .enumerate()
                 .skip(1)
                 .filter(|(_, s)| s.alive())
                 .max_by_key(|(_, s)| s.body.len())
-            {
-                // Distance to the nearest four food cells
-                let food_distance: f64 = food_distances
-                    .iter()
-                    .map(|&d| (area - d as f64) / area)
-                    .sum();
-                let enemy_len = longest_enemy.body.len() as f64;
-                // Sqrt because if we are larger we do not have to as grow much anymore.
-                let size_adv = ((own_len + food_distance * self.food_distance) / enemy_len).sqrt();
-
-                let enemy_space = flood_fill.count_health(i as _) as f64;
-                let space_adv = if space > 0.0 {
-                    // x^3 so that the effect is stronger when the value is higher.
-                    (space / (enemy_space + space)).powi(3)
-                } else {
-                    0.0
-                };
-                (size_adv, space_adv)
-            } else {
-                (0.0, 0.0)
-            };
+                .map(|(i, s)| (i as u8, s.body.len()));
 
-            let space = (space / (area * 100.0)).sqrt();
+            FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+                let food_distances = flood_fill.flood_snakes(game);
+                self.score(
+                    game.turn,
+                    (game.grid.width * game.grid.height) as f64,
+                    (game.snakes[0].body.len(), game.snakes[0].health),
+                    longest_enemy,
+                    &food_distances,
+                    |i| flood_fill.count_health(i),
+                ) + self.trap_score(flood_fill, game)
+            })
+        } else {
+            search::LOSS
+        }
+    }
+
+    fn eval_bitboard(&self, game: &BitboardGame) -> f64 {
+        self.eval_bits(game)
+    }
+
+    fn eval_wide_bitboard(&self, game: &WideBitboardGame) -> f64 {
+        self.eval_bits(game)
+    }
+
+    fn bitboard(&self) -> bool {
+        // The traps and policy are only computed on the game
+        !self.traps_enabled() && self.policy.is_none()
+    }
+
+    fn policy(&self) -> Option<&dyn Policy> {
+        self.policy.as_ref().map(|p| p as _)
+    }
+}
+
+impl FloodHeuristic {
+    /// Evaluates bitboards of any size with the [BitFloodFill].
+    fn eval_bits<B: BitSet>(&self, game: &BitboardGame<B>) -> f64 {
+        // The traps are only detected on the full floodfill
+        if self.traps_enabled() {
+            return self.eval(&game.to_game());
+        }
+
+        if game.snake_is_alive(0) {
+            let mut flood_fill = BitFloodFill::new();
+            let food_distances = flood_fill.flood_snakes(game);
+
+            let snakes = game.snakes();
+            let longest_enemy = snakes
+                .iter()
+                .enumerate()
+                .skip(1)
+                .filter(|(_, s)| s.alive())
+                .max_by_key(|(_, s)| s.len)
+                .map(|(i, s)| (i as u8, s.len as usize));
 
-            self.health * health
-                + self.space_adv * space_adv
-                + self.space * space
-                + self.size_adv * size_adv * (-(game.turn as f64) * self.size_adv_decay).exp2()
+            self.score(
+                game.turn,
+                (game.width * game.height) as f64,
+                (snakes[0].len as usize, snakes[0].health),
+                longest_enemy,
+                &food_distances,
+                |i| flood_fill.count_health(i),
+            )
         } else {
             search::LOSS
         }
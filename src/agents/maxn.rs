@@ -4,7 +4,7 @@ use std::time::Instant;
 
 use crate::env::*;
 use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::search::{self, Algorithm, Heuristic, MoveOrdering, SearchBudget, TranspositionTable};
 
 use crate::util::argmax;
 
@@ -14,19 +14,45 @@ use tokio::time;
 
 const FAST_TIMEOUT: u64 = 150;
 const MAX_DEPTH: usize = 16;
-
-pub async fn step(heuristic: Arc<dyn Heuristic>, timeout: u64, game: &Game) -> MoveResponse {
+/// Number of entries of the transposition table.
+const TT_SIZE: usize = 1 << 16;
+
+/// Performs an iterative deepening search until the timeout.
+///
+/// The searches are aborted as soon as the timeout is reached.
+/// The transposition table `tt` of the previous turn can be reused, as the
+/// positions of its subtrees are found by their hashes.
+/// It is returned, so that it can be reused by the next turn.
+pub async fn step(
+    heuristic: Arc<dyn Heuristic>,
+    algorithm: Algorithm,
+    timeout: u64,
+    game: &Game,
+    tt: Option<Arc<TranspositionTable>>,
+) -> (MoveResponse, Option<Arc<TranspositionTable>>) {
     if timeout <= FAST_TIMEOUT {
-        return step_fast(&*heuristic, game);
+        return (step_fast(&*heuristic, game), tt);
     }
 
+    let tt = tt.unwrap_or_else(|| Arc::new(TranspositionTable::new(TT_SIZE)));
+    let budget = Arc::new(SearchBudget::from_timeout(timeout));
     let (sender, mut receiver) = mpsc::channel(MAX_DEPTH);
 
     let _ = time::timeout(
         Duration::from_millis(timeout),
-        iterative_tree_search(heuristic, game, sender),
+        iterative_tree_search(
+            heuristic,
+            algorithm,
+            game,
+            tt.clone(),
+            budget.clone(),
+            sender,
+        ),
     )
     .await;
+    // Cancel the searches that are still running
+    budget.stop();
+    info!(">>> nodes {}", budget.nodes());
 
     let mut result = None;
     while let Some(dir) = receiver.recv().await {
@@ -34,16 +60,17 @@ pub async fn step(heuristic: Arc<dyn Heuristic>, timeout: u64, game: &Game) -> M
     }
 
     if let Some(dir) = result {
-        return MoveResponse::new(Direction::from(dir as u8));
+        return (MoveResponse::new(Direction::from(dir as u8)), Some(tt));
     }
 
     info!(">>> none");
-    MoveResponse::new(game.valid_moves(0).next().unwrap_or(Direction::Up))
+    let dir = game.valid_moves(0).next().unwrap_or(Direction::Up);
+    (MoveResponse::new(dir), Some(tt))
 }
 
 pub fn step_fast(heuristic: &dyn Heuristic, game: &Game) -> MoveResponse {
     let start = Instant::now();
-    let result = search::max_n(game, 1, heuristic);
+    let result = search::max_n(game, 1, heuristic, None, None);
 
     info!(">>> max_n 1 {:?}ms {result:?}", start.elapsed().as_millis());
 
@@ -59,13 +86,34 @@ pub fn step_fast(heuristic: &dyn Heuristic, game: &Game) -> MoveResponse {
 
 async fn iterative_tree_search(
     heuristic: Arc<dyn Heuristic>,
+    algorithm: Algorithm,
     game: &Game,
+    // Shared by all iterations
+    tt: Arc<TranspositionTable>,
+    budget: Arc<SearchBudget>,
     sender: mpsc::Sender<Direction>,
 ) {
+    // Searches the principal variation of the previous iteration first
+    let mut ordering = MoveOrdering::new();
+
     // Iterative deepening
     for depth in 1..MAX_DEPTH {
         let heuristic = heuristic.clone();
-        let (dir, value) = tree_search(heuristic, game, depth).await;
+        let (dir, value) = tree_search(
+            heuristic,
+            algorithm,
+            game,
+            depth,
+            Some(tt.clone()),
+            Some(budget.clone()),
+            &mut ordering,
+        )
+        .await;
+
+        // Discard the incomplete result
+        if budget.stopped() {
+            break;
+        }
 
         // Stop and fallback to random possible move
         if value <= search::LOSS {
@@ -82,21 +130,95 @@ async fn iterative_tree_search(
 }
 
 /// Performes a tree search and returns the maximized heuristic and move.
+///
+/// The move `ordering` should be reused for the iterations of iterative
+/// deepening, see [Algorithm::search].
 pub async fn tree_search(
     heuristic: Arc<dyn Heuristic>,
+    algorithm: Algorithm,
     game: &Game,
     depth: usize,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+    ordering: &mut MoveOrdering,
 ) -> (Direction, f64) {
     let start = Instant::now();
 
-    let result = search::async_max_n(game, depth, heuristic).await;
+    let result = algorithm
+        .search(game, depth, heuristic, tt, budget, ordering)
+        .await;
 
     info!(
-        ">>> max_n {depth} {:?}ms {result:.3?}",
+        ">>> {algorithm:?} {depth} {:?}ms {result:.3?} pv={:?}",
         start.elapsed().as_millis(),
+        ordering.pv(),
     );
 
     argmax(result.iter().copied())
         .map(|d| (Direction::from(d as u8), result[d]))
         .unwrap()
 }
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn principal_variation() {
+        use super::*;
+        use crate::agents::FloodHeuristic;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let heuristic: Arc<dyn Heuristic> = Arc::new(FloodHeuristic::default());
+        let mut rng = SmallRng::seed_from_u64(4);
+        for snakes in [2, 3] {
+            let game = init_game(11, 11, snakes, &mut rng);
+            info!("{game:?}");
+
+            for algorithm in [Algorithm::Paranoid, Algorithm::BestReply] {
+                // Iterative deepening with a shared ordering like the agent
+                let mut ordering = MoveOrdering::new();
+                let (mut reused, mut fresh) = (0, 0);
+                for depth in 1..=3 {
+                    let budget = Arc::new(SearchBudget::unlimited());
+                    let (dir, value) = tree_search(
+                        heuristic.clone(),
+                        algorithm,
+                        &game,
+                        depth,
+                        None,
+                        Some(budget.clone()),
+                        &mut ordering,
+                    )
+                    .await;
+                    reused += budget.nodes();
+
+                    let budget = Arc::new(SearchBudget::unlimited());
+                    let expected = tree_search(
+                        heuristic.clone(),
+                        algorithm,
+                        &game,
+                        depth,
+                        None,
+                        Some(budget.clone()),
+                        &mut MoveOrdering::new(),
+                    )
+                    .await;
+                    fresh += budget.nodes();
+                    info!("{algorithm:?} depth={depth} pv={:?}", ordering.pv());
+                    assert_eq!(value, expected.1);
+
+                    // The principal variation starts with the best move
+                    assert_eq!(ordering.pv().first(), Some(&dir));
+                }
+                info!("{algorithm:?} nodes: reused={reused} fresh={fresh}");
+                // Starting with the previous principal variation causes more cutoffs
+                assert!(reused < fresh);
+            }
+        }
+    }
+}
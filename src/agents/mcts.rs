@@ -2,9 +2,70 @@ use std::sync::Arc;
 
 use crate::env::MoveResponse;
 use crate::game::Game;
-use crate::search::{mcts, Heuristic};
+use crate::relevance::OpponentPruning;
+use crate::search::{mcts, Mcts, MctsConfig, Parallelism, SearchBudget};
 
-pub async fn step(heuristic: Arc<dyn Heuristic>, timeout: u64, game: &Game) -> MoveResponse {
-    let dir = mcts(heuristic, timeout, game).await;
-    MoveResponse::new(dir)
+use super::FloodHeuristic;
+
+/// Simultaneous-move Monte Carlo tree search
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonteCarloAgent {
+    /// Exploration constant of PUCT
+    exploration: f64,
+    /// Number of turns after which the rollouts are evaluated
+    horizon: usize,
+    /// Number of search threads
+    threads: usize,
+    /// Whether the threads search independent trees or share one tree
+    parallelism: Parallelism,
+    /// Opponents that are searched in games with many snakes
+    pub pruning: OpponentPruning,
+    /// Evaluates the end of the rollouts, its policy provides the priors
+    heuristic: FloodHeuristic,
+}
+
+impl Default for MonteCarloAgent {
+    fn default() -> Self {
+        let config = MctsConfig::default();
+        Self {
+            exploration: config.exploration,
+            horizon: config.horizon,
+            threads: config.threads,
+            parallelism: config.parallelism,
+            pruning: OpponentPruning::default(),
+            heuristic: FloodHeuristic::default(),
+        }
+    }
+}
+
+impl MonteCarloAgent {
+    /// Searches for the next move, continuing with the `trees` of the
+    /// previous turn if possible.
+    ///
+    /// The `snakes` are the request ids of the snakes of the `game`.
+    pub async fn step(
+        &self,
+        timeout: u64,
+        game: &Game,
+        snakes: &[String],
+        trees: Vec<Mcts>,
+    ) -> (MoveResponse, Vec<Mcts>) {
+        let config = MctsConfig {
+            exploration: self.exploration,
+            horizon: self.horizon,
+            threads: self.threads,
+            parallelism: self.parallelism,
+        };
+        let (dir, trees) = mcts(
+            Arc::new(self.heuristic.clone()),
+            config,
+            Arc::new(SearchBudget::from_timeout(timeout)),
+            game,
+            snakes,
+            trees,
+        )
+        .await;
+        (MoveResponse::new(dir), trees)
+    }
 }
@@ -8,6 +8,7 @@ use crate::env::*;
 use crate::floodfill::{FCell, FloodFill};
 use crate::game::{Game, Snake};
 use crate::grid::CellT;
+use crate::pathfinding::SpaceTimePathfinder;
 use crate::search::{self, Heuristic};
 use crate::util::{argmax, OrdPair};
 
@@ -39,9 +40,10 @@ struct MobilityHeuristic;
 impl Heuristic for MobilityHeuristic {
     fn eval(&self, game: &Game) -> f64 {
         if game.snake_is_alive(0) {
-            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.width);
-            flood_fill.flood_snakes(&game.grid, &game.snakes);
-            flood_fill.count_space(0) as f64
+            FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0) as f64
+            })
         } else {
             0.0
         }
@@ -62,14 +64,14 @@ impl MobilityAgent {
         // Heuristic for preferring high movement
         let first_move_costs = space_after_move.map(|x| (1.0 - x / area) * self.first_move_cost);
 
-        // Avoid longer enemy heads
-        let mut grid = game.grid.clone();
+        // Avoid the next moves of longer enemy heads
+        let mut dangerous = Vec::new();
         for snake in &game.snakes[1..] {
-            if snake.body.len() >= you.body.len() {
+            if snake.alive() && snake.body.len() >= you.body.len() {
                 for d in Direction::all() {
-                    let p = snake.head().apply(d);
-                    if grid.has(p) {
-                        grid[p].t = CellT::Owned;
+                    let p = game.grid.apply(snake.head(), d);
+                    if game.grid.has(p) {
+                        dangerous.push(p);
                     }
                 }
             }
@@ -85,16 +87,27 @@ impl MobilityAgent {
             }
         }
 
+        // Paths to all food in a single search, considering vacating tails
+        let grid = &game.grid;
+        let mut pathfinder = SpaceTimePathfinder::new(grid.width, grid.height);
+        pathfinder.search(
+            game,
+            0,
+            2 * (grid.width + grid.height),
+            &first_move_costs,
+            |p, moves| moves == 1 && dangerous.contains(&p),
+        );
+
         let mut food_dirs = BinaryHeap::new();
         for p in food {
-            if let Some(path) = grid.a_star(you.head(), p, &first_move_costs) {
+            if let Some(path) = pathfinder.path(p) {
                 if path.len() >= 2 {
                     let costs = path.len()
                         + match flood_fill[p] {
                             FCell::Owned { id: 0, .. } => 0,
                             _ => 5,
                         };
-                    food_dirs.push(OrdPair(Reverse(costs), Direction::from(path[1] - path[0])));
+                    food_dirs.push(OrdPair(Reverse(costs), grid.direction(path[0], path[1])));
                 }
             }
         }
@@ -113,14 +126,14 @@ impl MobilityAgent {
 
         // Flood fill heuristics
         let start = Instant::now();
-        let space_after_move = search::max_n(game, 1, &MobilityHeuristic);
+        let space_after_move = search::max_n(game, 1, &MobilityHeuristic, None, None);
         info!(
             "max_n {:?}ms {space_after_move:?}",
             start.elapsed().as_millis()
         );
 
         let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-        flood_fill.flood_snakes(&game.grid, &game.snakes);
+        flood_fill.flood_snakes(game);
 
         // Find Food
         if you.body.len() < self.min_len || you.health < self.health_threshold {
@@ -1,5 +1,5 @@
+use std::fmt;
 use std::str::FromStr;
-use std::string::ToString;
 use std::sync::Arc;
 
 mod original;
@@ -15,8 +15,14 @@ mod solo;
 pub use solo::*;
 mod mcts;
 pub use mcts::*;
+mod neural;
+pub use neural::*;
+mod session;
+pub use session::*;
 
 use crate::game::Game;
+use crate::relevance::OpponentPruning;
+use crate::search::{Algorithm, SearchBudget};
 
 use super::env::{GameRequest, MoveResponse};
 
@@ -28,7 +34,8 @@ pub enum Agent {
     Mobility(MobilityAgent),
     Tree(TreeHeuristic),
     Flood(FloodHeuristic),
-    MonteCarlo(FloodHeuristic),
+    MonteCarlo(MonteCarloAgent),
+    Neural(NeuralHeuristic),
     Solo(SoloHeuristic),
     Random(RandomAgent),
 }
@@ -41,25 +48,87 @@ impl Default for Agent {
 
 impl Agent {
     pub async fn step(&self, request: &GameRequest, latency: u64) -> MoveResponse {
-        let game = Game::from_request(request);
-        let timeout = request.game.timeout.saturating_sub(latency);
+        let game = Game::from_request_with(request, self.pruning());
+        let timeout = SearchBudget::allocate(request.game.timeout, latency, &game);
 
         self.step_internal(timeout, &game).await
     }
 
-    pub async fn step_internal(&self, timeout: u64, game: &Game) -> MoveResponse {
-        if game.grid.width > MAX_BOARD_SIZE || game.grid.height > MAX_BOARD_SIZE {
-            return RandomAgent.step(game).await;
+    /// Like [Agent::step], but continues with the search state of the
+    /// previous turn of this game.
+    pub async fn step_session(
+        &self,
+        request: &GameRequest,
+        latency: u64,
+        sessions: &Sessions,
+    ) -> MoveResponse {
+        let (game, snakes) = Game::from_request_ids(request, self.pruning());
+        let timeout = SearchBudget::allocate(request.game.timeout, latency, &game);
+
+        let state = sessions.take(request);
+        let (response, state) = self.step_state(timeout, &game, &snakes, state).await;
+        if let Some(state) = state {
+            sessions.insert(request, state);
         }
+        response
+    }
 
+    /// Opponents that are kept when the game is loaded from a request.
+    pub fn pruning(&self) -> OpponentPruning {
         match self {
-            Agent::Mobility(agent) => agent.step(game).await,
-            Agent::Tree(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
-            Agent::Flood(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
-            Agent::MonteCarlo(agent) => mcts::step(Arc::new(agent.clone()), timeout, game).await,
-            Agent::Solo(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
-            Agent::Random(agent) => agent.step(game).await,
+            Agent::Tree(agent) => agent.pruning,
+            Agent::Flood(agent) => agent.pruning,
+            Agent::Neural(agent) => agent.pruning,
+            Agent::MonteCarlo(agent) => agent.pruning,
+            _ => OpponentPruning::default(),
+        }
+    }
+
+    pub async fn step_internal(&self, timeout: u64, game: &Game) -> MoveResponse {
+        self.step_state(timeout, game, &[], None).await.0
+    }
+
+    /// Performs the step and returns the search state for the next turn.
+    ///
+    /// The `snakes` are the request ids of the snakes of the `game`.
+    async fn step_state(
+        &self,
+        timeout: u64,
+        game: &Game,
+        snakes: &[String],
+        state: Option<SearchState>,
+    ) -> (MoveResponse, Option<SearchState>) {
+        if game.grid.width > MAX_BOARD_SIZE || game.grid.height > MAX_BOARD_SIZE {
+            return (RandomAgent.step(game).await, None);
         }
+
+        let (tt, trees) = match state {
+            Some(SearchState::Tree(tt)) => (Some(tt), Vec::new()),
+            Some(SearchState::MonteCarlo(trees)) => (None, trees),
+            None => (None, Vec::new()),
+        };
+        let (response, tt) = match self {
+            Agent::Tree(agent) => {
+                maxn::step(Arc::new(agent.clone()), agent.search, timeout, game, tt).await
+            }
+            Agent::Flood(agent) => {
+                maxn::step(Arc::new(agent.clone()), agent.search, timeout, game, tt).await
+            }
+            Agent::Neural(agent) => {
+                maxn::step(Arc::new(agent.clone()), agent.search, timeout, game, tt).await
+            }
+            Agent::Solo(agent) => {
+                maxn::step(Arc::new(agent.clone()), Algorithm::MaxN, timeout, game, tt).await
+            }
+            Agent::MonteCarlo(agent) => {
+                let (response, trees) = agent.step(timeout, game, snakes, trees).await;
+                let state = (!trees.is_empty()).then_some(SearchState::MonteCarlo(trees));
+                return (response, state);
+            }
+            Agent::Mobility(agent) => return (agent.step(game).await, None),
+            Agent::Random(agent) => return (agent.step(game).await, None),
+        };
+        (response, tt.map(SearchState::Tree))
     }
 }
 
@@ -71,8 +140,8 @@ impl FromStr for Agent {
     }
 }
 
-impl ToString for Agent {
-    fn to_string(&self) -> String {
-        serde_json::to_string(self).unwrap_or_default()
+impl fmt::Display for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap_or_default())
     }
 }
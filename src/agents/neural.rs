@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::game::Game;
+use crate::nn::{PolicyModel, ValueNetwork};
+use crate::relevance::OpponentPruning;
+use crate::search::{self, Algorithm, Heuristic, Policy};
+
+/// Evaluates the game with a value network, see [crate::nn]
+///
+/// The weights are loaded when the config is parsed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "NeuralConfig", into = "NeuralConfig")]
+pub struct NeuralHeuristic {
+    weights: PathBuf,
+    /// Multi-player search algorithm
+    pub search: Algorithm,
+    /// Opponents that are searched in games with many snakes
+    pub pruning: OpponentPruning,
+    /// Policy that orders the moves of the search
+    pub policy: Option<PolicyModel>,
+    network: Arc<ValueNetwork>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NeuralConfig {
+    /// Path to the JSON weights
+    weights: PathBuf,
+    #[serde(default)]
+    search: Algorithm,
+    #[serde(default)]
+    pruning: OpponentPruning,
+    /// Path to the JSON weights of the policy network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    policy: Option<PolicyModel>,
+}
+
+impl NeuralHeuristic {
+    #[must_use]
+    pub fn new(network: ValueNetwork, search: Algorithm) -> Self {
+        Self {
+            weights: PathBuf::new(),
+            search,
+            pruning: OpponentPruning::default(),
+            policy: None,
+            network: Arc::new(network),
+        }
+    }
+}
+
+impl TryFrom<NeuralConfig> for NeuralHeuristic {
+    type Error = String;
+
+    fn try_from(config: NeuralConfig) -> Result<Self, Self::Error> {
+        let network = ValueNetwork::load(&config.weights)
+            .map_err(|e| format!("{}: {e}", config.weights.display()))?;
+        Ok(Self {
+            weights: config.weights,
+            search: config.search,
+            pruning: config.pruning,
+            policy: config.policy,
+            network: Arc::new(network),
+        })
+    }
+}
+
+impl From<NeuralHeuristic> for NeuralConfig {
+    fn from(heuristic: NeuralHeuristic) -> Self {
+        Self {
+            weights: heuristic.weights,
+            search: heuristic.search,
+            pruning: heuristic.pruning,
+            policy: heuristic.policy,
+        }
+    }
+}
+
+impl Heuristic for NeuralHeuristic {
+    fn eval(&self, game: &Game) -> f64 {
+        if game.snake_is_alive(0) {
+            self.network.eval(game) as f64
+        } else {
+            search::LOSS
+        }
+    }
+
+    fn policy(&self) -> Option<&dyn Policy> {
+        self.policy.as_ref().map(|p| p as _)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[tokio::test]
+    async fn neural_agent() {
+        use super::*;
+        use crate::agents::Agent;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(2);
+        let network = ValueNetwork::random(&[8], &[8], &mut rng);
+        let path = std::env::temp_dir().join("snork_neural_agent.json");
+        network.save(&path).unwrap();
+
+        let config = format!(r#"{{"Neural":{{"weights":{:?}}}}}"#, path);
+        let agent: Agent = config.parse().unwrap();
+        info!("{agent}");
+        assert_eq!(
+            agent.to_string().parse::<Agent>().unwrap().to_string(),
+            agent.to_string()
+        );
+
+        let game = Game::parse(
+            r#"
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . o . .
+            . ^ . . . 1 .
+            . . . . . ^ .
+            . . . . . ^ .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        let response = agent.step_internal(100, &game).await;
+        info!("{response:?}");
+
+        // Missing weights are reported when the config is parsed
+        std::fs::remove_file(&path).unwrap();
+        assert!(config.parse::<Agent>().is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use crate::env::*;
 use crate::floodfill::FloodFill;
 use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::nn::PolicyModel;
+use crate::relevance::OpponentPruning;
+use crate::search::{self, Algorithm, Heuristic, Policy};
+use crate::trap::TrapReport;
 
 /// Configuration of the tree search heuristic.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -17,6 +20,19 @@ pub struct TreeHeuristic {
     food_ownership_decay: f64,
     centrality: f64,
     centrality_decay: f64,
+    /// Trapped enemies minus being trapped, see [TrapReport::features]
+    trap: f64,
+    trap_decay: f64,
+    /// Enemies we can trap minus enemies that can trap us
+    trap_threat: f64,
+    trap_threat_decay: f64,
+    /// Multi-player search algorithm
+    pub search: Algorithm,
+    /// Opponents that are searched in games with many snakes
+    pub pruning: OpponentPruning,
+    /// Policy that orders the moves of the search, see [Heuristic::policy]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyModel>,
 }
 
 impl Default for TreeHeuristic {
@@ -32,6 +48,13 @@ impl Default for TreeHeuristic {
             food_ownership_decay: 0.0,
             centrality: 0.1,
             centrality_decay: 0.0,
+            trap: 0.0,
+            trap_decay: 0.0,
+            trap_threat: 0.0,
+            trap_threat_decay: 0.0,
+            search: Algorithm::MaxN,
+            pruning: OpponentPruning::default(),
+            policy: None,
         }
     }
 }
@@ -43,9 +66,18 @@ impl Heuristic for TreeHeuristic {
             return search::LOSS;
         }
 
-        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-        let food_distances = flood_fill.flood_snakes(&game.grid, &game.snakes);
-        let space = flood_fill.count_space(0);
+        // The trap detection is skipped if its weights are zero
+        let traps_enabled = self.trap != 0.0 || self.trap_threat != 0.0;
+        let (food_distances, space, (traps, trap_threats)) =
+            FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+                let food_distances = flood_fill.flood_snakes(game);
+                let traps = if traps_enabled {
+                    TrapReport::new(flood_fill, game).features(0)
+                } else {
+                    (0.0, 0.0)
+                };
+                (food_distances, flood_fill.count_space(0), traps)
+            });
         let mobility = space as f64 / (game.grid.width * game.grid.height) as f64;
 
         let health = game.snakes[0].health as f64 / 100.0;
@@ -65,9 +97,10 @@ impl Heuristic for TreeHeuristic {
 
         // Centrality
         let centrality = 1.0
-            - (game.snakes[0].head()
-                - Vec2D::new(game.grid.width as i16 / 2, game.grid.height as i16 / 2))
-            .manhattan() as f64
+            - game.grid.distance(
+                game.snakes[0].head(),
+                Vec2D::new(game.grid.width as i16 / 2, game.grid.height as i16 / 2),
+            ) as f64
                 / game.grid.width as f64;
 
         mobility * self.mobility * (-(game.turn as f64) * self.mobility_decay).exp()
@@ -79,5 +112,11 @@ impl Heuristic for TreeHeuristic {
                 * self.food_ownership
                 * (-(game.turn as f64) * self.food_ownership_decay).exp()
             + centrality * self.centrality * (-(game.turn as f64) * self.centrality_decay).exp()
+            + traps * self.trap * (-(game.turn as f64) * self.trap_decay).exp()
+            + trap_threats * self.trap_threat * (-(game.turn as f64) * self.trap_threat_decay).exp()
+    }
+
+    fn policy(&self) -> Option<&dyn Policy> {
+        self.policy.as_ref().map(|p| p as _)
     }
 }
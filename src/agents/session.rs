@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::env::GameRequest;
+use crate::search::{Mcts, TranspositionTable};
+
+/// Search state of a game, which is reused by the next turn.
+#[derive(Debug)]
+pub enum SearchState {
+    /// Transposition table of the iterative deepening searches
+    Tree(Arc<TranspositionTable>),
+    /// Trees of the Monte Carlo search (one per thread for root parallelism)
+    MonteCarlo(Vec<Mcts>),
+}
+
+/// Search states of the running games.
+///
+/// They are keyed by the game id and the id of our snake (in case we play
+/// multiple snakes in the same game).
+/// States are dropped at the end of the game or if they have not been used
+/// for the `idle` timeout.
+#[derive(Debug)]
+pub struct Sessions {
+    games: Mutex<HashMap<String, (Instant, SearchState)>>,
+    idle: Duration,
+}
+
+impl Sessions {
+    #[must_use]
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            games: Mutex::new(HashMap::new()),
+            idle,
+        }
+    }
+
+    fn key(request: &GameRequest) -> String {
+        format!("{}/{}", request.game.id, request.you.id)
+    }
+
+    /// Drops the states that have not been used for the idle timeout.
+    fn evict(&self, games: &mut HashMap<String, (Instant, SearchState)>) {
+        let now = Instant::now();
+        games.retain(|_, (last, _)| now.duration_since(*last) < self.idle);
+    }
+
+    /// Removes and returns the state of the game.
+    pub fn take(&self, request: &GameRequest) -> Option<SearchState> {
+        let mut games = self.games.lock().unwrap();
+        self.evict(&mut games);
+        games.remove(&Self::key(request)).map(|(_, state)| state)
+    }
+
+    /// Stores the state of the game for the next turn.
+    pub fn insert(&self, request: &GameRequest, state: SearchState) {
+        let mut games = self.games.lock().unwrap();
+        self.evict(&mut games);
+        games.insert(Self::key(request), (Instant::now(), state));
+    }
+
+    /// Drops the state of the ended game.
+    pub fn end(&self, request: &GameRequest) {
+        self.games.lock().unwrap().remove(&Self::key(request));
+    }
+
+    /// Number of stored games.
+    pub fn len(&self) -> usize {
+        self.games.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn evict_on_insert() {
+        use super::*;
+        logging();
+
+        let request = |id: &str| -> GameRequest {
+            serde_json::from_str(&format!(
+                r#"{{"game":{{"id":"{id}","ruleset":{{"name":"standard","version":"v1.0.15"}},"timeout":500}},"turn":0,"board":{{"height":11,"width":11,"food":[],"hazards":[],"snakes":[]}},"you":{{"id":"you","name":"you","health":100,"body":[],"shout":""}}}}"#
+            ))
+            .unwrap()
+        };
+        let state = || SearchState::MonteCarlo(Vec::new());
+
+        let sessions = Sessions::new(Duration::from_millis(50));
+        sessions.insert(&request("a"), state());
+        assert_eq!(sessions.len(), 1);
+        std::thread::sleep(Duration::from_millis(60));
+
+        // Idle games are dropped when another game is stored
+        sessions.insert(&request("b"), state());
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.take(&request("a")).is_none());
+        assert!(sessions.take(&request("b")).is_some());
+        assert!(sessions.is_empty());
+    }
+}
@@ -27,8 +27,11 @@ impl Heuristic for SoloHeuristic {
             let you = &game.snakes[0];
             let area = (game.grid.width * game.grid.height) as f64;
 
-            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-            let food_distances = flood_fill.flood_snakes(&game.grid, &game.snakes);
+            let (food_distances, space) =
+                FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+                    let food_distances = flood_fill.flood_snakes(game);
+                    (food_distances, flood_fill.count_space(0))
+                });
 
             let food_distance = food_distances[0] as f64;
             let saturated = if food_distance < you.health as f64 {
@@ -37,7 +40,7 @@ impl Heuristic for SoloHeuristic {
                 0.0
             };
 
-            let space = flood_fill.count_space(0) as f64 / area;
+            let space = space as f64 / area;
             let size = (3.0 / you.body.len() as f64).sqrt();
 
             self.saturated * saturated + self.space * space + self.size * size
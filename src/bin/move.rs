@@ -36,12 +36,12 @@ async fn main() {
         latency,
     } = Opts::parse();
 
-    let game = Game::from_request(&request);
+    let game = Game::from_request_with(&request, config.pruning());
     info!("{config:?}");
     info!("{game:?}");
 
     let mut flood_fill = FloodFill::new(request.board.width, request.board.height);
-    flood_fill.flood_snakes(&game.grid, &game.snakes);
+    flood_fill.flood_snakes(&game);
     info!("{flood_fill:?}");
 
     let step = config.step(&request, latency as _).await;
@@ -1,7 +1,7 @@
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use log::{info, warn};
 use snork::env::{GameRequest, IndexResponse, API_VERSION};
@@ -20,6 +20,8 @@ struct State {
     tail: String,
     author: String,
     config: Agent,
+    /// Search states of the running games
+    sessions: Sessions,
 }
 
 /// High performant rust snake.
@@ -50,6 +52,9 @@ struct Opt {
     /// Default configuration.
     #[clap(long, default_value_t)]
     config: Agent,
+    /// Time in s after which the search state of an idle game is dropped.
+    #[clap(long, default_value_t = 60)]
+    idle_timeout: u64,
 }
 
 #[tokio::main]
@@ -64,6 +69,7 @@ async fn main() {
         tail,
         author,
         config,
+        idle_timeout,
     } = Opt::parse();
 
     let state = Arc::new(State {
@@ -73,6 +79,7 @@ async fn main() {
         tail,
         author,
         config,
+        sessions: Sessions::new(Duration::from_secs(idle_timeout)),
     });
 
     let index = warp::get()
@@ -107,8 +114,10 @@ async fn main() {
     let end = warp::path("end")
         .and(warp::post())
         .and(warp::body::json())
-        .map(|request: GameRequest| {
+        .and(with_state(state.clone()))
+        .map(|request: GameRequest, state: Arc<State>| {
             warn!("end {request}");
+            state.sessions.end(&request);
             warp::reply()
         });
 
@@ -127,7 +136,10 @@ async fn step(request: GameRequest, state: Arc<State>) -> Result<impl warp::Repl
     warn!("move {request}");
 
     let timer = Instant::now();
-    let next_move = state.config.step(&request, state.latency).await;
+    let next_move = state
+        .config
+        .step_session(&request, state.latency, &state.sessions)
+        .await;
     info!("{next_move:?}, {:?}ms", timer.elapsed().as_millis());
 
     Ok(warp::reply::json(&next_move))
@@ -2,14 +2,18 @@ use clap::Parser;
 use log::{info, warn};
 use owo_colors::OwoColorize;
 
-use snork::agents::Agent;
+use snork::agents::{Agent, FloodHeuristic};
 use snork::env::*;
-use snork::game::{Game, Outcome};
+use snork::game::{Game, GameSettings, Outcome, MAX_SNAKES};
 use snork::logging;
+use snork::relevance::OpponentPruning;
+use snork::rules::GameMode;
 
 use rand::prelude::*;
-use snork::simulate::{init_game, play_game};
-use std::iter::repeat;
+use snork::simulate::{init_game, play_game, record_game, DataFormat};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
 use std::time::Instant;
 
 #[derive(clap::Parser)]
@@ -27,9 +31,18 @@ struct Opts {
     /// Chance new food spawns.
     #[clap(long, default_value_t = 0.15)]
     food_rate: f64,
+    /// Minimum amount of food on the board.
+    #[clap(long, default_value_t = 1)]
+    minimum_food: usize,
+    /// Additional damage of hazards.
+    #[clap(long, default_value_t = 14)]
+    hazard_damage: u8,
     /// Number of turns after which the hazard expands.
     #[clap(short, long, default_value_t = 25)]
     shrink_turns: usize,
+    /// Game mode (standard, royale, constrictor, wrapped, solo).
+    #[clap(long, default_value = "royale")]
+    ruleset: String,
     /// Number of games that are played.
     #[clap(short, long, default_value_t = 1)]
     game_count: usize,
@@ -42,6 +55,15 @@ struct Opts {
     /// Start config.
     #[clap(long, value_parser = parse_request)]
     init: Option<GameRequest>,
+    /// Records all positions of the games into this dataset.
+    #[clap(long)]
+    data: Option<PathBuf>,
+    /// Format of the dataset (jsonl, binary).
+    #[clap(long, default_value = "jsonl")]
+    data_format: DataFormat,
+    /// Depth of the max_n search that labels the recorded moves.
+    #[clap(long, default_value_t = 2)]
+    value_depth: usize,
     /// Configurations.
     #[clap()]
     agents: Vec<Agent>,
@@ -60,20 +82,40 @@ async fn main() {
         width,
         height,
         food_rate,
+        minimum_food,
+        hazard_damage,
         shrink_turns,
+        ruleset,
         game_count,
         swap,
         seed,
         init,
+        data,
+        data_format,
+        value_depth,
         mut agents,
     } = Opts::parse();
 
-    assert!(agents.len() <= 4, "Only up to 4 snakes are supported");
+    assert!(
+        agents.len() <= MAX_SNAKES,
+        "Only up to {MAX_SNAKES} snakes are supported"
+    );
     info!("agents: {agents:?}");
 
+    let mode = GameMode::from_name(&ruleset);
+    let settings = GameSettings {
+        hazard_damage,
+        food_spawn_chance: (food_rate * 100.0) as _,
+        minimum_food,
+        shrink_turns,
+    };
+
     let start = Instant::now();
 
-    let mut wins = repeat(0).take(agents.len()).collect::<Vec<usize>>();
+    let mut wins = vec![0; agents.len()];
+
+    let mut dataset = data.map(|path| BufWriter::new(File::create(path).unwrap()));
+    let heuristic = FloodHeuristic::default();
 
     for _ in 0..agents.len() {
         let mut rng = if seed == 0 {
@@ -84,20 +126,29 @@ async fn main() {
 
         for i in 0..game_count {
             let mut game = if let Some(request) = &init {
-                Game::from_request(request)
+                Game::from_request_with(request, OpponentPruning::All)
             } else {
-                init_game(width, height, agents.len(), &mut rng)
+                let mut game = init_game(width, height, agents.len(), &mut rng);
+                game.set_mode(mode);
+                game.settings = settings;
+                game
             };
 
-            let outcome = play_game(
-                &agents,
-                &mut game,
-                timeout,
-                food_rate,
-                shrink_turns,
-                &mut rng,
-            )
-            .await;
+            let outcome = if let Some(dataset) = &mut dataset {
+                let (outcome, positions) = record_game(
+                    &agents,
+                    &mut game,
+                    timeout,
+                    &mut rng,
+                    &heuristic,
+                    value_depth,
+                )
+                .await;
+                data_format.write(dataset, &positions).unwrap();
+                outcome
+            } else {
+                play_game(&agents, &mut game, timeout, &mut rng).await
+            };
             if let Outcome::Winner(winner) = outcome {
                 wins[winner as usize] += 1;
             }
@@ -116,6 +167,10 @@ async fn main() {
         agents.rotate_left(1);
     }
 
+    if let Some(mut dataset) = dataset {
+        dataset.flush().unwrap();
+    }
+
     println!("Agents: {agents:?}");
     println!("Result: {wins:?}");
 }
@@ -0,0 +1,470 @@
+//! # Bitboard Floodfill
+//!
+//! Floodfill on the [BitboardGame] that expands all snakes simultaneously,
+//! one distance level at a time, instead of cell by cell.
+//!
+//! It follows the rules of [crate::floodfill::FloodFill::flood_snakes]:
+//! Snakes can follow tails that have vanished at the given distance,
+//! contested cells go to the longer snake (on draw we lose) and snakes are
+//! limited by their health, which is reduced by hazards and restored by food.
+//! Cells reached with different health or food eaten on the way are tracked
+//! as separate fronts of the same snake.
+//! The owned cells and their health are the same as for the floodfill.
+
+use crate::bitboard::{BitSet, BitboardGame};
+use crate::floodfill::FoodDistances;
+use crate::game::MAX_SNAKES;
+use crate::util::FixedVec;
+
+/// Number of bit planes for the tail distances (up to 19x19 cells).
+const DIST_BITS: usize = 9;
+
+/// Cells that have been reached with the same health and food.
+#[derive(Debug, Clone, Copy)]
+struct Front<B: BitSet> {
+    cells: B,
+    health: u8,
+    food: u16,
+}
+
+/// Distances of the body cells from the tail, stored in bit planes.
+#[derive(Clone, Copy, Default)]
+struct TailDist<B: BitSet> {
+    body: B,
+    planes: [B; DIST_BITS],
+    persistent: bool,
+}
+
+impl<B: BitSet> TailDist<B> {
+    /// Returns the body cells that are still occupied after `distance` moves.
+    fn occupied(&self, distance: isize) -> B {
+        if self.persistent || distance < 0 {
+            return self.body;
+        }
+        if distance >= 1 << DIST_BITS {
+            return B::default();
+        }
+        // Bit sliced comparison: tail_dist > distance
+        let mut greater = B::default();
+        let mut equal = self.body;
+        for (i, &plane) in self.planes.iter().enumerate().rev() {
+            if distance & (1 << i) == 0 {
+                greater |= equal & plane;
+                equal &= !plane;
+            } else {
+                equal &= plane;
+            }
+        }
+        greater
+    }
+}
+
+/// Floodfill for bitboards, which stores the owned cells for every snake.
+pub struct BitFloodFill<B: BitSet = u128> {
+    owned: [B; MAX_SNAKES],
+    health: [usize; MAX_SNAKES],
+    /// Buffers for the fronts of the current and next distance
+    fronts: [Vec<Front<B>>; MAX_SNAKES],
+    next: [Vec<Front<B>>; MAX_SNAKES],
+}
+
+impl<B: BitSet> Default for BitFloodFill<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BitSet> BitFloodFill<B> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            owned: [B::default(); MAX_SNAKES],
+            health: [0; MAX_SNAKES],
+            fronts: Default::default(),
+            next: Default::default(),
+        }
+    }
+
+    /// Returns the cells owned by the snake.
+    pub fn owned(&self, i: u8) -> B {
+        self.owned[i as usize]
+    }
+
+    /// Counts the space of you or the enemies.
+    pub fn count_space(&self, i: u8) -> usize {
+        self.owned[i as usize].count() as usize
+    }
+
+    /// Counts the total health of you or the enemies.
+    pub fn count_health(&self, i: u8) -> usize {
+        self.health[i as usize]
+    }
+
+    /// Computes the floodfill for all snakes and returns the distances to
+    /// the nearest four food cells of the snake with id 0.
+    pub fn flood_snakes(&mut self, game: &BitboardGame<B>) -> FoodDistances {
+        self.owned = [B::default(); MAX_SNAKES];
+        self.health = [0; MAX_SNAKES];
+
+        let snakes = game.snakes();
+        let persistent = game.mode.ruleset().snakes_grow();
+        let hazard_cost = 1 + game.settings.hazard_damage;
+
+        let mut tails = [TailDist::default(); MAX_SNAKES];
+        let mut fronts = std::mem::take(&mut self.fronts);
+        let mut next = std::mem::take(&mut self.next);
+        fronts.iter_mut().for_each(Vec::clear);
+        for (id, snake) in snakes.iter().enumerate().filter(|(_, s)| s.alive()) {
+            let tail = &mut tails[id];
+            tail.body = snake.body;
+            tail.persistent = persistent;
+            for (i, cell) in game.body(snake).enumerate() {
+                let dist = snake.stacked as usize + i;
+                for (b, plane) in tail.planes.iter_mut().enumerate() {
+                    *plane = plane.with(cell, dist & (1 << b) != 0);
+                }
+            }
+            fronts[id].push(Front {
+                cells: B::bit(snake.head),
+                health: snake.health,
+                food: 0,
+            });
+        }
+
+        let mut food_distances = FixedVec::new();
+        let mut reached = B::default();
+
+        for distance in 0..game.cells().count() as isize {
+            if fronts.iter().all(|f| f.is_empty()) {
+                break;
+            }
+
+            let occupied: [B; MAX_SNAKES] = std::array::from_fn(|id| tails[id].occupied(distance));
+            let all_occupied = occupied.iter().fold(B::default(), |acc, &o| acc | o);
+
+            // Expand all fronts
+            next.iter_mut().for_each(Vec::clear);
+            let mut claimed = [B::default(); MAX_SNAKES];
+            for (id, snake_fronts) in fronts.iter().enumerate() {
+                if snake_fronts.is_empty() {
+                    continue;
+                }
+                let enemies = all_occupied & !tails[id].body;
+
+                let mut found = B::default();
+                for front in snake_fronts.iter() {
+                    let cells = game.neighbors(front.cells) & !reached;
+                    if id == 0 {
+                        // Collect food on the way
+                        let food = cells & game.food & !found;
+                        for _ in 0..food.count() {
+                            food_distances.push(distance as u16);
+                        }
+                        found |= food;
+                    }
+
+                    let own = if front.food == 0 {
+                        occupied[id]
+                    } else {
+                        tails[id].occupied(distance - front.food as isize)
+                    };
+                    let cells = cells & !enemies & !own;
+                    let food = cells & game.food;
+                    let hazards = cells & !food & game.hazards;
+                    let normal = cells & !food & !game.hazards;
+
+                    let next = &mut next[id];
+                    push_front(next, food, 100, front.food + 1);
+                    push_front(
+                        next,
+                        hazards,
+                        front.health.saturating_sub(hazard_cost),
+                        front.food,
+                    );
+                    push_front(next, normal, front.health.saturating_sub(1), front.food);
+                }
+
+                // Keep the cells with the most food, then the most health
+                next[id].sort_unstable_by_key(|f| std::cmp::Reverse((f.food, f.health)));
+                for front in next[id].iter_mut() {
+                    front.cells &= !claimed[id];
+                    claimed[id] |= front.cells;
+                }
+            }
+
+            // Cells that are reached by multiple snakes in this step
+            let mut contested = B::default();
+            for &cells in &claimed {
+                contested |= reached & cells;
+                reached |= cells;
+            }
+
+            for id in 0..MAX_SNAKES {
+                fronts[id].clear();
+                for front in next[id].iter() {
+                    let mut cells = front.cells;
+
+                    // Longer snakes win contested cells (on draw we lose)
+                    if !(cells & contested).is_empty() {
+                        let len = snakes[id].len + front.food;
+                        for (other, other_fronts) in next.iter().enumerate() {
+                            for other_front in other_fronts.iter() {
+                                let other_len = snakes[other].len + other_front.food;
+                                if (other_len, other) > (len, id) {
+                                    cells &= !other_front.cells;
+                                }
+                            }
+                        }
+                    }
+
+                    if !cells.is_empty() {
+                        self.owned[id] |= cells;
+                        self.health[id] += cells.count() as usize * front.health as usize;
+                        fronts[id].push(Front { cells, ..*front });
+                    }
+                }
+            }
+        }
+
+        self.fronts = fronts;
+        self.next = next;
+        food_distances
+    }
+}
+
+/// Adds the cells to the fronts, merging them with a front of the same
+/// health and food.
+fn push_front<B: BitSet>(fronts: &mut Vec<Front<B>>, cells: B, health: u8, food: u16) {
+    if cells.is_empty() || health == 0 {
+        return;
+    }
+    if let Some(front) = fronts
+        .iter_mut()
+        .find(|f| f.health == health && f.food == food)
+    {
+        front.cells |= cells;
+    } else {
+        fronts.push(Front {
+            cells,
+            health,
+            food,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::floodfill::{FCell, FloodFill};
+    use crate::logging;
+
+    /// Cells owned by the snake `id` as bitboard.
+    fn owned(floodfill: &FloodFill, id: u8) -> u128 {
+        let mut owned = 0;
+        for y in 0..floodfill.height {
+            for x in 0..floodfill.width {
+                let p = crate::env::Vec2D::new(x as _, y as _);
+                if matches!(floodfill[p], FCell::Owned { id: i, .. } if i == id) {
+                    owned |= 1 << (x + y * floodfill.width);
+                }
+            }
+        }
+        owned
+    }
+
+    #[test]
+    fn bitflood_fixtures() {
+        use super::*;
+        use crate::env::Vec2D;
+        use crate::game::Game;
+        use crate::rules::GameMode;
+        logging();
+
+        fn check(game: &Game, expected: usize) {
+            let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+            let food = floodfill.flood_snakes(game);
+            assert_eq!(floodfill.count_space(0), expected);
+
+            let bitboard = BitboardGame::<u128>::from_game(game).unwrap();
+            let mut bitflood = BitFloodFill::new();
+            let bitfood = bitflood.flood_snakes(&bitboard);
+            info!("Filled {} {game:?}", bitflood.count_space(0));
+            assert_eq!(bitflood.count_space(0), expected);
+            assert_eq!(bitflood.count_health(0), floodfill.count_health(0));
+            assert_eq!(&*bitfood, &*food);
+            for i in 0..game.snakes.len() as u8 {
+                assert_eq!(bitflood.owned(i), owned(&floodfill, i));
+                assert_eq!(bitflood.count_health(i), floodfill.count_health(i));
+            }
+        }
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            > > > v . . . . . . .
+            ^ . . v . . . . . . .
+            ^ 0 < < . . . . . . ."#,
+        )
+        .unwrap();
+        check(&game, 11 * 11);
+        let mut constrictor = game.clone();
+        constrictor.set_mode(GameMode::Constrictor);
+        check(&constrictor, 2);
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            v . . . . . . . . . .
+            v . . . . . . . . . .
+            v . . . . . . . . . .
+            > > > v . . . . . . .
+            . . . v . . . . . . .
+            . 0 < < . . . . . . ."#,
+        )
+        .unwrap();
+        check(&game, 4);
+        let mut wrapped = game.clone();
+        wrapped.set_mode(GameMode::Wrapped);
+        check(&wrapped, 11 * 11);
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            v . . . . . . . . . .
+            > > v . . . . . . . .
+            o 0 < . . . . . . . ."#,
+        )
+        .unwrap();
+        check(&game, 1);
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            1 . . . . . . . . . .
+            ^ v . . . . . . . . .
+            ^ > v . . . . . . . .
+            . 0 < . . . . . . . ."#,
+        )
+        .unwrap();
+        check(&game, 24);
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . 0 . . . . .
+            . . . . . ^ . . . . .
+            . . . . . ^ . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        game.snakes[0].health = 6;
+        check(&game, 59);
+
+        game.snakes[0].health = 50;
+        for y in 0..game.grid.height {
+            for x in game.grid.width / 2 + 1..game.grid.width {
+                game.grid[Vec2D::new(x as _, y as _)].hazard = true;
+            }
+        }
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+        check(&game, floodfill.count_space(0));
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . 1 < < < v . . . .
+            o 0 < < < ^ v . . . .
+            > > > > ^ ^ < . . . ."#,
+        )
+        .unwrap();
+        check(&game, 29);
+    }
+
+    #[test]
+    fn bitflood_random() {
+        use super::*;
+        use crate::bitboard::WideBitboardGame;
+        use crate::env::Direction;
+        use crate::game::Outcome;
+        use crate::rules::GameMode;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [GameMode::Standard, GameMode::Constrictor, GameMode::Wrapped] {
+            for i in 0..20 {
+                let snakes = if i % 2 == 0 { 4 } else { MAX_SNAKES };
+                let mut game = init_game(11, 11, snakes, &mut rng);
+                game.set_mode(mode);
+                let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+                let mut bitflood = BitFloodFill::new();
+                let mut wideflood = BitFloodFill::new();
+
+                while game.outcome() == Outcome::None {
+                    floodfill.flood_snakes(&game);
+                    bitflood.flood_snakes(&BitboardGame::<u128>::from_game(&game).unwrap());
+                    wideflood.flood_snakes(&WideBitboardGame::from_game(&game).unwrap());
+
+                    for i in 0..game.snakes.len() as u8 {
+                        assert_eq!(bitflood.owned(i), owned(&floodfill, i), "{game:?}");
+                        assert_eq!(bitflood.count_health(i), floodfill.count_health(i));
+                        // Both bitboard sizes have to behave the same
+                        assert_eq!(bitflood.count_space(i), wideflood.count_space(i));
+                        assert_eq!(bitflood.count_health(i), wideflood.count_health(i));
+                    }
+
+                    let moves: [Direction; MAX_SNAKES] = std::array::from_fn(|i| {
+                        let valid = game.valid_moves(i as u8).collect::<Vec<_>>();
+                        if valid.is_empty() {
+                            Direction::Up
+                        } else {
+                            valid[rng.gen_range(0..valid.len())]
+                        }
+                    });
+                    game.step(&moves);
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,20 @@
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::mem::size_of;
 use std::ops::{Index, IndexMut};
 
-use crate::env::{Direction, Vec2D, HAZARD_DAMAGE};
-use crate::game::Snake;
+use crate::env::{Direction, Vec2D};
+use crate::game::Game;
 use crate::grid::{CellT, Grid};
+use crate::territory::FloodReport;
 use crate::util::FixedVec;
 
 use owo_colors::{AnsiColors, OwoColorize};
 
+/// Distances to the nearest four food cells of the agent (id 0), which are
+/// returned by the floodfills.
+pub type FoodDistances = FixedVec<u16, 4>;
+
 /// Floodfill Cell that stores the important data in a single Byte.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FCell {
@@ -28,6 +34,13 @@ pub enum FCell {
 
 const _: () = assert!(size_of::<FCell>() == 8);
 
+/// Cell that is owned on the grid but not by any snake, see
+/// [crate::relevance].
+const OBSTACLE: FCell = FCell::Occupied {
+    id: u8::MAX,
+    tail_dist: u16::MAX,
+};
+
 impl std::fmt::Debug for FCell {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         fn id_color(id: u8) -> AnsiColors {
@@ -41,6 +54,7 @@ impl std::fmt::Debug for FCell {
         }
 
         match self {
+            &OBSTACLE => write!(f, "{}", "###".bright_black()),
             FCell::Occupied { id, tail_dist } => {
                 write!(f, "{:0>3}", tail_dist.color(id_color(*id)))
             }
@@ -77,11 +91,59 @@ impl SnakePos {
     }
 }
 
+/// Returns if the snake can enter the `body` cell after `s_distance` moves.
+const fn passable(body: FCell, s_id: u8, s_distance: u16, food: u16) -> bool {
+    match body {
+        // Follow own tail
+        FCell::Occupied { id, tail_dist } if id == s_id => tail_dist + food <= s_distance,
+        // Follow enemy tail
+        FCell::Occupied { tail_dist, .. } => tail_dist <= s_distance, // <= enemy eats!
+        _ => true,
+    }
+}
+
+/// Returns if the snake takes over the `cell` when reaching it.
+const fn owns(cell: FCell, s_id: u8, s_distance: u16, s_len: u16, s_health: u8) -> bool {
+    match cell {
+        FCell::Free | FCell::Occupied { .. } => true,
+        // Reached in same step?
+        FCell::Owned {
+            id,
+            health,
+            len,
+            distance,
+        } => {
+            distance == s_distance
+                && if id != s_id {
+                    // Longer snake wins (on draw we loose)
+                    len < s_len || len == s_len && id < s_id
+                } else {
+                    // We can reach this with more food or health
+                    len < s_len || len == s_len && health < s_health
+                }
+        }
+    }
+}
+
+thread_local! {
+    /// Buffers of [FloodFill::with_workspace].
+    static WORKSPACE: RefCell<FloodFill> = RefCell::new(FloodFill::new(0, 0));
+}
+
 /// Grid that performs the floodfill algorithm asses area control.
 ///
 /// This struct also contains all necessary buffers for the floodfill algorithm.
+/// They are reused by the following computations, so that a floodfill
+/// should be kept for repeated evaluations (see [FloodFill::with_workspace]).
 pub struct FloodFill {
     cells: Vec<FCell>,
+    /// Snake bodies of the prepared board, which still block the cells
+    /// after they have been reached by another snake
+    bodies: Vec<FCell>,
+    /// Snakes (bit per id) that reached the cell at the same distance,
+    /// which are only tracked by [FloodFill::report]
+    contested: Vec<u16>,
+    queue: VecDeque<SnakePos>,
     pub width: usize,
     pub height: usize,
 }
@@ -91,11 +153,40 @@ impl FloodFill {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
             cells: vec![FCell::Free; width * height],
+            bodies: vec![FCell::Free; width * height],
+            contested: vec![0; width * height],
+            // Assuming there are at most n^2 elements in the queue
+            queue: VecDeque::with_capacity(width * height),
             width,
             height,
         }
     }
 
+    /// Runs `f` with a floodfill of the given size, whose buffers are
+    /// reused by all calls on the same thread.
+    ///
+    /// The content of the floodfill is undefined, it has to be filled with
+    /// [FloodFill::flood_snakes] first.
+    pub fn with_workspace<R>(width: usize, height: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        WORKSPACE.with(|workspace| match workspace.try_borrow_mut() {
+            Ok(mut flood_fill) => {
+                flood_fill.resize(width, height);
+                f(&mut flood_fill)
+            }
+            // Nested calls get their own buffers
+            Err(_) => f(&mut Self::new(width, height)),
+        })
+    }
+
+    /// Changes the size of the board, keeping the allocated buffers.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.cells.resize(width * height, FCell::Free);
+        self.bodies.resize(width * height, FCell::Free);
+        self.contested.resize(width * height, 0);
+        self.width = width;
+        self.height = height;
+    }
+
     /// Returns if `p` is within the boundaries of the board.
     pub fn has(&self, p: Vec2D) -> bool {
         p.within(self.width, self.height)
@@ -120,6 +211,29 @@ impl FloodFill {
             .count()
     }
 
+    /// Returns the snakes (bit per id) that reached `p` at the same distance,
+    /// which are either zero or at least two.
+    ///
+    /// They are only tracked by [FloodFill::report] for snakes with an id
+    /// below 16.
+    pub fn contested(&self, p: Vec2D) -> u16 {
+        self.contested[self.index(p)]
+    }
+
+    /// Computes the flood fill like [FloodFill::flood_snakes], additionally
+    /// tracking the contested cells, and summarizes the territories of all
+    /// snakes, see [FloodReport].
+    pub fn report(&mut self, game: &Game) -> FloodReport {
+        self.prepare(game);
+        self.flood_heads::<true>(game);
+        FloodReport::new(self, game)
+    }
+
+    fn index(&self, p: Vec2D) -> usize {
+        debug_assert!(p.within(self.width, self.height));
+        p.x as usize % self.width + p.y as usize * self.width
+    }
+
     /// Clears the board so that it can be reused for another floodfill computation.
     pub fn clear(&mut self) {
         self.cells.fill(FCell::Free);
@@ -134,42 +248,20 @@ impl FloodFill {
     /// This allows the snake to follow its tail or enemy tails.
     ///
     /// Food on the way is been accounted for the own tail.
-    fn flood(&mut self, grid: &Grid, heads: impl Iterator<Item = SnakePos>) -> FixedVec<u16, 4> {
-        const fn owns(
-            cell: FCell,
-            s_id: u8,
-            s_distance: u16,
-            food: u16,
-            s_len: u16,
-            s_health: u8,
-        ) -> bool {
-            match cell {
-                FCell::Free => true,
-                // Follow own tail
-                FCell::Occupied { id, tail_dist } if id == s_id => tail_dist + food <= s_distance,
-                // Follow enemy tail
-                FCell::Occupied { tail_dist, .. } => tail_dist <= s_distance, // <= enemy eats!
-                // Reached in same step?
-                FCell::Owned {
-                    id,
-                    health,
-                    len,
-                    distance,
-                } => {
-                    distance == s_distance
-                        && if id != s_id {
-                            // Longer snake wins (on draw we loose)
-                            len < s_len || len == s_len && id < s_id
-                        } else {
-                            // We can reach this with more health
-                            health < s_health
-                        }
-                }
-            }
+    ///
+    /// The contested cells are only tracked if `CONTESTED` is set.
+    fn flood<const CONTESTED: bool>(
+        &mut self,
+        grid: &Grid,
+        hazard_damage: u8,
+        heads: impl Iterator<Item = SnakePos>,
+    ) -> FoodDistances {
+        if CONTESTED {
+            self.contested.fill(0);
         }
-
-        // Assuming there are at most n^2 elements in the queue
-        let mut queue = VecDeque::with_capacity(self.width * self.height);
+        self.bodies.clone_from(&self.cells);
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.clear();
         queue.extend(heads);
 
         // Collect food on the way
@@ -184,8 +276,21 @@ impl FloodFill {
             health,
         }) = queue.pop_front()
         {
+            // The cell has been taken over by a longer snake or a better path
+            if distance > 0
+                && self[p]
+                    != (FCell::Owned {
+                        id,
+                        health,
+                        len,
+                        distance: distance - 1,
+                    })
+            {
+                continue;
+            }
+
             for d in Direction::all() {
-                let p = p.apply(d);
+                let p = grid.apply(p, d);
                 if !self.has(p) {
                     continue;
                 }
@@ -198,7 +303,7 @@ impl FloodFill {
                 let health = if is_food {
                     100
                 } else {
-                    let cost = if g_cell.hazard { HAZARD_DAMAGE } else { 1 };
+                    let cost = if g_cell.hazard { 1 + hazard_damage } else { 1 };
                     health.saturating_sub(cost)
                 };
 
@@ -210,7 +315,26 @@ impl FloodFill {
                 let food = food + is_food as u16;
                 let len = len + is_food as u16;
 
-                if health > 0 && owns(cell, id, distance, food, len, health) {
+                // Reached by another snake at the same distance
+                if CONTESTED {
+                    if let FCell::Owned {
+                        id: other,
+                        distance: other_distance,
+                        ..
+                    } = cell
+                    {
+                        if other != id && other_distance == distance && health > 0 {
+                            let bit = |id: u8| 1u16.checked_shl(id as _).unwrap_or(0);
+                            let i = self.index(p);
+                            self.contested[i] |= bit(id) | bit(other);
+                        }
+                    }
+                }
+
+                if health > 0
+                    && passable(self.bodies[self.index(p)], id, distance, food)
+                    && owns(cell, id, distance, len, health)
+                {
                     self[p] = FCell::Owned {
                         id,
                         health,
@@ -221,29 +345,72 @@ impl FloodFill {
                 }
             }
         }
+        self.queue = queue;
         food_distances
     }
 
     /// Prepare the board and compute flood fill.
     /// It is assumed that the snake at position and id 0 is the evaluated
     /// agent and the other snakes are the enemies.
-    pub fn flood_snakes(&mut self, grid: &Grid, snakes: &[Snake]) -> FixedVec<u16, 4> {
+    pub fn flood_snakes(&mut self, game: &Game) -> FoodDistances {
+        self.prepare(game);
+        self.flood_heads::<false>(game)
+    }
+
+    /// Computes the flood fill of the snake `id` alone, as if the other
+    /// snakes would not compete for the cells.
+    ///
+    /// All cells that the snake can reach are owned by it afterwards.
+    pub fn flood_alone(&mut self, game: &Game, id: u8) -> FoodDistances {
+        self.prepare(game);
+        let head = game
+            .snakes
+            .get(id as usize)
+            .filter(|s| s.alive())
+            .map(|s| SnakePos::new(s.head(), id, 0, 0, s.body.len() as _, s.health));
+        self.flood::<false>(&game.grid, game.settings.hazard_damage, head.into_iter())
+    }
+
+    /// Prepares the board with the obstacles and snake bodies.
+    fn prepare(&mut self, game: &Game) {
         self.clear();
 
+        // Snakes that grow every turn never free their bodies
+        let persistent = game.rules().snakes_grow();
+        let area = (self.width * self.height) as u16;
+
+        self.occupy_obstacles(&game.grid);
         // Prepare board with snakes (tail = 1, ..., head = n)
-        for (id, snake) in snakes.iter().enumerate() {
+        for (id, snake) in game.snakes.iter().enumerate() {
             for (i, p) in snake.body.iter().enumerate() {
                 self[*p] = FCell::Occupied {
                     id: id as _,
-                    tail_dist: i as u16,
+                    tail_dist: if persistent { area } else { i as u16 },
                 }
             }
         }
+    }
+
+    /// Occupies all cells of the grid that are owned, which are overwritten
+    /// by the snake bodies afterwards.
+    ///
+    /// The remaining cells are obstacles, like the bodies of snakes that are
+    /// not simulated, and are never freed.
+    fn occupy_obstacles(&mut self, grid: &Grid) {
+        for (cell, g_cell) in self.cells.iter_mut().zip(&grid.cells) {
+            if g_cell.t == CellT::Owned {
+                *cell = OBSTACLE;
+            }
+        }
+    }
 
+    /// Floods the prepared board from the heads of the living snakes.
+    fn flood_heads<const CONTESTED: bool>(&mut self, game: &Game) -> FoodDistances {
         // Longer or equally long snakes first
-        self.flood(
-            grid,
-            snakes
+        self.flood::<CONTESTED>(
+            &game.grid,
+            game.settings.hazard_damage,
+            game.snakes
                 .iter()
                 .enumerate()
                 .filter(|&(_, s)| s.alive())
@@ -252,19 +419,376 @@ impl FloodFill {
     }
 }
 
+/// Floodfill that follows a game turn by turn.
+///
+/// Instead of flooding the whole board again, the ownership of the last
+/// floodfill is repaired after a single [Game::step]:
+/// The distances are shifted by the elapsed turn, so that the cells that
+/// are reached through the new heads keep their owner.
+/// Only the cells whose neighbours, bodies, food or hazards have changed are
+/// recomputed, in the order of their distances, and their changes are
+/// propagated to the cells that depend on them.
+///
+/// The ownership is identical to [FloodFill::flood_snakes].
+/// If the game does not follow the last update by a single step (e.g. a
+/// new game or snakes that grow every turn) or most of the board changes,
+/// the board is flooded from scratch.
+///
+/// Note that a moving head changes the distances of most cells it owns, so
+/// the repair usually revisits the larger part of the board and is currently
+/// slower than a full flood (see the `floodfill` benchmarks).
+pub struct IncrementalFloodFill {
+    flood_fill: FloodFill,
+    /// Labels of the last floodfill, while the board is prepared
+    labels: Vec<FCell>,
+    /// Food (1) and hazards (2) of the last floodfill
+    terrain: Vec<u8>,
+    /// Head states of the living snakes, indexed by id
+    heads: Vec<Option<SnakePos>>,
+    /// Id + 1 of the living snake whose head is on the cell
+    head_ids: Vec<u8>,
+    /// Cells that are (re)computed at the given distance
+    buckets: Vec<Vec<u32>>,
+    /// Generation in which the final label of the cell has been found
+    done: Vec<u32>,
+    /// Generation and distance at which the cell was last computed
+    seen: Vec<u64>,
+    generation: u32,
+    turn: Option<usize>,
+    hazard_damage: u8,
+}
+
+impl IncrementalFloodFill {
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            flood_fill: FloodFill::new(width, height),
+            labels: Vec::new(),
+            terrain: Vec::new(),
+            heads: Vec::new(),
+            head_ids: Vec::new(),
+            buckets: Vec::new(),
+            done: Vec::new(),
+            seen: Vec::new(),
+            generation: 0,
+            turn: None,
+            hazard_damage: 0,
+        }
+    }
+
+    /// Result of the last update.
+    pub fn flood_fill(&self) -> &FloodFill {
+        &self.flood_fill
+    }
+
+    /// Updates the ownership for the `game`, like [FloodFill::flood_snakes].
+    ///
+    /// Returns if the last floodfill has been repaired, instead of flooding
+    /// the board from scratch.
+    pub fn update(&mut self, game: &Game) -> bool {
+        let (width, height) = (game.grid.width, game.grid.height);
+        let follows = self.turn.map(|t| t + 1) == Some(game.turn)
+            && width == self.flood_fill.width
+            && height == self.flood_fill.height
+            && game.snakes.len() == self.heads.len()
+            && game.settings.hazard_damage == self.hazard_damage
+            && !game.rules().snakes_grow();
+
+        self.heads.clear();
+        self.head_ids.clear();
+        self.head_ids.resize(width * height, 0);
+        for (id, snake) in game.snakes.iter().enumerate() {
+            let head = snake.alive().then(|| {
+                let head = snake.head();
+                self.head_ids[head.x as usize + head.y as usize * width] = id as u8 + 1;
+                SnakePos::new(head, id as _, 0, 0, snake.body.len() as _, snake.health)
+            });
+            self.heads.push(head);
+        }
+
+        let repaired = follows && self.repair(game);
+        if !repaired {
+            self.flood_fill.resize(width, height);
+            self.flood_fill.flood_snakes(game);
+        }
+        self.turn = Some(game.turn);
+        self.hazard_damage = game.settings.hazard_damage;
+        self.terrain.clear();
+        self.terrain.extend(
+            (game.grid.cells.iter()).map(|c| (c.t == CellT::Food) as u8 | (c.hazard as u8) << 1),
+        );
+        repaired
+    }
+
+    /// Repairs the last floodfill, returns false if it was aborted.
+    fn repair(&mut self, game: &Game) -> bool {
+        let grid = &game.grid;
+        let area = self.flood_fill.width * self.flood_fill.height;
+        self.generation += 1;
+        if self.done.len() != area {
+            self.done = vec![0; area];
+            self.seen = vec![0; area];
+        }
+
+        // Prepare the new board, keeping the labels of the last floodfill
+        std::mem::swap(&mut self.flood_fill.cells, &mut self.labels);
+        self.flood_fill.cells.resize(area, FCell::Free);
+        self.flood_fill.prepare(game);
+
+        // Cells whose inputs have changed and the cells that depended on
+        // the labels next to the old heads
+        let mut seeds = Vec::new();
+        let mut dependents = Vec::new();
+        for i in 0..area {
+            let board = self.flood_fill.cells[i];
+            // The bodies of the last board, one turn later
+            let last = match self.flood_fill.bodies[i] {
+                FCell::Occupied { tail_dist: 0, .. } => FCell::Free,
+                FCell::Occupied { id, tail_dist } if id != u8::MAX => FCell::Occupied {
+                    id,
+                    tail_dist: tail_dist - 1,
+                },
+                cell => cell,
+            };
+            self.flood_fill.bodies[i] = board;
+            let g_cell = grid.cells[i];
+            let terrain = (g_cell.t == CellT::Food) as u8 | (g_cell.hazard as u8) << 1;
+            if last != board || self.terrain[i] != terrain {
+                seeds.push(i);
+            }
+
+            match self.labels[i] {
+                FCell::Owned { distance: 0, .. } => {
+                    seeds.push(i);
+                    dependents.push(i);
+                }
+                FCell::Owned {
+                    id,
+                    health,
+                    len,
+                    distance,
+                } => {
+                    self.flood_fill.cells[i] = FCell::Owned {
+                        id,
+                        health,
+                        len,
+                        distance: distance - 1,
+                    }
+                }
+                _ => {}
+            }
+        }
+        for head in self.heads.iter().flatten() {
+            for d in Direction::all() {
+                let p = grid.apply(head.p, d);
+                if self.flood_fill.has(p) {
+                    seeds.push(self.flood_fill.index(p));
+                }
+            }
+        }
+
+        for bucket in &mut self.buckets {
+            bucket.clear();
+        }
+        for i in dependents {
+            self.notify(grid, i, 0);
+        }
+        for i in seeds {
+            if let Some(level) = self.lower_bound(grid, i) {
+                self.push(i, level);
+            }
+        }
+
+        let mut processed = 0;
+        let mut level = 0;
+        while level < self.buckets.len() {
+            let bucket = std::mem::take(&mut self.buckets[level]);
+            let stamp = (self.generation as u64) << 16 | level as u64;
+            for &i in &bucket {
+                let i = i as usize;
+                if self.done[i] == self.generation || self.seen[i] == stamp {
+                    continue;
+                }
+                self.seen[i] = stamp;
+                processed += 1;
+                if processed > 2 * area {
+                    // The whole board has changed, a full flood is faster
+                    return false;
+                }
+                self.relabel(grid, i, level as u16);
+            }
+            self.buckets[level] = bucket;
+            level += 1;
+        }
+        true
+    }
+
+    /// Computes the label of the cell `i` at the distance `level`, when all
+    /// labels of smaller distances are final.
+    fn relabel(&mut self, grid: &Grid, i: usize, level: u16) {
+        let p = Vec2D::new(
+            (i % self.flood_fill.width) as _,
+            (i / self.flood_fill.width) as _,
+        );
+        if let Some(label) = self.candidate(grid, p, level) {
+            self.done[i] = self.generation;
+            if self.flood_fill.cells[i] != label {
+                self.flood_fill.cells[i] = label;
+                self.notify(grid, i, level as usize + 1);
+            }
+            return;
+        }
+
+        // Not reached yet, but maybe later by one of the neighbours
+        let mut next = (self.neighbours(grid, p))
+            .filter_map(|q| match self.flood_fill.cells[q] {
+                FCell::Owned { distance, .. } if distance >= level => Some(distance as usize + 1),
+                _ => None,
+            })
+            .min();
+        if let FCell::Owned { distance, .. } = self.flood_fill.cells[i] {
+            if distance <= level || next.is_none() {
+                self.flood_fill.cells[i] = self.flood_fill.bodies[i];
+                self.notify(grid, i, level as usize + 1);
+            } else {
+                next = next.min(Some(distance as usize));
+            }
+        }
+        if let Some(next) = next {
+            self.push(i, next);
+        }
+    }
+
+    /// Best label of `p` that is offered by the heads and the labels closer
+    /// than `level`.
+    fn candidate(&self, grid: &Grid, p: Vec2D, level: u16) -> Option<FCell> {
+        let g_cell = grid[p];
+        let is_food = g_cell.t == CellT::Food;
+        let body = self.flood_fill.bodies[self.flood_fill.index(p)];
+
+        let mut best: Option<FCell> = None;
+        for q in self.neighbours(grid, p) {
+            let head = self.head_ids[q]
+                .checked_sub(1)
+                .and_then(|id| self.heads[id as usize].clone());
+            let label = match self.flood_fill.cells[q] {
+                FCell::Owned {
+                    id,
+                    health,
+                    len,
+                    distance,
+                } if distance < level => {
+                    let head_len = self.heads[id as usize].as_ref().map_or(len, |h| h.len);
+                    let food = len.saturating_sub(head_len);
+                    Some(SnakePos::new(p, id, distance + 1, food, len, health))
+                }
+                _ => None,
+            };
+
+            for s in [head, label].into_iter().flatten() {
+                let health = if is_food {
+                    100
+                } else {
+                    let cost = if g_cell.hazard {
+                        1 + self.hazard_damage
+                    } else {
+                        1
+                    };
+                    s.health.saturating_sub(cost)
+                };
+                let food = s.food + is_food as u16;
+                let len = s.len + is_food as u16;
+                if health > 0 && passable(body, s.id, s.distance, food) {
+                    let better = match best {
+                        Some(FCell::Owned { distance, .. }) if distance != s.distance => {
+                            s.distance < distance
+                        }
+                        Some(cell) => owns(cell, s.id, s.distance, len, health),
+                        None => true,
+                    };
+                    if better {
+                        best = Some(FCell::Owned {
+                            id: s.id,
+                            health,
+                            len,
+                            distance: s.distance,
+                        });
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Lowest distance at which the label of the cell `i` might change.
+    fn lower_bound(&self, grid: &Grid, i: usize) -> Option<usize> {
+        let p = Vec2D::new(
+            (i % self.flood_fill.width) as _,
+            (i / self.flood_fill.width) as _,
+        );
+        let own = match self.flood_fill.cells[i] {
+            FCell::Owned { distance, .. } => Some(distance as usize),
+            _ => None,
+        };
+        self.neighbours(grid, p)
+            .filter_map(|q| {
+                if self.head_ids[q] != 0 {
+                    Some(0)
+                } else if let FCell::Owned { distance, .. } = self.flood_fill.cells[q] {
+                    Some(distance as usize + 1)
+                } else {
+                    None
+                }
+            })
+            .chain(own)
+            .min()
+    }
+
+    /// Recomputes the neighbours of the changed cell `i` at `level`.
+    fn notify(&mut self, grid: &Grid, i: usize, level: usize) {
+        let p = Vec2D::new(
+            (i % self.flood_fill.width) as _,
+            (i / self.flood_fill.width) as _,
+        );
+        for d in Direction::all() {
+            let q = grid.apply(p, d);
+            if self.flood_fill.has(q) {
+                let q = self.flood_fill.index(q);
+                if self.done[q] != self.generation {
+                    self.push(q, level);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, i: usize, level: usize) {
+        if self.buckets.len() <= level {
+            self.buckets.resize_with(level + 1, Vec::new);
+        }
+        self.buckets[level].push(i as u32);
+    }
+
+    /// Indices of the neighbours of `p` on the board.
+    fn neighbours<'a>(&'a self, grid: &'a Grid, p: Vec2D) -> impl Iterator<Item = usize> + 'a {
+        Direction::all().into_iter().filter_map(move |d| {
+            let q = grid.apply(p, d);
+            self.flood_fill.has(q).then(|| self.flood_fill.index(q))
+        })
+    }
+}
+
 impl Index<Vec2D> for FloodFill {
     type Output = FCell;
 
     fn index(&self, p: Vec2D) -> &Self::Output {
-        debug_assert!(p.within(self.width, self.height));
-        &self.cells[p.x as usize % self.width + p.y as usize * self.width]
+        &self.cells[self.index(p)]
     }
 }
 
 impl IndexMut<Vec2D> for FloodFill {
     fn index_mut(&mut self, p: Vec2D) -> &mut Self::Output {
-        debug_assert!(p.within(self.width, self.height));
-        &mut self.cells[p.x as usize % self.width + p.y as usize * self.width]
+        let i = self.index(p);
+        &mut self.cells[i]
     }
 }
 
@@ -296,8 +820,9 @@ mod test {
         let grid = Grid::new(11, 11);
 
         let mut floodfill = FloodFill::new(grid.width, grid.height);
-        floodfill.flood(
+        floodfill.flood::<false>(
             &grid,
+            14,
             [SnakePos::new(Vec2D::new(0, 0), 0, 0, 0, 3, 100)].into_iter(),
         );
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
@@ -305,8 +830,9 @@ mod test {
 
         let grid = Grid::new(11, 11);
         floodfill.clear();
-        floodfill.flood(
+        floodfill.flood::<false>(
             &grid,
+            14,
             [
                 SnakePos::new(Vec2D::new(0, 0), 0, 0, 0, 4, 100),
                 SnakePos::new(Vec2D::new(10, 10), 1, 0, 0, 3, 100),
@@ -318,8 +844,9 @@ mod test {
         assert_eq!(floodfill.count_space(0), 66);
         let grid = Grid::new(11, 11);
         floodfill.clear();
-        floodfill.flood(
+        floodfill.flood::<false>(
             &grid,
+            14,
             [
                 SnakePos::new(Vec2D::new(5, 5), 0, 0, 0, 2, 100),
                 SnakePos::new(Vec2D::new(10, 10), 1, 0, 0, 3, 100),
@@ -332,6 +859,37 @@ mod test {
         assert_eq!(floodfill.count_space(0), 61);
     }
 
+    #[test]
+    fn flood_head_wrapped() {
+        logging();
+        use super::*;
+        let mut grid = Grid::new(11, 11);
+        grid.wrapped = true;
+
+        let mut floodfill = FloodFill::new(grid.width, grid.height);
+        floodfill.flood::<false>(
+            &grid,
+            14,
+            [SnakePos::new(Vec2D::new(0, 0), 0, 0, 0, 3, 100)].into_iter(),
+        );
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 11 * 11);
+
+        floodfill.clear();
+        floodfill.flood::<false>(
+            &grid,
+            14,
+            [
+                SnakePos::new(Vec2D::new(0, 0), 0, 0, 0, 4, 100),
+                SnakePos::new(Vec2D::new(10, 10), 1, 0, 0, 3, 100),
+            ]
+            .iter()
+            .cloned(),
+        );
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 87);
+    }
+
     #[test]
     fn flood_snakes_follow_tail() {
         use super::*;
@@ -355,7 +913,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
@@ -377,12 +935,43 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
     }
 
+    #[test]
+    fn flood_snakes_constrictor() {
+        use super::*;
+        use crate::game::Game;
+        use crate::rules::GameMode;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            > > > v . . . . . . .
+            ^ . . v . . . . . . .
+            ^ 0 < < . . . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Constrictor);
+
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 2);
+    }
+
     #[test]
     fn flood_snakes_bite_tail() {
         use super::*;
@@ -406,12 +995,43 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 4);
     }
 
+    #[test]
+    fn flood_snakes_wrapped() {
+        use super::*;
+        use crate::game::Game;
+        use crate::rules::GameMode;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            v . . . . . . . . . .
+            v . . . . . . . . . .
+            v . . . . . . . . . .
+            > > > v . . . . . . .
+            . . . v . . . . . . .
+            . 0 < < . . . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Wrapped);
+
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 11 * 11);
+    }
+
     #[test]
     fn flood_snakes_bite_food() {
         use super::*;
@@ -435,7 +1055,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
@@ -457,7 +1077,7 @@ mod test {
         .unwrap();
 
         floodfill.clear();
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 1);
     }
@@ -485,7 +1105,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 24);
     }
@@ -514,7 +1134,7 @@ mod test {
         game.snakes[0].health = 6;
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 59);
     }
@@ -548,7 +1168,7 @@ mod test {
         }
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 96);
     }
@@ -577,7 +1197,7 @@ mod test {
         info!("{:?}", game);
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(29, floodfill.count_space(0));
 
@@ -599,8 +1219,126 @@ mod test {
         info!("{:?}", game);
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(35, floodfill.count_space(0));
     }
+
+    #[test]
+    fn flood_snakes_obstacle() {
+        use super::*;
+        use crate::game::Game;
+        use crate::relevance::OpponentPruning;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . v . .
+            . . v . .
+            . 0 v . .
+            . ^ v . .
+            . ^ 1 . ."#,
+        )
+        .unwrap();
+
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        // The enemy tail is vacating in time
+        assert!(matches!(
+            floodfill[Vec2D::new(3, 4)],
+            FCell::Owned { id: 0, .. }
+        ));
+
+        // The pruned enemy is a permanent wall
+        OpponentPruning::Nearest(0).apply(&mut game);
+        assert_eq!(game.snakes.len(), 1);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 10);
+    }
+
+    #[test]
+    fn workspace() {
+        use super::*;
+        use crate::game::Game;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . 0 . . .
+            . ^ . . .
+            . ^ . 1 .
+            . . . ^ ."#,
+        )
+        .unwrap();
+        let mut expected = FloodFill::new(5, 5);
+        expected.flood_snakes(&game);
+
+        let space = FloodFill::with_workspace(7, 7, |outer| {
+            // Nested calls do not share the buffers
+            FloodFill::with_workspace(5, 5, |inner| {
+                inner.flood_snakes(&game);
+                assert!(inner.cells == expected.cells);
+            });
+            outer.resize(5, 5);
+            outer.flood_snakes(&game);
+            outer.count_space(0)
+        });
+        assert_eq!(space, expected.count_space(0));
+        let reused = FloodFill::with_workspace(5, 5, |f| f.cells.len());
+        assert_eq!(reused, 25);
+    }
+
+    #[test]
+    fn incremental() {
+        use super::*;
+        use crate::game::Outcome;
+        use crate::rules::GameMode;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::seq::IteratorRandom;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut floodfill = FloodFill::new(0, 0);
+        let mut incremental = IncrementalFloodFill::new(0, 0);
+        let (mut turns, mut repaired) = (0, 0);
+        for (i, mode) in [
+            GameMode::Standard,
+            GameMode::Royale,
+            GameMode::Wrapped,
+            GameMode::Constrictor,
+        ]
+        .into_iter()
+        .cycle()
+        .take(24)
+        .enumerate()
+        {
+            let mut game = init_game(11, 11, 2 + i % 7, &mut rng);
+            game.set_mode(mode);
+            game.settings.shrink_turns = 10;
+            while game.outcome() == Outcome::None {
+                floodfill.resize(game.grid.width, game.grid.height);
+                floodfill.flood_snakes(&game);
+                repaired += incremental.update(&game) as usize;
+                turns += 1;
+                assert!(
+                    incremental.flood_fill().cells == floodfill.cells,
+                    "{game:?}\nexpected {floodfill:?}\nincremental {:?}",
+                    incremental.flood_fill()
+                );
+
+                let moves: Vec<_> = (0..game.snakes.len())
+                    .map(|i| (game.valid_moves(i as u8).choose(&mut rng)).unwrap_or(Direction::Up))
+                    .collect();
+                game.step(&moves);
+                game.rules().update_board(&mut game, &mut rng);
+            }
+        }
+        info!("repaired {repaired} of {turns}");
+        assert!(repaired > turns / 2);
+    }
 }
@@ -2,6 +2,10 @@ pub use snork_engine::*;
 
 // Exported to be accessable in benchmarks
 pub mod agents;
+pub mod bitflood;
 pub mod floodfill;
+pub mod nn;
 pub mod search;
 pub mod simulate;
+pub mod territory;
+pub mod trap;
@@ -0,0 +1,625 @@
+//! # Value and Policy Networks
+//!
+//! Small convolutional networks that evaluate game states on the CPU.
+//!
+//! The board is encoded into input planes, which are processed by 3x3
+//! convolutions with ReLU activations.
+//! The resulting feature planes are averaged over the board, so that the
+//! same weights can be used for every board size.
+//! The averages and some scalar features are then reduced by fully connected
+//! layers to a single value in the range [-1, 1], which estimates the outcome
+//! for the agent (id 0), or to the probabilities of its four moves.
+//!
+//! Without convolutions, the network is a plain MLP on the averaged input
+//! planes and scalars.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::env::{v2, Direction};
+use crate::floodfill::{FCell, FloodFill};
+use crate::game::Game;
+use crate::grid::CellT;
+use crate::search::{Policy, UniformPolicy};
+
+/// Number of input planes.
+///
+/// 0. own head
+/// 1. own body, from 1 at the head towards 0 at the tail
+/// 2. enemy heads
+/// 3. enemy bodies, like the own body
+/// 4. food
+/// 5. hazards
+/// 6. cells owned by the agent (floodfill)
+/// 7. cells owned by the enemies (floodfill)
+pub const PLANES: usize = 8;
+/// Number of scalar inputs: own health, own length, length of the longest
+/// enemy and number of living enemies.
+pub const SCALARS: usize = 4;
+
+/// 3x3 convolution with zero padding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conv {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Kernels in the order `[outputs][inputs][3][3]`
+    pub weights: Vec<f32>,
+    pub bias: Vec<f32>,
+}
+
+impl Conv {
+    /// Convolves the `input` planes and applies ReLU.
+    fn forward(&self, input: &[f32], output: &mut Vec<f32>, width: usize, height: usize) {
+        let area = width * height;
+        output.clear();
+        output.resize(self.outputs * area, 0.0);
+
+        for (o, out) in output.chunks_exact_mut(area).enumerate() {
+            out.fill(self.bias[o]);
+            for (i, inp) in input.chunks_exact(area).enumerate() {
+                let kernel = &self.weights[(o * self.inputs + i) * 9..][..9];
+                for (k, &w) in kernel.iter().enumerate() {
+                    if w == 0.0 {
+                        continue;
+                    }
+                    let dy = k / 3;
+                    let dx = k % 3;
+                    // Output rows and columns whose source is on the board
+                    let ys = 1usize.saturating_sub(dy)..(height + 1 - dy).min(height);
+                    let (x0, x1) = (1usize.saturating_sub(dx), (width + 1 - dx).min(width));
+                    for y in ys {
+                        let src = &inp[(y + dy - 1) * width + x0 + dx - 1..][..x1 - x0];
+                        let dst = &mut out[y * width + x0..][..x1 - x0];
+                        for (o, i) in dst.iter_mut().zip(src) {
+                            *o += w * i;
+                        }
+                    }
+                }
+            }
+            out.iter_mut().for_each(|v| *v = v.max(0.0));
+        }
+    }
+}
+
+/// Fully connected layer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dense {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Weights in the order `[outputs][inputs]`
+    pub weights: Vec<f32>,
+    pub bias: Vec<f32>,
+}
+
+impl Dense {
+    fn forward(&self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend(
+            self.weights
+                .chunks_exact(self.inputs)
+                .zip(&self.bias)
+                .map(|(row, b)| b + row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>()),
+        );
+    }
+}
+
+/// Encoded game state from the perspective of the agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub width: usize,
+    pub height: usize,
+    /// Planes in the order `[PLANES][height][width]`
+    pub planes: Vec<f32>,
+    pub scalars: [f32; SCALARS],
+}
+
+impl Input {
+    /// Encodes the game, the `flood_fill` has to be filled with
+    /// [FloodFill::flood_snakes].
+    #[must_use]
+    pub fn encode(game: &Game, flood_fill: &FloodFill) -> Self {
+        let (width, height) = (game.grid.width, game.grid.height);
+        let area = width * height;
+        let mut planes = vec![0.0f32; PLANES * area];
+
+        for (i, snake) in game.snakes.iter().enumerate().filter(|(_, s)| s.alive()) {
+            let (head, body) = if i == 0 { (0, 1) } else { (2, 3) };
+            let len = snake.body.len() as f32;
+            for (j, p) in snake.body.iter().rev().enumerate() {
+                let cell = p.x as usize + p.y as usize * width;
+                planes[body * area + cell] = planes[body * area + cell].max((len - j as f32) / len);
+            }
+            let p = snake.head();
+            planes[head * area + p.x as usize + p.y as usize * width] = 1.0;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let cell = x + y * width;
+                let p = v2(x as _, y as _);
+                let grid = game.grid[p];
+                if grid.t == CellT::Food {
+                    planes[4 * area + cell] = 1.0;
+                }
+                if grid.hazard {
+                    planes[5 * area + cell] = 1.0;
+                }
+                // Obstacles are enemy bodies that are never freed
+                if grid.t == CellT::Owned
+                    && planes[area + cell] == 0.0
+                    && planes[3 * area + cell] == 0.0
+                {
+                    planes[3 * area + cell] = 1.0;
+                }
+                if let FCell::Owned { id, .. } = flood_fill[p] {
+                    planes[if id == 0 { 6 } else { 7 } * area + cell] = 1.0;
+                }
+            }
+        }
+
+        let own = &game.snakes[0];
+        let enemies = game.snakes.iter().skip(1).filter(|s| s.alive());
+        let longest = enemies.clone().map(|s| s.body.len()).max().unwrap_or(0);
+        let scalars = [
+            own.health as f32 / 100.0,
+            own.body.len() as f32 / area as f32,
+            longest as f32 / area as f32,
+            enemies.count() as f32 / 3.0,
+        ];
+
+        Self {
+            width,
+            height,
+            planes,
+            scalars,
+        }
+    }
+}
+
+/// Layers of a network, whose weights are stored as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// Convolutions, applied one after another
+    pub conv: Vec<Conv>,
+    /// Fully connected layers, the last one produces the outputs
+    pub dense: Vec<Dense>,
+}
+
+impl Network {
+    /// Creates a network with random weights.
+    ///
+    /// `channels` are the outputs of the convolutions and `hidden` the
+    /// outputs of the fully connected layers before the final one.
+    #[must_use]
+    pub fn random(
+        channels: &[usize],
+        hidden: &[usize],
+        outputs: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
+        // He initialization
+        let mut init = |inputs: usize, len: usize| -> Vec<f32> {
+            let limit = (6.0 / inputs as f32).sqrt();
+            (0..len).map(|_| rng.gen_range(-limit..limit)).collect()
+        };
+
+        let mut inputs = PLANES;
+        let mut conv = Vec::new();
+        for &outputs in channels {
+            conv.push(Conv {
+                inputs,
+                outputs,
+                weights: init(inputs * 9, outputs * inputs * 9),
+                bias: vec![0.0; outputs],
+            });
+            inputs = outputs;
+        }
+
+        let mut inputs = inputs + SCALARS;
+        let mut dense = Vec::new();
+        for &outputs in hidden.iter().chain(&[outputs]) {
+            dense.push(Dense {
+                inputs,
+                outputs,
+                weights: init(inputs, outputs * inputs),
+                bias: vec![0.0; outputs],
+            });
+            inputs = outputs;
+        }
+        Self { conv, dense }
+    }
+
+    /// Loads the weights and checks that the network has the given number
+    /// of `outputs`.
+    fn load(path: &Path, outputs: usize) -> io::Result<Self> {
+        let network: Self = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        network
+            .validate(outputs)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(network)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Checks that the shapes of the layers fit together.
+    pub fn validate(&self, outputs: usize) -> Result<(), String> {
+        let mut inputs = PLANES;
+        for (i, layer) in self.conv.iter().enumerate() {
+            if layer.inputs != inputs
+                || layer.weights.len() != layer.outputs * layer.inputs * 9
+                || layer.bias.len() != layer.outputs
+            {
+                return Err(format!("invalid shape of conv layer {i}"));
+            }
+            inputs = layer.outputs;
+        }
+        let mut inputs = inputs + SCALARS;
+        for (i, layer) in self.dense.iter().enumerate() {
+            if layer.inputs != inputs
+                || layer.weights.len() != layer.outputs * layer.inputs
+                || layer.bias.len() != layer.outputs
+            {
+                return Err(format!("invalid shape of dense layer {i}"));
+            }
+            inputs = layer.outputs;
+        }
+        if inputs != outputs || self.dense.is_empty() {
+            return Err(format!("the last dense layer needs {outputs} outputs"));
+        }
+        Ok(())
+    }
+
+    /// Computes the outputs of the last layer, without activation.
+    pub fn forward(&self, input: &Input) -> Vec<f32> {
+        let area = input.width * input.height;
+        let mut planes = input.planes.clone();
+        let mut buffer = Vec::new();
+        for layer in &self.conv {
+            layer.forward(&planes, &mut buffer, input.width, input.height);
+            std::mem::swap(&mut planes, &mut buffer);
+        }
+
+        // Global average pooling
+        let mut features: Vec<f32> = planes
+            .chunks_exact(area)
+            .map(|p| p.iter().sum::<f32>() / area as f32)
+            .collect();
+        features.extend_from_slice(&input.scalars);
+
+        for (i, layer) in self.dense.iter().enumerate() {
+            layer.forward(&features, &mut buffer);
+            std::mem::swap(&mut features, &mut buffer);
+            if i + 1 < self.dense.len() {
+                features.iter_mut().for_each(|v| *v = v.max(0.0));
+            }
+        }
+        features
+    }
+}
+
+/// Value network with a single output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ValueNetwork(pub Network);
+
+impl ValueNetwork {
+    /// Creates a network with random weights, see [Network::random].
+    #[must_use]
+    pub fn random(channels: &[usize], hidden: &[usize], rng: &mut impl Rng) -> Self {
+        Self(Network::random(channels, hidden, 1, rng))
+    }
+
+    /// Loads and validates the weights.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Network::load(path, 1).map(Self)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.0.save(path)
+    }
+
+    /// Checks that the shapes of the layers fit together.
+    pub fn validate(&self) -> Result<(), String> {
+        self.0.validate(1)
+    }
+
+    /// Estimates the outcome for the agent in the range [-1, 1].
+    pub fn forward(&self, input: &Input) -> f32 {
+        self.0.forward(input)[0].tanh()
+    }
+
+    /// Encodes and evaluates the game.
+    pub fn eval(&self, game: &Game) -> f32 {
+        let input = FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+            flood_fill.flood_snakes(game);
+            Input::encode(game, flood_fill)
+        });
+        self.forward(&input)
+    }
+}
+
+/// Policy network with one output per [Direction].
+///
+/// The outputs are the logits of the moves of the agent, which are
+/// normalized with a softmax over the valid moves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PolicyNetwork(pub Network);
+
+impl PolicyNetwork {
+    /// Creates a network with random weights, see [Network::random].
+    #[must_use]
+    pub fn random(channels: &[usize], hidden: &[usize], rng: &mut impl Rng) -> Self {
+        Self(Network::random(channels, hidden, 4, rng))
+    }
+
+    /// Loads and validates the weights.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Network::load(path, 4).map(Self)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.0.save(path)
+    }
+
+    /// Checks that the shapes of the layers fit together.
+    pub fn validate(&self) -> Result<(), String> {
+        self.0.validate(4)
+    }
+
+    /// Logits of the moves of the agent.
+    pub fn forward(&self, input: &Input) -> [f32; 4] {
+        let outputs = self.0.forward(input);
+        [outputs[0], outputs[1], outputs[2], outputs[3]]
+    }
+}
+
+impl Policy for PolicyNetwork {
+    /// Encodes the game from the perspective of the `snake`, by swapping it
+    /// with the agent.
+    fn policy(&self, game: &Game, snake: u8) -> [f64; 4] {
+        let mut game = game.clone();
+        game.snakes.swap(0, snake as usize);
+        let input = FloodFill::with_workspace(game.grid.width, game.grid.height, |flood_fill| {
+            flood_fill.flood_snakes(&game);
+            Input::encode(&game, flood_fill)
+        });
+        let logits = self.forward(&input);
+
+        let valid = Direction::all().map(|d| game.move_is_valid(0, d));
+        if !valid.contains(&true) {
+            return UniformPolicy.policy(&game, 0);
+        }
+        let max = (logits.iter().zip(valid))
+            .filter(|(_, v)| *v)
+            .map(|(l, _)| *l)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let exp = Direction::all().map(|d| {
+            if valid[d as usize] {
+                ((logits[d as usize] - max) as f64).exp()
+            } else {
+                0.0
+            }
+        });
+        let sum: f64 = exp.iter().sum();
+        exp.map(|e| e / sum)
+    }
+}
+
+/// Policy network that is loaded from its path when the config is parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct PolicyModel {
+    path: PathBuf,
+    network: Arc<PolicyNetwork>,
+}
+
+impl PolicyModel {
+    #[must_use]
+    pub fn new(network: PolicyNetwork) -> Self {
+        Self {
+            path: PathBuf::new(),
+            network: Arc::new(network),
+        }
+    }
+}
+
+impl TryFrom<PathBuf> for PolicyModel {
+    type Error = String;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let network = PolicyNetwork::load(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self {
+            path,
+            network: Arc::new(network),
+        })
+    }
+}
+
+impl From<PolicyModel> for PathBuf {
+    fn from(model: PolicyModel) -> Self {
+        model.path
+    }
+}
+
+impl Policy for PolicyModel {
+    fn policy(&self, game: &Game, snake: u8) -> [f64; 4] {
+        self.network.policy(game, snake)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn encode() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . 0 . o .
+            . ^ . . .
+            . ^ . 1 .
+            . . . ^ ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        let mut flood_fill = FloodFill::new(5, 5);
+        flood_fill.flood_snakes(&game);
+        let input = Input::encode(&game, &flood_fill);
+
+        let plane = |i: usize, x: usize, y: usize| input.planes[i * 25 + x + y * 5];
+        assert_eq!(plane(0, 1, 3), 1.0);
+        assert_eq!(plane(1, 1, 3), 1.0);
+        assert!(plane(1, 1, 1) > 0.0 && plane(1, 1, 1) < plane(1, 1, 2));
+        assert_eq!(plane(2, 3, 1), 1.0);
+        assert_eq!(plane(3, 3, 0), 2.0 / 3.0);
+        assert_eq!(plane(4, 3, 3), 1.0);
+        assert_eq!(plane(6, 0, 4), 1.0);
+        assert_eq!(plane(7, 4, 0), 1.0);
+        assert_eq!(input.scalars[3], 1.0 / 3.0);
+    }
+
+    #[test]
+    fn forward() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . o . .
+            . ^ . . . 1 .
+            . . . . . ^ .
+            . . . . . ^ .
+            . . . . . . ."#,
+        )
+        .unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let network = ValueNetwork::random(&[8, 8], &[16], &mut rng);
+        network.validate().unwrap();
+        let value = network.eval(&game);
+        info!("{value}");
+        assert!((-1.0..=1.0).contains(&value));
+
+        // Plain MLP
+        let mlp = ValueNetwork::random(&[], &[8], &mut rng);
+        mlp.validate().unwrap();
+        assert!((-1.0..=1.0).contains(&mlp.eval(&game)));
+
+        // A single kernel that copies the input planes
+        let mut conv = ValueNetwork::random(&[PLANES], &[], &mut rng)
+            .0
+            .conv
+            .remove(0);
+        conv.weights.fill(0.0);
+        for i in 0..PLANES {
+            conv.weights[(i * PLANES + i) * 9 + 4] = 1.0;
+        }
+        let mut flood_fill = FloodFill::new(7, 7);
+        flood_fill.flood_snakes(&game);
+        let input = Input::encode(&game, &flood_fill);
+        let mut output = Vec::new();
+        conv.forward(&input.planes, &mut output, 7, 7);
+        assert_eq!(output, input.planes);
+
+        // Shifts the planes one cell to the right
+        conv.weights.fill(0.0);
+        for i in 0..PLANES {
+            conv.weights[(i * PLANES + i) * 9 + 3] = 1.0;
+        }
+        conv.forward(&input.planes, &mut output, 7, 7);
+        assert_eq!(output[1 + 3 * 7], input.planes[3 * 7]);
+        assert_eq!(output[3 * 7], 0.0);
+    }
+
+    #[test]
+    fn load() {
+        use super::*;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let network = ValueNetwork::random(&[4], &[8], &mut rng);
+        let path = std::env::temp_dir().join("snork_value_network.json");
+        network.save(&path).unwrap();
+        assert_eq!(ValueNetwork::load(&path).unwrap(), network);
+
+        let mut invalid = network.clone();
+        invalid.0.dense[0].bias.pop();
+        assert!(invalid.validate().is_err());
+        invalid.save(&path).unwrap();
+        assert!(ValueNetwork::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn policy() {
+        use super::*;
+        use crate::agents::Agent;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            0 . . . . . .
+            ^ . . . . . .
+            ^ < . . o . .
+            . . . . . 1 .
+            . . . . . ^ .
+            . . . . . ^ .
+            . . . . . . ."#,
+        )
+        .unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(3);
+        let network = PolicyNetwork::random(&[8], &[8], &mut rng);
+        network.validate().unwrap();
+        // Only one valid move
+        assert_eq!(network.policy(&game, 0), [0.0, 1.0, 0.0, 0.0]);
+        let p = network.policy(&game, 1);
+        info!("{p:?}");
+        assert_eq!(p[Direction::Down as usize], 0.0);
+        assert!((p.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(p.iter().filter(|&&p| p > 0.0).count() == 3);
+
+        // Value networks are no policy networks
+        let path = std::env::temp_dir().join("snork_policy_network.json");
+        ValueNetwork::random(&[4], &[], &mut rng)
+            .save(&path)
+            .unwrap();
+        assert!(PolicyNetwork::load(&path).is_err());
+
+        network.save(&path).unwrap();
+        assert_eq!(PolicyNetwork::load(&path).unwrap(), network);
+        let config = format!(r#"{{"Flood":{{"policy":{:?}}}}}"#, path);
+        let agent: Agent = config.parse().unwrap();
+        info!("{agent}");
+        assert!(agent
+            .to_string()
+            .contains(&format!(r#""policy":{:?}"#, path)));
+        std::fs::remove_file(&path).unwrap();
+        assert!(config.parse::<Agent>().is_err());
+    }
+}
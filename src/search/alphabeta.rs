@@ -1,66 +1,164 @@
 use std::sync::Arc;
 
-use super::{Heuristic, DRAW, LOSS, WIN};
+use super::{
+    aborted, first_move, ordered_by_prior, prior, stopped, Actions, Bound, Heuristic, MoveOrdering,
+    SearchBudget, SearchGame, TTEntry, TranspositionTable, DRAW, LOSS, WIN,
+};
 use crate::env::*;
-use crate::game::{Game, Outcome};
+use crate::game::{Game, Outcome, MAX_SNAKES};
 
 use async_recursion::async_recursion;
 
+/// Narrows the search window with the stored result of the position.
+///
+/// Returns the stored result if it is exact or the window is empty.
+pub(super) fn tt_cutoff(
+    tt: Option<&TranspositionTable>,
+    hash: u64,
+    depth: usize,
+    alpha: &mut f64,
+    beta: &mut f64,
+) -> Option<(Direction, f64)> {
+    let entry = tt?.get(hash)?;
+    if (entry.depth as usize) < depth {
+        return None;
+    }
+    match entry.bound {
+        Bound::Exact => return Some((entry.best, entry.value)),
+        Bound::Lower => *alpha = alpha.max(entry.value),
+        Bound::Upper => *beta = beta.min(entry.value),
+    }
+    (*alpha >= *beta).then_some((entry.best, entry.value))
+}
+
+/// Stores the result of a node, searched with the window `alpha..beta`.
+pub(super) fn tt_store_bound(
+    tt: Option<&TranspositionTable>,
+    hash: u64,
+    depth: usize,
+    (best, value): (Direction, f64),
+    alpha: f64,
+    beta: f64,
+) {
+    if let Some(tt) = tt {
+        let bound = if value <= alpha {
+            Bound::Upper
+        } else if value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        tt.insert(
+            hash,
+            TTEntry {
+                value,
+                depth: depth.min(u8::MAX as _) as _,
+                bound,
+                best,
+            },
+        );
+    }
+}
+
 pub async fn async_alphabeta(
     game: &Game,
     depth: usize,
     heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
 ) -> (Direction, f64) {
-    async_alphabeta_rec(game, [Direction::Up; 4], depth, 0, LOSS, WIN, heuristic).await
+    let mut game = game.clone();
+    game.rehash();
+    async_alphabeta_rec(
+        &mut game,
+        [Direction::Up; MAX_SNAKES],
+        depth,
+        0,
+        LOSS,
+        WIN,
+        heuristic,
+        tt,
+        budget,
+    )
+    .await
 }
 
 /// # WARNING
 /// This version is very slow, even slower than the synchronous alphabeta
 /// and much slower than multithreaded max n
+///
+/// The moves are made and unmade in place, only the subtrees of the max
+/// nodes, which are searched by separate tasks, get their own copy.
 #[async_recursion]
+#[allow(clippy::too_many_arguments)]
 async fn async_alphabeta_rec(
-    game: &Game,
-    actions: [Direction; 4],
+    game: &mut Game,
+    actions: Actions,
     depth: usize,
     ply: usize,
     mut alpha: f64,
     mut beta: f64,
     heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
 ) -> (Direction, f64) {
+    if aborted(budget.as_deref()) {
+        return (Direction::Up, DRAW);
+    }
+
     if ply == game.snakes.len() {
-        let mut game = game.clone();
-        game.step(&actions);
-        match game.outcome() {
-            Outcome::Winner(0) => return (Direction::Up, WIN),
-            Outcome::Winner(_) => return (Direction::Up, LOSS),
-            Outcome::Match => return (Direction::Up, DRAW),
-            Outcome::None => {}
+        let delta = game.make_move(&actions);
+        let value = match game.outcome() {
+            Outcome::Winner(0) => (Direction::Up, WIN),
+            Outcome::Winner(_) => (Direction::Up, LOSS),
+            Outcome::Match => (Direction::Up, DRAW),
+            Outcome::None if depth == 0 => (Direction::Up, heuristic.eval(game)),
+            Outcome::None => {
+                async_alphabeta_rec(
+                    game,
+                    [Direction::Up; MAX_SNAKES],
+                    depth - 1,
+                    0,
+                    alpha,
+                    beta,
+                    heuristic,
+                    tt,
+                    budget,
+                )
+                .await
+            }
+        };
+        game.unmake_move(delta);
+        value
+    } else if ply == 0 {
+        let hash = game.zobrist();
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        if let Some(value) = tt_cutoff(tt.as_deref(), hash, depth, &mut alpha, &mut beta) {
+            return value;
         }
 
-        if depth == 0 {
-            (Direction::Up, heuristic.eval(&game))
-        } else {
-            async_alphabeta_rec(
-                &game,
-                [Direction::Up; 4],
-                depth - 1,
-                0,
-                alpha,
-                beta,
-                heuristic,
-            )
-            .await
-        }
-    } else if ply == 0 {
         let mut value = (Direction::Up, LOSS);
 
         let mut futures = [None, None, None, None];
         for d in Direction::all() {
-            let game = game.clone();
+            let mut game = game.clone();
             let heuristic = heuristic.clone();
-            let actions = [d, Direction::Up, Direction::Up, Direction::Up];
+            let tt = tt.clone();
+            let budget = budget.clone();
+            let actions = first_move(d);
             futures[d as u8 as usize] = Some(tokio::task::spawn(async move {
-                async_alphabeta_rec(&game, actions, depth, ply + 1, alpha, beta, heuristic).await
+                async_alphabeta_rec(
+                    &mut game,
+                    actions,
+                    depth,
+                    ply + 1,
+                    alpha,
+                    beta,
+                    heuristic,
+                    tt,
+                    budget,
+                )
+                .await
             }));
         }
 
@@ -77,10 +175,17 @@ async fn async_alphabeta_rec(
                 }
             }
         }
+        if !stopped(budget.as_deref()) {
+            tt_store_bound(tt.as_deref(), hash, depth, value, alpha_orig, beta_orig);
+        }
         value
     } else {
         let mut value = (Direction::Up, WIN);
-        for d in Direction::all() {
+        let moves = match prior(&*heuristic, game, ply as u8, depth == 0) {
+            Some(prior) => ordered_by_prior(None, &prior),
+            None => Direction::all(),
+        };
+        for d in moves {
             let mut actions = actions;
             actions[ply] = d;
             let newval = async_alphabeta_rec(
@@ -91,6 +196,8 @@ async fn async_alphabeta_rec(
                 alpha,
                 beta,
                 heuristic.clone(),
+                tt.clone(),
+                budget.clone(),
             )
             .await;
             if newval.1 < value.1 {
@@ -105,73 +212,151 @@ async fn async_alphabeta_rec(
     }
 }
 
+/// State that is shared by all nodes of the alpha-beta search.
+struct AlphaBeta<'a> {
+    heuristic: &'a dyn Heuristic,
+    tt: Option<&'a TranspositionTable>,
+    budget: Option<&'a SearchBudget>,
+    ordering: &'a mut MoveOrdering,
+    /// Depth of the root
+    depth: usize,
+}
+
 /// Alpha-Beta tree search.
 ///
 /// @see https://en.wikipedia.org/wiki/Alpha%E2%80%93beta_pruning
 /// - Assumes the maximizing agent has id 0
-/// - Assumes only two snakes are alive
-pub fn alphabeta(game: &Game, depth: usize, heuristic: &dyn Heuristic) -> (Direction, f64) {
-    alphabeta_rec(game, [Direction::Up; 4], depth, 0, LOSS, WIN, heuristic)
+/// - Assumes only two snakes are alive, [paranoid] generalizes it to more snakes
+///
+/// The bounds of the max nodes are stored in the optional transposition table.
+/// The move `ordering` should be reused for iterative deepening, so that
+/// the principal variation of the previous iteration is searched first.
+/// The search is aborted if the optional `budget` is exhausted.
+pub fn alphabeta<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+    ordering: &mut MoveOrdering,
+) -> (Direction, f64) {
+    let mut game = game.clone();
+    game.rehash();
+    ordering.start_iteration();
+    let mut search = AlphaBeta {
+        heuristic,
+        tt,
+        budget,
+        ordering,
+        depth,
+    };
+    let result = alphabeta_rec(
+        &mut search,
+        &mut game,
+        [Direction::Up; MAX_SNAKES],
+        depth,
+        0,
+        LOSS,
+        WIN,
+    );
+    // Keep the principal variation of the last complete iteration
+    if !stopped(budget) {
+        search.ordering.finish_iteration();
+    }
+    result
 }
 
-fn alphabeta_rec(
-    game: &Game,
-    actions: [Direction; 4],
+fn alphabeta_rec<G: SearchGame>(
+    search: &mut AlphaBeta,
+    game: &mut G,
+    actions: Actions,
     depth: usize,
     ply: usize,
     mut alpha: f64,
     mut beta: f64,
-    heuristic: &dyn Heuristic,
 ) -> (Direction, f64) {
-    if ply == game.snakes.len() {
-        let mut game = game.clone();
-        game.step(&actions);
-        match game.outcome() {
-            Outcome::Winner(0) => return (Direction::Up, WIN),
-            Outcome::Winner(_) => return (Direction::Up, LOSS),
-            Outcome::Match => return (Direction::Up, DRAW),
-            Outcome::None => {}
-        }
+    if aborted(search.budget) {
+        return (Direction::Up, DRAW);
+    }
 
-        if depth == 0 {
-            (Direction::Up, heuristic.eval(&game))
-        } else {
-            alphabeta_rec(
-                &game,
-                [Direction::Up; 4],
+    let snakes = game.snake_count();
+    // Number of moves from the root
+    let sply = (search.depth - depth) * snakes + ply;
+    search.ordering.leaf(sply);
+
+    if ply == snakes {
+        let delta = game.make_move(&actions);
+        let value = match game.outcome() {
+            Outcome::Winner(0) => (Direction::Up, WIN),
+            Outcome::Winner(_) => (Direction::Up, LOSS),
+            Outcome::Match => (Direction::Up, DRAW),
+            Outcome::None if depth == 0 => (Direction::Up, game.eval(search.heuristic)),
+            Outcome::None => alphabeta_rec(
+                search,
+                game,
+                [Direction::Up; MAX_SNAKES],
                 depth - 1,
                 0,
                 alpha,
                 beta,
-                heuristic,
-            )
-        }
+            ),
+        };
+        game.unmake_move(delta);
+        value
     } else if ply == 0 {
+        let hash = game.zobrist();
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        if let Some(value) = tt_cutoff(search.tt, hash, depth, &mut alpha, &mut beta) {
+            return value;
+        }
+        let tt_move = search.tt.and_then(|tt| tt.get(hash)).map(|e| e.best);
+
+        let prior = prior(search.heuristic, game, 0, depth == 0);
         let mut value = (Direction::Up, LOSS);
-        for d in Direction::all() {
+        for (i, d) in search
+            .ordering
+            .order(game, 0, sply, tt_move, prior)
+            .into_iter()
+            .enumerate()
+        {
             let mut actions = actions;
             actions[ply] = d;
-            let (_, outcome) = alphabeta_rec(game, actions, depth, ply + 1, alpha, beta, heuristic);
-            if outcome > value.1 {
+            search.ordering.enter(sply, d);
+            let (_, outcome) = alphabeta_rec(search, game, actions, depth, ply + 1, alpha, beta);
+            if outcome > value.1 || i == 0 {
                 value = (d, outcome);
+                search.ordering.update_pv(sply, d);
             }
             alpha = alpha.max(outcome);
             if alpha >= beta {
+                search.ordering.cutoff(game, 0, sply, depth, d);
                 break;
             }
         }
+        if !stopped(search.budget) {
+            tt_store_bound(search.tt, hash, depth, value, alpha_orig, beta_orig);
+        }
         value
     } else {
+        let prior = prior(search.heuristic, game, ply as u8, depth == 0);
         let mut value = (Direction::Up, WIN);
-        for d in Direction::all() {
+        for (i, d) in search
+            .ordering
+            .order(game, ply as u8, sply, None, prior)
+            .into_iter()
+            .enumerate()
+        {
             let mut actions = actions;
             actions[ply] = d;
-            let (_, outcome) = alphabeta_rec(game, actions, depth, ply + 1, alpha, beta, heuristic);
-            if outcome < value.1 {
+            search.ordering.enter(sply, d);
+            let (_, outcome) = alphabeta_rec(search, game, actions, depth, ply + 1, alpha, beta);
+            if outcome < value.1 || i == 0 {
                 value = (d, outcome);
+                search.ordering.update_pv(sply, d);
             }
             beta = beta.min(outcome);
             if alpha >= beta {
+                search.ordering.cutoff(game, ply as u8, sply, depth, d);
                 break;
             }
         }
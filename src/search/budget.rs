@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::game::Game;
+
+/// Number of nodes between checking the clock.
+const CHECK_INTERVAL: u64 = 64;
+
+/// Time and node limit of a search.
+///
+/// The budget is shared by all threads of a search, which count their nodes
+/// and abort as soon as it is exhausted or stopped.
+/// The results of an aborted search are incomplete and have to be discarded.
+#[derive(Debug)]
+pub struct SearchBudget {
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    stop: AtomicBool,
+    nodes: AtomicU64,
+}
+
+impl SearchBudget {
+    #[must_use]
+    pub fn new(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Self::unlimited()
+        }
+    }
+
+    /// Budget that ends `timeout` ms from now.
+    #[must_use]
+    pub fn from_timeout(timeout: u64) -> Self {
+        Self::new(Instant::now() + Duration::from_millis(timeout))
+    }
+
+    /// Budget that only ends if it is stopped.
+    #[must_use]
+    pub fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            max_nodes: None,
+            stop: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+        }
+    }
+
+    /// Additionally limits the number of nodes.
+    #[must_use]
+    pub fn with_max_nodes(mut self, max_nodes: u64) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Allocates the time in ms for the next move from the `timeout` of the
+    /// game and the network `latency`.
+    ///
+    /// Forced moves are played without searching, all other moves get the
+    /// whole remaining time.
+    pub fn allocate(timeout: u64, latency: u64, game: &Game) -> u64 {
+        if game.valid_moves(0).nth(1).is_none() {
+            0
+        } else {
+            timeout.saturating_sub(latency)
+        }
+    }
+
+    /// Aborts all searches that use this budget.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns if the budget has been stopped, without checking the limits.
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Checks the limits and returns if the search has to be aborted.
+    pub fn exhausted(&self) -> bool {
+        if self.stopped() {
+            return true;
+        }
+        let exhausted = self.deadline.is_some_and(|d| Instant::now() >= d)
+            || self.max_nodes.is_some_and(|max| self.nodes() >= max);
+        if exhausted {
+            self.stop();
+        }
+        exhausted
+    }
+
+    /// Counts the searched `nodes` and returns if the search has to be
+    /// aborted.
+    ///
+    /// The limits are only checked every [CHECK_INTERVAL] nodes.
+    pub fn count(&self, nodes: u64) -> bool {
+        let before = self.nodes.fetch_add(nodes, Ordering::Relaxed);
+        if self.stopped() {
+            return true;
+        }
+        if before / CHECK_INTERVAL != (before + nodes) / CHECK_INTERVAL {
+            return self.exhausted();
+        }
+        false
+    }
+
+    /// Number of searched nodes.
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the node and returns if the search has to be aborted.
+pub(super) fn aborted(budget: Option<&SearchBudget>) -> bool {
+    budget.is_some_and(|b| b.count(1))
+}
+
+/// Returns if the search has been aborted, without counting a node.
+pub(super) fn stopped(budget: Option<&SearchBudget>) -> bool {
+    budget.is_some_and(SearchBudget::stopped)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn node_limit() {
+        use super::*;
+        logging();
+
+        let budget = SearchBudget::unlimited().with_max_nodes(100);
+        let mut nodes = 0;
+        while !budget.count(1) {
+            nodes += 1;
+        }
+        info!("{budget:?}");
+        // Checked at the next interval
+        assert_eq!(nodes, 127);
+        assert!(budget.stopped());
+        assert!(budget.count(1));
+
+        let budget = SearchBudget::from_timeout(0);
+        assert!(!budget.stopped());
+        assert!(budget.exhausted());
+        assert!(budget.stopped());
+    }
+
+    #[test]
+    fn allocate() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . 0 . . .
+            . ^ . . .
+            . ^ . 1 .
+            . . . ^ ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        assert_eq!(SearchBudget::allocate(500, 100, &game), 400);
+        assert_eq!(SearchBudget::allocate(50, 100, &game), 0);
+
+        // Forced move
+        let game = Game::parse(
+            r#"
+            0 . . . .
+            ^ . . . .
+            ^ < . . .
+            . . . 1 .
+            . . . ^ ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        assert_eq!(SearchBudget::allocate(500, 100, &game), 0);
+    }
+}
@@ -1,111 +1,924 @@
 //! # Monte Carlo Tree Search
 //!
-//! Idea: use mcts with a fast agent to simulate games instead of random playouts
+//! Simultaneous-move MCTS with decoupled PUCT: every node has a separate
+//! bandit for each snake, which selects its move independently of the
+//! others. The selected moves form the joint move that leads to the child.
+//! The exploration of the bandits is weighted by the prior probabilities of
+//! the moves, which come from the policy of the heuristic or are uniform
+//! over the valid moves (see [Heuristic::policy]).
+//!
+//! Instead of random playouts, the rollouts use a fast agent that avoids
+//! dead ends and dangerous head-to-heads. They end after a fixed number of
+//! turns (horizon) and are evaluated by the heuristic from the perspective
+//! of every snake.
+//!
+//! The search can run on multiple threads, either with independent trees
+//! that are merged at the root ([Parallelism::Root]) or with a single shared
+//! tree and virtual loss ([Parallelism::Tree]).
+
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::env::Direction;
+use crate::game::{Delta, Game, Outcome, MAX_SNAKES};
+use crate::grid::CellT;
+
+use super::{Actions, Heuristic, Policy, SearchBudget, UniformPolicy};
+
+type Path = Vec<(usize, Actions)>;
+/// Prior probabilities, indexed by snake and direction.
+type Priors = [[f32; 4]; MAX_SNAKES];
+
+/// Number of iterations between checking the budget.
+const BATCH: usize = 16;
 
-use std::sync::Arc;
+/// How the search is distributed over multiple threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Parallelism {
+    /// Independent trees, whose root statistics are summed up
+    Root,
+    /// One shared tree, where pending iterations count as losses
+    /// (virtual loss) to spread the threads over different paths
+    #[default]
+    Tree,
+}
 
-use log::{info, warn};
-use mocats::UctPolicy;
-use tokio::time::Instant;
+/// Parameters of the search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MctsConfig {
+    /// Exploration constant of PUCT
+    pub exploration: f64,
+    /// Number of turns after the root, where the rollouts are evaluated
+    pub horizon: usize,
+    /// Number of search threads
+    pub threads: usize,
+    pub parallelism: Parallelism,
+}
 
-use crate::{env::Direction, game::Game};
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration: std::f64::consts::SQRT_2,
+            horizon: 8,
+            threads: 1,
+            parallelism: Parallelism::Tree,
+        }
+    }
+}
 
-use super::Heuristic;
+/// Result of a simulation, before it is normalized into rewards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Leaf {
+    /// The game is over
+    Over(Outcome),
+    /// Evaluations of the living snakes
+    Running([Option<f64>; MAX_SNAKES]),
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct Player(u8);
+/// Statistics of a move of a snake.
+#[derive(Debug, Clone, Copy, Default)]
+struct Stat {
+    visits: u32,
+    reward: f64,
+}
 
-impl mocats::Player for Player {}
+#[derive(Debug, Clone, Default)]
+struct Node {
+    visits: u32,
+    /// Bandit of each snake, indexed by snake and direction
+    stats: [[Stat; 4]; MAX_SNAKES],
+    /// Priors of the policy, uniform if not present
+    priors: Option<Priors>,
+    /// Children with their packed joint move
+    children: Vec<(u16, u32)>,
+}
 
-#[derive(Debug, Clone)]
-struct MctsGame {
-    start: usize,
+impl Node {
+    fn child(&self, joint: u16) -> Option<usize> {
+        self.children
+            .iter()
+            .find(|(j, _)| *j == joint)
+            .map(|(_, c)| *c as usize)
+    }
+}
+
+fn pack(moves: &Actions) -> u16 {
+    moves
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &d)| acc | (d as u16) << (2 * i))
+}
+
+/// Search tree of the decoupled PUCT.
+pub struct Mcts {
+    config: MctsConfig,
+    /// State of the root, which is restored after every iteration
     game: Game,
-    actions: Vec<Direction>,
-    player: Player,
-    heuristic: Arc<dyn Heuristic>,
+    /// Request ids of the snakes of the root, empty if they are unknown
+    snakes: Vec<String>,
+    nodes: Vec<Node>,
+    /// Range of the evaluations, used to normalize the rewards
+    bounds: (f64, f64),
+    rng: SmallRng,
 }
 
-impl mocats::GameState<Direction, Player> for MctsGame {
-    fn get_actions(&self) -> Vec<Direction> {
-        if self.game.turn > self.start + 8 {
-            return Vec::new();
+impl Mcts {
+    #[must_use]
+    pub fn new(game: &Game, config: MctsConfig, rng: SmallRng) -> Self {
+        assert!(game.snakes.len() <= MAX_SNAKES);
+        Self {
+            config,
+            game: game.clone(),
+            snakes: Vec::new(),
+            nodes: vec![Node::default()],
+            bounds: (f64::INFINITY, f64::NEG_INFINITY),
+            rng,
+        }
+    }
+
+    /// Number of completed iterations.
+    pub fn iterations(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    /// Number of nodes in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Visits and average reward of every move of the `snake` at the root.
+    pub fn root_stats(&self, snake: u8) -> [(u32, f64); 4] {
+        self.nodes[0].stats[snake as usize].map(|s| {
+            if s.visits > 0 {
+                (s.visits, s.reward / s.visits as f64)
+            } else {
+                (0, 0.0)
+            }
+        })
+    }
+
+    /// The most visited move of the agent at the root.
+    pub fn best_move(&self) -> Direction {
+        merged_best_move(std::slice::from_ref(self))
+    }
+
+    /// Continues with the subtree of the moves that lead from the root to
+    /// the `game` of the next turn.
+    ///
+    /// The moves are derived from the new heads of the snakes, which are
+    /// matched by their request ids (`snakes`), as the opponent pruning may
+    /// select other opponents than in the previous turn.
+    /// Returns false if the `game` does not follow from the root, for example
+    /// if a snake has been eliminated or another opponent is simulated.
+    pub fn advance(&mut self, game: &Game, snakes: &[String]) -> bool {
+        if snakes != self.snakes
+            || game.turn != self.game.turn + 1
+            || game.snakes.len() != self.game.snakes.len()
+            || game.grid.width != self.game.grid.width
+            || game.grid.height != self.game.grid.height
+        {
+            return false;
+        }
+
+        // Observed moves
+        let mut moves = [Direction::Up; MAX_SNAKES];
+        for (m, (old, new)) in moves
+            .iter_mut()
+            .zip(self.game.snakes.iter().zip(&game.snakes))
+        {
+            if !old.alive() || !new.alive() {
+                return false;
+            }
+            let Some(d) = Direction::all()
+                .into_iter()
+                .find(|&d| self.game.grid.apply(old.head(), d) == new.head())
+            else {
+                return false;
+            };
+            *m = d;
+        }
+
+        // Check that the bodies match (food and hazards may have spawned)
+        let delta = self.game.make_move(&moves);
+        let matches = (self.game.snakes.iter().zip(&game.snakes)).all(|(a, b)| a.body == b.body);
+        self.game.unmake_move(delta);
+        if !matches {
+            return false;
+        }
+
+        let Some(child) = self.nodes[0].child(pack(&moves)) else {
+            return false;
+        };
+        self.reroot(child);
+        self.game = game.clone();
+        true
+    }
+
+    /// Removes all nodes that are not part of the subtree of `root`.
+    fn reroot(&mut self, root: usize) {
+        let mut nodes = Vec::new();
+        let mut stack = vec![(root, 0)];
+        nodes.push(Node::default());
+        while let Some((old, new)) = stack.pop() {
+            let mut node = std::mem::take(&mut self.nodes[old]);
+            for (_, child) in &mut node.children {
+                let index = nodes.len();
+                nodes.push(Node::default());
+                stack.push((*child as usize, index));
+                *child = index as u32;
+            }
+            nodes[new] = node;
+        }
+        self.nodes = nodes;
+    }
+
+    /// Performs the given number of iterations.
+    pub fn run(&mut self, heuristic: &dyn Heuristic, iterations: usize) {
+        // The game is restored after every iteration
+        let mut game = self.game.clone();
+        let end = game.turn + self.config.horizon;
+        let mut path = Vec::new();
+        let mut deltas = Vec::new();
+        let mut rng = self.rng.clone();
+        self.root_priors(heuristic);
+        for _ in 0..iterations {
+            let expanded = self.descend(&mut game, &mut rng, &mut path, &mut deltas, end);
+            let priors = expanded_priors(&game, heuristic, expanded);
+            rollout(&mut game, &mut rng, &mut deltas, end);
+            let leaf = evaluate(&mut game, heuristic);
+            self.backpropagate(&mut path, leaf, priors);
+            for delta in deltas.drain(..).rev() {
+                game.unmake_move(delta);
+            }
+        }
+        self.rng = rng;
+    }
+
+    /// Performs iterations on the shared `tree` until the `budget` is
+    /// exhausted.
+    ///
+    /// The tree is only locked for the selection and backpropagation,
+    /// the priors and rollouts are computed in parallel.
+    fn work(
+        tree: &Mutex<Mcts>,
+        heuristic: &dyn Heuristic,
+        mut rng: SmallRng,
+        budget: &SearchBudget,
+    ) {
+        let (mut game, end) = {
+            let mut tree = tree.lock().unwrap();
+            tree.root_priors(heuristic);
+            (tree.game.clone(), tree.game.turn + tree.config.horizon)
+        };
+        let mut path = Vec::new();
+        let mut deltas = Vec::new();
+        while !budget.exhausted() {
+            for _ in 0..BATCH {
+                let expanded =
+                    tree.lock()
+                        .unwrap()
+                        .descend(&mut game, &mut rng, &mut path, &mut deltas, end);
+                let priors = expanded_priors(&game, heuristic, expanded);
+                rollout(&mut game, &mut rng, &mut deltas, end);
+                let leaf = evaluate(&mut game, heuristic);
+                tree.lock().unwrap().backpropagate(&mut path, leaf, priors);
+                for delta in deltas.drain(..).rev() {
+                    game.unmake_move(delta);
+                }
+            }
+            budget.count(BATCH as _);
+        }
+    }
+
+    /// Computes the priors of the root, if they are missing.
+    fn root_priors(&mut self, heuristic: &dyn Heuristic) {
+        if let (None, Some(policy)) = (self.nodes[0].priors, heuristic.policy()) {
+            self.nodes[0].priors = Some(priors(&self.game, policy));
+        }
+    }
+
+    /// Selects moves from the root of the `game` until a new node is expanded
+    /// or the horizon is reached.
+    /// Returns the expanded node, whose priors are still missing.
+    ///
+    /// The visits are counted immediately, so that the iteration counts as a
+    /// loss (virtual loss) until its rewards are backpropagated.
+    fn descend(
+        &mut self,
+        game: &mut Game,
+        rng: &mut SmallRng,
+        path: &mut Path,
+        deltas: &mut Vec<Delta>,
+        end: usize,
+    ) -> Option<usize> {
+        let mut node = 0;
+        let mut expanded = None;
+        while game.turn < end && game.outcome() == Outcome::None {
+            let moves = self.select(game, rng, node);
+            path.push((node, moves));
+            deltas.push(game.make_move(&moves));
+
+            let current = &mut self.nodes[node];
+            current.visits += 1;
+            for (stats, d) in current.stats.iter_mut().zip(moves) {
+                stats[d as usize].visits += 1;
+            }
+
+            let joint = pack(&moves);
+            if let Some(child) = current.child(joint) {
+                node = child;
+            } else {
+                let child = self.nodes.len();
+                self.nodes[node].children.push((joint, child as u32));
+                self.nodes.push(Node::default());
+                node = child;
+                expanded = Some(child);
+                break;
+            }
+        }
+        self.nodes[node].visits += 1;
+        expanded
+    }
+
+    /// Adds the rewards of the simulation to the moves of the `path` and
+    /// stores the `priors` of the expanded node.
+    fn backpropagate(&mut self, path: &mut Path, leaf: Leaf, priors: Option<(usize, Priors)>) {
+        if let Some((node, priors)) = priors {
+            self.nodes[node].priors = Some(priors);
         }
+        let rewards = self.rewards(leaf);
+        for (node, moves) in path.drain(..) {
+            let node = &mut self.nodes[node];
+            for (stats, (d, reward)) in node.stats.iter_mut().zip(moves.iter().zip(rewards)) {
+                stats[*d as usize].reward += reward;
+            }
+        }
+    }
+
+    /// Every snake chooses its move with PUCT.
+    fn select(&self, game: &Game, rng: &mut SmallRng, node: usize) -> Actions {
+        let node = &self.nodes[node];
+        let mut moves = [Direction::Up; MAX_SNAKES];
+        let sqrt_n = (node.visits.max(1) as f64).sqrt();
 
-        let mut moves: Vec<Direction> = self.game.valid_moves(self.player.0).collect();
-        if self.player.0 != 0 && moves.is_empty() {
-            moves.push(Direction::Up);
+        let snakes = game.snakes.len();
+        for (snake, (stats, m)) in node.stats.iter().zip(&mut moves).enumerate().take(snakes) {
+            let mut valid: Vec<Direction> = game.valid_moves(snake as u8).collect();
+            valid.shuffle(rng);
+            let prior = match node.priors {
+                Some(priors) => priors[snake].map(f64::from),
+                None => UniformPolicy.policy(game, snake as u8),
+            };
+            let by_prior =
+                |a: &Direction, b: &Direction| prior[*a as usize].total_cmp(&prior[*b as usize]);
+
+            // Unvisited moves first, starting with the most likely one
+            let unvisited = valid
+                .iter()
+                .copied()
+                .filter(|&d| stats[d as usize].visits == 0);
+            *m = if let Some(d) = unvisited.max_by(by_prior) {
+                d
+            } else {
+                let puct = |d: Direction| {
+                    let s = stats[d as usize];
+                    let n = s.visits as f64;
+                    s.reward / n + self.config.exploration * prior[d as usize] * sqrt_n / (1.0 + n)
+                };
+                valid
+                    .iter()
+                    .copied()
+                    .max_by(|&a, &b| puct(a).total_cmp(&puct(b)))
+                    .unwrap_or(Direction::Up)
+            };
         }
         moves
     }
 
-    fn apply_action(&mut self, action: &Direction) {
-        self.actions.push(*action);
-        if self.actions.len() == self.game.snakes.len() {
-            info!("step={:?}", self.actions);
-            self.game.step(&self.actions);
-            self.actions.clear();
+    /// Rewards of all snakes in the range [0, 1].
+    ///
+    /// The winner gets 1 and dead snakes 0.
+    /// The evaluations of the living snakes are normalized with the range
+    /// of all previous evaluations into the range between.
+    fn rewards(&mut self, leaf: Leaf) -> [f64; MAX_SNAKES] {
+        let mut rewards = [0.0; MAX_SNAKES];
+        match leaf {
+            Leaf::Over(Outcome::Winner(i)) => rewards[i as usize] = 1.0,
+            Leaf::Over(_) => {}
+            Leaf::Running(values) => {
+                for v in values.iter().flatten() {
+                    self.bounds = (self.bounds.0.min(*v), self.bounds.1.max(*v));
+                }
+                let (min, max) = self.bounds;
+                for (reward, value) in rewards.iter_mut().zip(values) {
+                    if let Some(v) = value {
+                        let norm = if max > min {
+                            (v - min) / (max - min)
+                        } else {
+                            0.5
+                        };
+                        *reward = 0.25 + 0.5 * norm;
+                    }
+                }
+            }
         }
-        self.player = Player((self.player.0 + 1) % self.game.snakes.len() as u8);
+        rewards
     }
+}
 
-    fn get_turn(&self) -> Player {
-        self.player
+impl std::fmt::Debug for Mcts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mcts")
+            .field("config", &self.config)
+            .field("iterations", &self.iterations())
+            .field("nodes", &self.nodes.len())
+            .field("root", &self.root_stats(0))
+            .finish()
     }
+}
 
-    fn get_reward_for_player(&self, player: Player) -> f32 {
-        let mut game = self.game.clone();
-        game.snakes.swap(0, player.0 as usize);
-        let res = self.heuristic.eval(&self.game) as f32;
-        info!("reward={res} for {player:?}");
-        res
+/// Prior probabilities of the moves of all living snakes.
+fn priors(game: &Game, policy: &dyn Policy) -> Priors {
+    let mut priors = Priors::default();
+    for (snake, p) in priors.iter_mut().enumerate().take(game.snakes.len()) {
+        if game.snake_is_alive(snake as u8) {
+            *p = policy.policy(game, snake as u8).map(|p| p as f32);
+        }
     }
+    priors
 }
 
-pub async fn mcts(heuristic: Arc<dyn Heuristic>, timeout: u64, game: &Game) -> Direction {
-    let tree_policy = UctPolicy::new(2.0);
+/// Priors of the node that has just been `expanded` with the `game`, if the
+/// heuristic has a policy.
+fn expanded_priors(
+    game: &Game,
+    heuristic: &dyn Heuristic,
+    expanded: Option<usize>,
+) -> Option<(usize, Priors)> {
+    let policy = heuristic.policy()?;
+    if game.outcome() != Outcome::None {
+        return None;
+    }
+    Some((expanded?, priors(game, policy)))
+}
 
-    let game = MctsGame {
-        start: game.turn,
-        game: game.clone(),
-        actions: Vec::new(),
-        player: Player(0),
-        heuristic,
+/// Simulates the game with a fast agent until the horizon is reached.
+fn rollout(game: &mut Game, rng: &mut SmallRng, deltas: &mut Vec<Delta>, end: usize) {
+    while game.turn < end && game.outcome() == Outcome::None {
+        let mut moves = [Direction::Up; MAX_SNAKES];
+        for (snake, m) in moves.iter_mut().enumerate().take(game.snakes.len()) {
+            *m = rollout_move(game, snake as u8, rng);
+        }
+        deltas.push(game.make_move(&moves));
+    }
+}
+
+/// Evaluates the game from the perspective of every living snake.
+fn evaluate(game: &mut Game, heuristic: &dyn Heuristic) -> Leaf {
+    match game.outcome() {
+        Outcome::None => {
+            let mut values = [None; MAX_SNAKES];
+            for (i, value) in values.iter_mut().enumerate().take(game.snakes.len()) {
+                if game.snake_is_alive(i as u8) {
+                    *value = Some(eval_for(game, heuristic, i));
+                }
+            }
+            Leaf::Running(values)
+        }
+        outcome => Leaf::Over(outcome),
+    }
+}
+
+/// Evaluates the game from the perspective of the `snake`, by swapping it
+/// with the agent.
+fn eval_for(game: &mut Game, heuristic: &dyn Heuristic, snake: usize) -> f64 {
+    game.snakes.swap(0, snake);
+    let value = heuristic.eval(game);
+    game.snakes.swap(0, snake);
+    value
+}
+
+/// Move of the fast rollout agent, which prefers moves with many free
+/// neighbors and avoids head-to-heads with snakes that are at least as long.
+fn rollout_move(game: &Game, snake: u8, rng: &mut SmallRng) -> Direction {
+    if !game.snake_is_alive(snake) {
+        return Direction::Up;
+    }
+    let grid = &game.grid;
+    let own = &game.snakes[snake as usize];
+
+    let mut moves = Direction::all();
+    moves.shuffle(rng);
+    moves
+        .into_iter()
+        .filter(|&d| game.move_is_valid(snake, d))
+        .max_by_key(|&d| {
+            let p = grid.apply(own.head(), d);
+            let free = Direction::all()
+                .into_iter()
+                .map(|n| grid.apply(p, n))
+                .filter(|&n| grid.has(n) && grid[n].t != CellT::Owned)
+                .count() as i32;
+            let danger = game.snakes.iter().enumerate().any(|(i, s)| {
+                i != snake as usize
+                    && s.alive()
+                    && s.body.len() >= own.body.len()
+                    && Direction::all()
+                        .into_iter()
+                        .any(|n| grid.apply(s.head(), n) == p)
+            });
+            let food = grid[p].t == CellT::Food;
+            free - 4 * danger as i32 + food as i32
+        })
+        .unwrap_or(Direction::Up)
+}
+
+/// The most visited move of the agent, summed up over all `trees`.
+pub fn merged_best_move(trees: &[Mcts]) -> Direction {
+    let Some(first) = trees.first() else {
+        return Direction::Up;
     };
-    let mut search_tree = mocats::SearchTree::new(game, tree_policy);
+    Direction::all()
+        .into_iter()
+        .filter(|&d| first.game.move_is_valid(0, d))
+        .max_by_key(|&d| {
+            trees
+                .iter()
+                .map(|t| t.nodes[0].stats[0][d as usize].visits)
+                .sum::<u32>()
+        })
+        .unwrap_or_default()
+}
 
-    let start = Instant::now();
-    while start.elapsed().as_millis() < timeout as _ {
-        warn!(">>> mcts {:?}", start.elapsed().as_millis());
-        async {
-            search_tree.run(4);
+/// Continues with the subtree of the previous `tree` if possible.
+fn prepare(tree: Option<Mcts>, game: &Game, snakes: &[String], config: MctsConfig) -> Mcts {
+    let reused = tree
+        .and_then(|mut tree| (tree.config == config && tree.advance(game, snakes)).then_some(tree));
+    match reused {
+        Some(tree) => {
+            info!(">>> mcts reuse {} iterations", tree.iterations());
+            tree
+        }
+        None => {
+            let mut tree = Mcts::new(game, config, SmallRng::from_entropy());
+            tree.snakes = snakes.to_vec();
+            tree
         }
-        .await;
     }
+}
+
+/// Searches until the `budget` is exhausted and returns the most visited move.
+///
+/// The search runs on `config.threads` blocking tasks.
+/// If the `trees` of the previous turn are given, the search continues with
+/// the subtrees of the observed moves.
+/// The `snakes` are the request ids of the snakes of the `game`, which are
+/// used to match them with the previous turn.
+/// The trees are returned, so that they can be reused by the next turn.
+pub async fn mcts(
+    heuristic: Arc<dyn Heuristic>,
+    config: MctsConfig,
+    budget: Arc<SearchBudget>,
+    game: &Game,
+    snakes: &[String],
+    trees: Vec<Mcts>,
+) -> (Direction, Vec<Mcts>) {
+    let threads = config.threads.max(1);
+    let mut previous = trees.into_iter();
+
+    let trees = match config.parallelism {
+        Parallelism::Root => {
+            let tasks: Vec<_> = (0..threads)
+                .map(|_| {
+                    let heuristic = heuristic.clone();
+                    let budget = budget.clone();
+                    let tree = previous.next();
+                    let game = game.clone();
+                    let snakes = snakes.to_vec();
+                    tokio::task::spawn_blocking(move || {
+                        let mut tree = prepare(tree, &game, &snakes, config);
+                        while !budget.exhausted() {
+                            tree.run(&*heuristic, BATCH);
+                            budget.count(BATCH as _);
+                        }
+                        tree
+                    })
+                })
+                .collect();
+            let mut trees = Vec::with_capacity(threads);
+            for task in tasks {
+                if let Ok(tree) = task.await {
+                    trees.push(tree);
+                }
+            }
+            trees
+        }
+        Parallelism::Tree => {
+            let tree = previous.next();
+            let game = game.clone();
+            let snakes = snakes.to_vec();
+            let Ok(tree) =
+                tokio::task::spawn_blocking(move || prepare(tree, &game, &snakes, config)).await
+            else {
+                return Default::default();
+            };
+            let tree = Arc::new(Mutex::new(tree));
+            let tasks: Vec<_> = (0..threads)
+                .map(|_| {
+                    let heuristic = heuristic.clone();
+                    let budget = budget.clone();
+                    let tree = tree.clone();
+                    let rng = SmallRng::from_entropy();
+                    tokio::task::spawn_blocking(move || {
+                        Mcts::work(&tree, &*heuristic, rng, &budget)
+                    })
+                })
+                .collect();
+            for task in tasks {
+                let _ = task.await;
+            }
+            Arc::try_unwrap(tree)
+                .ok()
+                .and_then(|tree| tree.into_inner().ok())
+                .into_iter()
+                .collect()
+        }
+    };
 
-    search_tree.get_best_action().unwrap_or_default()
+    for tree in &trees {
+        info!(">>> mcts {tree:?}");
+    }
+    (merged_best_move(&trees), trees)
 }
 
 #[cfg(test)]
 mod test {
     use crate::game::Game;
     use crate::logging;
-    use crate::search::{mcts, Heuristic};
+    use crate::search::{Heuristic, SearchBudget};
     use log::info;
     use std::sync::Arc;
 
+    #[derive(Debug, Clone, Default)]
+    struct SimpleHeuristic;
+    impl Heuristic for SimpleHeuristic {
+        fn eval(&self, game: &Game) -> f64 {
+            if game.snake_is_alive(0) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    fn budget(timeout: u64) -> Arc<SearchBudget> {
+        Arc::new(SearchBudget::from_timeout(timeout))
+    }
+
     #[tokio::test]
     async fn simple() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 0 . 1 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let heuristic = Arc::new(SimpleHeuristic);
+        let (dir, _) = mcts(
+            heuristic,
+            MctsConfig::default(),
+            budget(100),
+            &game,
+            &[],
+            Vec::new(),
+        )
+        .await;
+        info!("dir={:?}", dir);
+        assert!(game.move_is_valid(0, dir));
+    }
+
+    #[test]
+    fn avoid_dead_end() {
+        use super::*;
+        use crate::floodfill::FloodFill;
         logging();
 
         #[derive(Debug, Clone, Default)]
-        struct SimpleHeuristic;
-        impl Heuristic for SimpleHeuristic {
+        struct SpaceHeuristic;
+        impl Heuristic for SpaceHeuristic {
             fn eval(&self, game: &Game) -> f64 {
-                if game.snake_is_alive(0) {
-                    1.0
+                let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0) as f64
+            }
+        }
+
+        // Moving right leads into a dead end
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . > > 1 . .
+            . . . . . . ^ < . . .
+            . . . . . 0 . ^ . . .
+            . . . . . ^ > ^ . . .
+            . . . . . ^ ^ . . . .
+            . . . > > > ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(42));
+        tree.run(&SpaceHeuristic, 2000);
+        info!("{tree:?}");
+        assert_eq!(tree.iterations(), 2000);
+        assert_ne!(tree.best_move(), Direction::Right);
+
+        // Same seed, same search
+        let mut other = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(42));
+        other.run(&SpaceHeuristic, 2000);
+        assert_eq!(other.root_stats(0), tree.root_stats(0));
+        assert_eq!(other.len(), tree.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn parallel() {
+        use super::*;
+        use crate::floodfill::FloodFill;
+        logging();
+
+        #[derive(Debug, Clone, Default)]
+        struct SpaceHeuristic;
+        impl Heuristic for SpaceHeuristic {
+            fn eval(&self, game: &Game) -> f64 {
+                let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0) as f64
+            }
+        }
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . > > 1 . .
+            . . . . . . ^ < . . .
+            . . . . . 0 . ^ . . .
+            . . . . . ^ > ^ . . .
+            . . . . . ^ ^ . . . .
+            . . . > > > ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        let heuristic = Arc::new(SpaceHeuristic);
+
+        // One shared tree
+        let config = MctsConfig {
+            threads: 4,
+            parallelism: Parallelism::Tree,
+            ..MctsConfig::default()
+        };
+        let (dir, trees) = mcts(
+            heuristic.clone(),
+            config,
+            budget(200),
+            &game,
+            &[],
+            Vec::new(),
+        )
+        .await;
+        assert_eq!(trees.len(), 1);
+        assert_ne!(dir, Direction::Right);
+        // No virtual losses are left
+        let tree = &trees[0];
+        let visits: u32 = tree.root_stats(0).iter().map(|s| s.0).sum();
+        assert_eq!(visits, tree.iterations());
+        assert!(tree.nodes[0].stats[0]
+            .iter()
+            .all(|s| s.reward <= s.visits as f64));
+
+        // Independent trees
+        let config = MctsConfig {
+            threads: 4,
+            parallelism: Parallelism::Root,
+            ..MctsConfig::default()
+        };
+        let (dir, trees) = mcts(
+            heuristic.clone(),
+            config,
+            budget(200),
+            &game,
+            &[],
+            Vec::new(),
+        )
+        .await;
+        assert_eq!(trees.len(), 4);
+        assert_ne!(dir, Direction::Right);
+        assert_eq!(merged_best_move(&trees), dir);
+
+        // The trees are continued
+        let mut next = game.clone();
+        next.step(&[dir, Direction::Up, Direction::Up, Direction::Up]);
+        let (_, trees) = mcts(heuristic, config, budget(50), &next, &[], trees).await;
+        assert_eq!(trees.len(), 4);
+        assert!(trees.iter().all(|t| t.game.turn == next.turn));
+    }
+
+    #[test]
+    fn advance_subtree() {
+        use super::*;
+        use crate::search::first_move;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 0 . 1 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(3));
+        tree.run(&SimpleHeuristic, 500);
+        info!("{tree:?}");
+
+        // Unrelated states are not matched
+        assert!(!tree.advance(&game, &[]));
+
+        let moves = first_move(Direction::Left);
+        let child = &tree.nodes[tree.nodes[0].child(pack(&moves)).unwrap()];
+        let (visits, children) = (child.visits, child.children.len());
+
+        let mut next = game.clone();
+        next.step(&moves);
+        // New food does not matter
+        next.grid[crate::env::v2(0, 0)].t = CellT::Food;
+        next.rehash();
+        // Snakes are matched by their request ids
+        tree.snakes = vec!["you".into(), "a".into()];
+        assert!(!tree.advance(&next, &["you".into(), "b".into()]));
+        assert!(tree.advance(&next, &["you".into(), "a".into()]));
+        info!("{tree:?}");
+        assert_eq!(tree.iterations(), visits);
+        assert_eq!(tree.nodes[0].children.len(), children);
+        assert!(tree.len() < 500);
+        assert_eq!(tree.game.compute_zobrist(), next.compute_zobrist());
+
+        tree.run(&SimpleHeuristic, 100);
+        assert_eq!(tree.iterations(), visits + 100);
+        assert_eq!(tree.game.compute_zobrist(), next.compute_zobrist());
+    }
+
+    #[test]
+    fn policy_priors() {
+        use super::*;
+        use crate::search::Guided;
+        logging();
+
+        /// Prefers moving left
+        #[derive(Debug)]
+        struct LeftPolicy;
+        impl Policy for LeftPolicy {
+            fn policy(&self, game: &Game, snake: u8) -> [f64; 4] {
+                if game.move_is_valid(snake, Direction::Left) {
+                    [0.02, 0.02, 0.02, 0.94]
                 } else {
-                    0.0
+                    UniformPolicy.policy(game, snake)
                 }
             }
         }
@@ -126,8 +939,106 @@ mod test {
         )
         .unwrap();
 
-        let heuristic = Arc::new(SimpleHeuristic);
-        let dir = mcts(heuristic, 1000, &game).await;
-        info!("dir={:?}", dir);
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(4));
+        tree.run(&SimpleHeuristic, 300);
+        info!("{tree:?}");
+        assert!(tree.nodes[0].priors.is_none());
+        let uniform = tree.root_stats(0)[Direction::Left as usize].0;
+
+        let heuristic = Guided {
+            heuristic: SimpleHeuristic,
+            policy: LeftPolicy,
+        };
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(4));
+        tree.run(&heuristic, 300);
+        info!("{tree:?}");
+        assert_eq!(tree.best_move(), Direction::Left);
+        let visits = tree.root_stats(0).map(|s| s.0);
+        assert!(visits[Direction::Left as usize] > uniform);
+        // All inner nodes have priors
+        assert!(tree
+            .nodes
+            .iter()
+            .filter(|n| !n.children.is_empty())
+            .all(|n| n.priors.is_some()));
+    }
+
+    #[test]
+    fn rewards_perspective() {
+        use super::*;
+        logging();
+
+        #[derive(Debug, Clone, Default)]
+        struct LengthHeuristic;
+        impl Heuristic for LengthHeuristic {
+            fn eval(&self, game: &Game) -> f64 {
+                game.snakes[0].body.len() as f64
+            }
+        }
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 0 . 1 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . . . ^ . . . .
+            . . . . . . ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        let hash = game.compute_zobrist();
+
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(1));
+        let leaf = evaluate(&mut tree.game, &LengthHeuristic);
+        let rewards = tree.rewards(leaf);
+        info!("{rewards:?}");
+        // Each snake is evaluated from its own perspective
+        assert!(rewards[0] < rewards[1]);
+        assert_eq!(rewards[1], 0.75);
+        assert_eq!(rewards[2], 0.0);
+
+        // The tree search restores the root state
+        tree.run(&LengthHeuristic, 100);
+        assert_eq!(tree.game.compute_zobrist(), hash);
+        assert_eq!(tree.game.turn, game.turn);
+    }
+
+    #[test]
+    fn many_snakes() {
+        use super::*;
+        use crate::game::MAX_SNAKES;
+        use crate::simulate::init_game;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(5);
+        let game = init_game(11, 11, MAX_SNAKES, &mut rng);
+        info!("{game:?}");
+
+        let mut tree = Mcts::new(&game, MctsConfig::default(), SmallRng::seed_from_u64(5));
+        tree.run(&SimpleHeuristic, 200);
+        info!("{tree:?}");
+        assert_eq!(tree.iterations(), 200);
+        assert!(game.move_is_valid(0, tree.best_move()));
+        // Every snake has its own bandit
+        for snake in 0..MAX_SNAKES as u8 {
+            let visits: u32 = tree.root_stats(snake).iter().map(|(v, _)| v).sum();
+            assert_eq!(visits, 200);
+        }
+
+        // The packed joint moves of all snakes lead to the subtree
+        let (joint, child) = tree.nodes[0].children[0];
+        let moves: [Direction; MAX_SNAKES] =
+            std::array::from_fn(|i| Direction::from((joint >> (2 * i)) as u8 & 3));
+        assert_eq!(pack(&moves), joint);
+        let visits = tree.nodes[child as usize].visits;
+        let mut next = game.clone();
+        next.step(&moves);
+        assert!(tree.advance(&next, &[]));
+        assert_eq!(tree.iterations(), visits);
     }
 }
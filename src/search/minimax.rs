@@ -1,12 +1,43 @@
 use std::sync::Arc;
 
-use crate::game::Game;
+use crate::game::MAX_SNAKES;
 use crate::{env::Direction, game::Outcome};
 
 use async_recursion::async_recursion;
 use tokio::task::JoinSet;
 
-use super::{Heuristic, DRAW, LOSS, WIN};
+use super::{
+    aborted, first_move, node_hash, ordered, stopped, Actions, Bound, Heuristic, SearchBudget,
+    SearchGame, TTEntry, TranspositionTable, DRAW, LOSS, WIN,
+};
+
+/// Returns the stored value if the node has already been searched at least
+/// as deep, otherwise the stored best move, which should be searched first.
+fn tt_probe(
+    tt: Option<&TranspositionTable>,
+    hash: u64,
+    depth: usize,
+) -> Result<f64, Option<Direction>> {
+    match tt.and_then(|tt| tt.get(hash)) {
+        Some(entry) if entry.depth as usize >= depth => Ok(entry.value),
+        entry => Err(entry.map(|e| e.best)),
+    }
+}
+
+/// Stores the exact value and best move of a node.
+fn tt_store(tt: Option<&TranspositionTable>, hash: u64, depth: usize, value: f64, best: Direction) {
+    if let Some(tt) = tt {
+        tt.insert(
+            hash,
+            TTEntry {
+                value,
+                depth: depth.min(u8::MAX as _) as _,
+                bound: Bound::Exact,
+                best,
+            },
+        );
+    }
+}
 
 /// This algorithm is more or less a hacky variation of minmax with multiple agents.
 /// The player with id 0 is the maximizing player, the others are minimizing.
@@ -15,8 +46,21 @@ use super::{Heuristic, DRAW, LOSS, WIN};
 ///
 /// If the maximizing player dies traversal ends and min is returned.
 /// Dead enemies are skipped.
-pub async fn async_max_n(game: &Game, depth: usize, heuristic: Arc<dyn Heuristic>) -> [f64; 4] {
-    assert!(game.snakes.len() <= 4);
+///
+/// The results of the max nodes are stored in the optional transposition table,
+/// which can be reused for the following iterations of iterative deepening.
+/// The search is aborted if the optional `budget` is exhausted.
+pub async fn async_max_n<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+) -> [f64; 4] {
+    assert!(game.snake_count() <= MAX_SNAKES);
+
+    let mut game = game.clone();
+    game.rehash();
 
     let mut set = JoinSet::new();
     for d in Direction::all() {
@@ -24,13 +68,15 @@ pub async fn async_max_n(game: &Game, depth: usize, heuristic: Arc<dyn Heuristic
             continue;
         }
 
-        let actions = [d, Direction::Up, Direction::Up, Direction::Up];
-        let game = game.clone();
+        let actions = first_move(d);
+        let mut game = game.clone();
         let heuristic = heuristic.clone();
+        let tt = tt.clone();
+        let budget = budget.clone();
 
         // Create tasks for subtrees.
         set.spawn(async move {
-            let r = async_max_n_rec(&game, depth, 1, actions, heuristic).await;
+            let r = async_max_n_rec(&mut game, depth, 1, actions, heuristic, tt, budget).await;
             (d, r)
         });
     }
@@ -41,36 +87,60 @@ pub async fn async_max_n(game: &Game, depth: usize, heuristic: Arc<dyn Heuristic
             result[d as usize] = r;
         }
     }
+    if !stopped(budget.as_deref()) {
+        store_root(tt.as_deref(), game.zobrist(), depth, &result);
+    }
     result
 }
 
+/// The moves are made and unmade in place, only the subtrees of the max
+/// nodes, which are searched by separate tasks, get their own copy.
 #[async_recursion]
-async fn async_max_n_rec(
-    game: &Game,
+async fn async_max_n_rec<G: SearchGame>(
+    game: &mut G,
     depth: usize,
     ply: usize,
-    actions: [Direction; 4],
+    actions: Actions,
     heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
 ) -> f64 {
-    if ply == game.snakes.len() {
-        // simulate
-        let mut game = game.clone();
-        game.step(&actions[..]);
+    if aborted(budget.as_deref()) {
+        return DRAW;
+    }
 
-        match game.outcome() {
-            Outcome::Winner(0) => return WIN + heuristic.eval(&game),
-            Outcome::Winner(_) => return LOSS,
-            Outcome::Match => return DRAW,
-            Outcome::None => {}
-        }
+    if ply == game.snake_count() {
+        // simulate
+        let delta = game.make_move(&actions[..]);
 
-        if depth <= 1 {
+        let value = match game.outcome() {
+            Outcome::Winner(0) => WIN + game.eval(&*heuristic),
+            Outcome::Winner(_) => LOSS,
+            Outcome::Match => DRAW,
             // eval
-            heuristic.eval(&game)
-        } else {
-            async_max_n_rec(&game, depth - 1, 0, [Direction::Up; 4], heuristic).await
-        }
+            Outcome::None if depth <= 1 => game.eval(&*heuristic),
+            Outcome::None => {
+                async_max_n_rec(
+                    game,
+                    depth - 1,
+                    0,
+                    [Direction::Up; MAX_SNAKES],
+                    heuristic,
+                    tt,
+                    budget,
+                )
+                .await
+            }
+        };
+
+        game.unmake_move(delta);
+        value
     } else if ply == 0 {
+        let hash = game.zobrist();
+        if let Ok(value) = tt_probe(tt.as_deref(), hash, depth) {
+            return value;
+        }
+
         // max
         let mut set = JoinSet::new();
         for d in Direction::all() {
@@ -78,37 +148,62 @@ async fn async_max_n_rec(
                 continue;
             }
 
-            let actions = [d, Direction::Up, Direction::Up, Direction::Up];
-            let game = game.clone();
+            let actions = first_move(d);
+            let mut game = game.clone();
             let heuristic = heuristic.clone();
+            let tt = tt.clone();
+            let budget = budget.clone();
 
             // Create tasks for subtrees.
-            set.spawn(
-                async move { async_max_n_rec(&game, depth, ply + 1, actions, heuristic).await },
-            );
+            set.spawn(async move {
+                let r = async_max_n_rec(&mut game, depth, ply + 1, actions, heuristic, tt, budget)
+                    .await;
+                (d, r)
+            });
         }
 
-        let mut max = LOSS;
+        let mut max = (Direction::Up, LOSS);
         while let Some(r) = set.join_next().await {
-            if let Ok(r) = r {
-                max = max.max(r);
+            if let Ok((d, r)) = r {
+                if r > max.1 {
+                    max = (d, r);
+                }
             }
         }
-        max
+        if !stopped(budget.as_deref()) {
+            tt_store(tt.as_deref(), hash, depth, max.1, max.0);
+        }
+        max.1
     } else {
         // min
-        let mut min = 2.0 * WIN;
+        let hash = node_hash(game.zobrist(), &actions[..ply]);
+        let tt_move = match tt_probe(tt.as_deref(), hash, depth) {
+            Ok(value) => return value,
+            Err(tt_move) => tt_move,
+        };
+
+        let mut min = (Direction::Up, 2.0 * WIN);
         let mut moved = false;
-        for d in Direction::all() {
+        // the best move of the previous iteration likely is the best again
+        for d in ordered(tt_move) {
             if !game.move_is_valid(ply as u8, d) {
                 continue;
             }
 
             let mut actions = actions;
             actions[ply] = d;
-            let val = async_max_n_rec(game, depth, ply + 1, actions, heuristic.clone()).await;
-            if val < min {
-                min = val;
+            let val = async_max_n_rec(
+                game,
+                depth,
+                ply + 1,
+                actions,
+                heuristic.clone(),
+                tt.clone(),
+                budget.clone(),
+            )
+            .await;
+            if val < min.1 {
+                min = (d, val);
                 moved = true;
 
                 // skip if already lowest possible outcome
@@ -119,9 +214,34 @@ async fn async_max_n_rec(
         }
         if !moved {
             // continue with next agent
-            min = async_max_n_rec(game, depth, ply + 1, actions, heuristic).await;
+            min.1 = async_max_n_rec(
+                game,
+                depth,
+                ply + 1,
+                actions,
+                heuristic,
+                tt.clone(),
+                budget.clone(),
+            )
+            .await;
+        }
+        if !stopped(budget.as_deref()) {
+            tt_store(tt.as_deref(), hash, depth, min.1, min.0);
         }
-        min
+        min.1
+    }
+}
+
+/// Stores the best move of the root, so that it can be searched first by
+/// the next iteration.
+pub(super) fn store_root(
+    tt: Option<&TranspositionTable>,
+    hash: u64,
+    depth: usize,
+    result: &[f64; 4],
+) {
+    if let Some(best) = crate::util::argmax(result.iter().copied()) {
+        tt_store(tt, hash, depth, result[best], Direction::from(best as u8));
     }
 }
 
@@ -132,67 +252,110 @@ async fn async_max_n_rec(
 ///
 /// If the maximizing player dies traversal ends and min is returned.
 /// Dead enemies are skipped.
-pub fn max_n(game: &Game, depth: usize, heuristic: &dyn Heuristic) -> [f64; 4] {
+///
+/// The results of the max nodes are stored in the optional transposition table.
+/// The search is aborted if the optional `budget` is exhausted.
+pub fn max_n<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+) -> [f64; 4] {
+    let mut game = game.clone();
+    game.rehash();
     let mut result = [LOSS; 4];
     for d in Direction::all() {
         if game.move_is_valid(0, d) {
-            let actions = [d, Direction::Up, Direction::Up, Direction::Up];
-            result[d as usize] = max_n_rec(game, depth, 1, actions, heuristic);
+            let actions = first_move(d);
+            result[d as usize] = max_n_rec(&mut game, depth, 1, actions, heuristic, tt, budget);
         }
     }
+    if !stopped(budget) {
+        store_root(tt, game.zobrist(), depth, &result);
+    }
     result
 }
 
-fn max_n_rec(
-    game: &Game,
+pub(super) fn max_n_rec<G: SearchGame>(
+    game: &mut G,
     depth: usize,
     ply: usize,
-    actions: [Direction; 4],
+    actions: Actions,
     heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
 ) -> f64 {
-    if ply == game.snakes.len() {
-        // simulate
-        let mut game = game.clone();
-        game.step(&actions[..]);
+    if aborted(budget) {
+        return DRAW;
+    }
 
-        match game.outcome() {
-            Outcome::Winner(0) => return WIN + heuristic.eval(&game),
-            Outcome::Winner(_) => return LOSS,
-            Outcome::Match => return DRAW,
-            Outcome::None => {}
-        }
+    if ply == game.snake_count() {
+        // simulate
+        let delta = game.make_move(&actions[..]);
 
-        if depth <= 1 {
+        let value = match game.outcome() {
+            Outcome::Winner(0) => WIN + game.eval(heuristic),
+            Outcome::Winner(_) => LOSS,
+            Outcome::Match => DRAW,
             // eval
-            heuristic.eval(&game)
-        } else {
-            max_n_rec(&game, depth - 1, 0, [Direction::Up; 4], heuristic)
-        }
+            Outcome::None if depth <= 1 => game.eval(heuristic),
+            Outcome::None => max_n_rec(
+                game,
+                depth - 1,
+                0,
+                [Direction::Up; MAX_SNAKES],
+                heuristic,
+                tt,
+                budget,
+            ),
+        };
+
+        game.unmake_move(delta);
+        value
     } else if ply == 0 {
+        let hash = game.zobrist();
+        if let Ok(value) = tt_probe(tt, hash, depth) {
+            return value;
+        }
+
         // collect all outcomes instead of max
-        let mut max = LOSS;
+        let mut max = (Direction::Up, LOSS);
         for d in Direction::all() {
             if !game.move_is_valid(0, d) {
                 continue;
             }
             let mut actions = actions;
             actions[ply] = d;
-            max = max.max(max_n_rec(game, depth, ply + 1, actions, heuristic));
+            let value = max_n_rec(game, depth, ply + 1, actions, heuristic, tt, budget);
+            if value > max.1 {
+                max = (d, value);
+            }
         }
-        max
+        if !stopped(budget) {
+            tt_store(tt, hash, depth, max.1, max.0);
+        }
+        max.1
     } else {
-        let mut min = 2.0 * WIN;
+        let hash = node_hash(game.zobrist(), &actions[..ply]);
+        let tt_move = match tt_probe(tt, hash, depth) {
+            Ok(value) => return value,
+            Err(tt_move) => tt_move,
+        };
+
+        let mut min = (Direction::Up, 2.0 * WIN);
         let mut moved = false;
-        for d in Direction::all() {
+        // the best move of the previous iteration likely is the best again
+        for d in ordered(tt_move) {
             if !game.move_is_valid(ply as u8, d) {
                 continue;
             }
 
             let mut actions = actions;
             actions[ply] = d;
-            let val = max_n_rec(game, depth, ply + 1, actions, heuristic);
-            if val < min {
-                min = val;
+            let val = max_n_rec(game, depth, ply + 1, actions, heuristic, tt, budget);
+            if val < min.1 {
+                min = (d, val);
                 moved = true;
 
                 // skip if already lowest possible outcome
@@ -203,9 +366,12 @@ fn max_n_rec(
         }
         if !moved {
             // continue with next agent
-            min = max_n_rec(game, depth, ply + 1, actions, heuristic);
+            min.1 = max_n_rec(game, depth, ply + 1, actions, heuristic, tt, budget);
+        }
+        if !stopped(budget) {
+            tt_store(tt, hash, depth, min.1, min.0);
         }
-        min
+        min.1
     }
 }
 
@@ -216,7 +382,7 @@ mod test {
     use crate::floodfill::FloodFill;
     use crate::game::Game;
     use crate::logging;
-    use crate::search::{alphabeta, Heuristic};
+    use crate::search::{alphabeta, Heuristic, MoveOrdering};
 
     #[derive(Debug, Clone, Default)]
     struct TestH;
@@ -224,7 +390,7 @@ mod test {
         fn eval(&self, game: &Game) -> f64 {
             let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
             if game.snake_is_alive(0) {
-                flood_fill.flood_snakes(&game.grid, &game.snakes);
+                flood_fill.flood_snakes(game);
                 flood_fill.count_space(0) as f64
             } else {
                 0.0
@@ -270,12 +436,358 @@ mod test {
         info!("{:?}", game.grid);
         let start = Instant::now();
 
-        let moves = max_n(&game, 3, &TestH);
+        let moves = max_n(&game, 3, &TestH, None, None);
         let end = Instant::now();
         info!("{:?}", moves);
         info!("time {}ms", (end - start).as_millis());
     }
 
+    #[test]
+    fn max_n_bitboard() {
+        use super::*;
+        use crate::bitboard::BitboardGame;
+        use crate::rules::GameMode;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for (mode, snakes) in [
+            (GameMode::Standard, 4),
+            (GameMode::Wrapped, 3),
+            (GameMode::Standard, 2),
+        ] {
+            let mut game = init_game(11, 11, snakes, &mut rng);
+            game.set_mode(mode);
+            info!("{game:?}");
+            let bitboard = BitboardGame::<u128>::from_game(&game).unwrap();
+
+            let expected = max_n(&game, 2, &TestH, None, None);
+            info!("{expected:?}");
+            assert_eq!(max_n(&bitboard, 2, &TestH, None, None), expected);
+            if snakes == 2 {
+                assert_eq!(
+                    alphabeta(&bitboard, 3, &TestH, None, None, &mut MoveOrdering::new()),
+                    alphabeta(&game, 3, &TestH, None, None, &mut MoveOrdering::new())
+                );
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_bitboard() {
+        use super::*;
+        use crate::bitboard::{BitboardGame, WideBitboardGame};
+        use crate::env::Vec2D;
+        use crate::grid::CellT;
+        use crate::search::Algorithm;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        logging();
+
+        /// Like [TestH], but counts the evaluated bitboards.
+        #[derive(Debug, Default)]
+        struct BitboardH(AtomicUsize);
+        impl Heuristic for BitboardH {
+            fn eval(&self, game: &Game) -> f64 {
+                TestH.eval(game)
+            }
+            fn eval_bitboard(&self, game: &BitboardGame) -> f64 {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                self.eval(&game.to_game())
+            }
+            fn eval_wide_bitboard(&self, game: &WideBitboardGame) -> f64 {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                self.eval(&game.to_game())
+            }
+            fn bitboard(&self) -> bool {
+                true
+            }
+        }
+
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut obstructed = init_game(11, 11, 2, &mut rng);
+        // Obstacles are not supported by the bitboards
+        let p = Vec2D::new(5, 4);
+        assert!(obstructed.grid[p].t == CellT::Free);
+        obstructed.grid[p].t = CellT::Owned;
+        obstructed.obstacles.push(p);
+        obstructed.rehash();
+        for (game, bitboard) in [
+            (init_game(11, 11, 3, &mut rng), true),
+            (init_game(19, 19, 2, &mut rng), true),
+            (obstructed, false),
+        ] {
+            info!("{game:?}");
+            for algorithm in [
+                Algorithm::MaxN,
+                Algorithm::Paranoid,
+                Algorithm::BestReply,
+                Algorithm::Simultaneous,
+            ] {
+                let search = |heuristic: Arc<dyn Heuristic>| {
+                    let game = game.clone();
+                    async move {
+                        let mut ordering = MoveOrdering::new();
+                        (algorithm.search(&game, 2, heuristic, None, None, &mut ordering)).await
+                    }
+                };
+                let heuristic = Arc::new(BitboardH::default());
+                let result = search(heuristic.clone()).await;
+                assert_eq!(result, search(Arc::new(TestH)).await, "{algorithm:?}");
+                assert_eq!(heuristic.0.load(Ordering::Relaxed) > 0, bitboard);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn abort_budget() {
+        use super::*;
+        use crate::search::{paranoid, SearchBudget, TranspositionTable};
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(11);
+        let game = init_game(11, 11, 3, &mut rng);
+        info!("{game:?}");
+        let expected = max_n(&game, 2, &TestH, None, None);
+
+        // Aborted searches do not store their incomplete results
+        let tt = TranspositionTable::new(1 << 12);
+        let budget = SearchBudget::unlimited().with_max_nodes(200);
+        max_n(&game, 3, &TestH, Some(&tt), Some(&budget));
+        assert!(budget.stopped());
+        assert!(budget.nodes() < 300);
+        assert_eq!(max_n(&game, 2, &TestH, Some(&tt), None), expected);
+
+        let budget = SearchBudget::unlimited().with_max_nodes(200);
+        let mut ordering = MoveOrdering::new();
+        paranoid(&game, 3, &TestH, Some(&tt), Some(&budget), &mut ordering);
+        assert!(budget.stopped());
+        assert!(ordering.pv().is_empty());
+        assert_eq!(
+            paranoid(&game, 2, &TestH, Some(&tt), None, &mut MoveOrdering::new()),
+            expected
+        );
+
+        // The parallel search stops promptly
+        let tt = Arc::new(TranspositionTable::new(1 << 12));
+        let budget = Arc::new(SearchBudget::from_timeout(20));
+        let start = std::time::Instant::now();
+        async_max_n(
+            &game,
+            12,
+            Arc::new(TestH),
+            Some(tt.clone()),
+            Some(budget.clone()),
+        )
+        .await;
+        info!(
+            "aborted after {:?} and {} nodes",
+            start.elapsed(),
+            budget.nodes()
+        );
+        assert!(budget.stopped());
+        assert!(start.elapsed().as_millis() < 500);
+        let result = async_max_n(&game, 2, Arc::new(TestH), Some(tt), None).await;
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_transposition_table() {
+        use super::*;
+        use crate::search::{async_alphabeta, TranspositionTable};
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        for snakes in [2, 3, 4] {
+            let game = init_game(11, 11, snakes, &mut rng);
+            info!("{game:?}");
+
+            // Reused for iterative deepening
+            let tt = Arc::new(TranspositionTable::new(1 << 12));
+            for depth in 1..=2 {
+                let expected = max_n(&game, depth, &TestH, None, None);
+                assert_eq!(max_n(&game, depth, &TestH, Some(&tt), None), expected);
+                let result =
+                    async_max_n(&game, depth, Arc::new(TestH), Some(tt.clone()), None).await;
+                assert_eq!(result, expected);
+
+                let root = tt.get(game.compute_zobrist()).unwrap();
+                assert_eq!(root.depth as usize, depth);
+                assert_eq!(root.value, expected[root.best as usize]);
+            }
+
+            if snakes == 2 {
+                let tt = Arc::new(TranspositionTable::new(1 << 12));
+                let mut ordering = MoveOrdering::new();
+                for depth in 1..=3 {
+                    let expected =
+                        alphabeta(&game, depth, &TestH, None, None, &mut MoveOrdering::new()).1;
+                    let result = alphabeta(&game, depth, &TestH, Some(&tt), None, &mut ordering);
+                    assert_eq!(result.1, expected);
+                    let result =
+                        async_alphabeta(&game, depth, Arc::new(TestH), Some(tt.clone()), None)
+                            .await;
+                    assert_eq!(result.1, expected);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn many_snakes() {
+        use super::*;
+        use crate::search::{async_paranoid, best_reply, paranoid};
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        /// Cheap evaluation, the full trees of eight snakes are large
+        #[derive(Debug, Clone, Default)]
+        struct MobilityH;
+        impl Heuristic for MobilityH {
+            fn eval(&self, game: &Game) -> f64 {
+                game.valid_moves(0).count() as f64
+            }
+        }
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let game = init_game(11, 11, MAX_SNAKES, &mut rng);
+        info!("{game:?}");
+        assert_eq!(game.snakes.len(), MAX_SNAKES);
+
+        let expected = max_n(&game, 1, &MobilityH, None, None);
+        info!("{expected:?}");
+        for d in Direction::all() {
+            assert_eq!(game.move_is_valid(0, d), expected[d as usize] > LOSS);
+        }
+        assert_eq!(
+            async_max_n(&game, 1, Arc::new(MobilityH), None, None).await,
+            expected
+        );
+        assert_eq!(
+            paranoid(&game, 1, &MobilityH, None, None, &mut MoveOrdering::new()),
+            expected
+        );
+        assert_eq!(
+            async_paranoid(
+                &game,
+                1,
+                Arc::new(MobilityH),
+                None,
+                None,
+                &mut MoveOrdering::new()
+            )
+            .await,
+            expected
+        );
+
+        // Only the strongest opponent replies, which can only be better for us
+        let result = best_reply(&game, 1, &MobilityH, None, None, &mut MoveOrdering::new());
+        info!("{result:?}");
+        for d in Direction::all() {
+            assert!(result[d as usize] >= expected[d as usize]);
+        }
+    }
+
+    #[test]
+    fn alphabeta_move_ordering() {
+        use crate::search::TranspositionTable;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(3);
+        for _ in 0..4 {
+            let game = init_game(11, 11, 2, &mut rng);
+            info!("{game:?}");
+
+            // Iterative deepening with a shared ordering and table
+            let tt = TranspositionTable::new(1 << 12);
+            let mut ordering = MoveOrdering::new();
+            for depth in 1..=4 {
+                let expected =
+                    alphabeta(&game, depth, &TestH, None, None, &mut MoveOrdering::new());
+                let result = alphabeta(&game, depth, &TestH, Some(&tt), None, &mut ordering);
+                info!("depth={depth} {result:?} pv={:?}", ordering.pv());
+                assert_eq!(result.1, expected.1);
+
+                // The principal variation starts with the best move
+                let pv = ordering.pv();
+                assert!(pv.len() >= 2);
+                assert!(pv.len() <= 2 * (depth + 1));
+                assert_eq!(pv[0], result.0);
+            }
+        }
+    }
+
+    #[test]
+    fn policy_ordering() {
+        use super::*;
+        use crate::search::{paranoid, Guided, Policy};
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        logging();
+
+        /// Prefers a different direction for every snake
+        #[derive(Debug, Default)]
+        struct TestPolicy {
+            calls: AtomicUsize,
+        }
+        impl Policy for TestPolicy {
+            fn policy(&self, _game: &Game, snake: u8) -> [f64; 4] {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                let mut p = [0.4, 0.3, 0.2, 0.1];
+                p.rotate_right(snake as usize % 4);
+                p
+            }
+        }
+        let guided = Guided {
+            heuristic: TestH,
+            policy: TestPolicy::default(),
+        };
+
+        // The order does not change the results
+        let mut rng = SmallRng::seed_from_u64(5);
+        for snakes in [2, 3] {
+            let game = init_game(11, 11, snakes, &mut rng);
+            info!("{game:?}");
+            for depth in 1..=3 {
+                guided.policy.calls.store(0, Ordering::Relaxed);
+                let expected = max_n(&game, depth, &TestH, None, None);
+                // Without pruning the order does not matter
+                assert_eq!(max_n(&game, depth, &guided, None, None), expected);
+                assert_eq!(guided.policy.calls.load(Ordering::Relaxed), 0);
+
+                let result = paranoid(&game, depth, &guided, None, None, &mut MoveOrdering::new());
+                assert_eq!(result, expected);
+                // Except for the last turn
+                assert_eq!(guided.policy.calls.load(Ordering::Relaxed) > 0, depth > 1);
+
+                if snakes == 2 {
+                    let expected =
+                        alphabeta(&game, depth, &TestH, None, None, &mut MoveOrdering::new());
+                    let result =
+                        alphabeta(&game, depth, &guided, None, None, &mut MoveOrdering::new());
+                    assert_eq!(result.1, expected.1);
+                }
+            }
+        }
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[ignore]
     async fn async_max_n() {
@@ -313,7 +825,7 @@ mod test {
         let game = Game::new(0, 11, 11, snakes, &[], &[]);
         info!("{:?}", game.grid);
         let start = Instant::now();
-        let moves = async_max_n(&game, 3, Arc::new(TestH)).await;
+        let moves = async_max_n(&game, 3, Arc::new(TestH), None, None).await;
         let end = Instant::now();
         info!("{:?}", moves);
         info!("async time {}ms", (end - start).as_millis());
@@ -349,13 +861,13 @@ mod test {
         info!("{:?}", game.grid);
 
         let start = Instant::now();
-        let moves = max_n(&game, 6, &TestH);
+        let moves = max_n(&game, 6, &TestH, None, None);
         let end = Instant::now();
         info!("max_n {:?}", moves);
         info!("max_n time {}ms", (end - start).as_millis());
 
         let start = Instant::now();
-        let moves = alphabeta(&game, 6, &TestH);
+        let moves = alphabeta(&game, 6, &TestH, None, None, &mut MoveOrdering::new());
         let end = Instant::now();
         info!("alpha_beta {:?}", moves);
         info!("alpha_beta time {}ms", (end - start).as_millis());
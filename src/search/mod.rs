@@ -4,16 +4,244 @@ mod alphabeta;
 pub use alphabeta::*;
 mod mcts;
 pub use mcts::*;
+mod tt;
+pub use tt::*;
+mod ordering;
+pub use ordering::*;
+mod paranoid;
+pub use paranoid::*;
+mod simultaneous;
+pub use simultaneous::*;
+mod budget;
+pub use budget::*;
+mod policy;
+pub use policy::*;
 
 use std::fmt::Debug;
+use std::sync::Arc;
 
-use crate::game::Game;
+use crate::bitboard::{BitDelta, BitSet, BitboardGame, SizedBitboard, WideBitboardGame};
+use crate::env::Direction;
+use crate::game::{Delta, Game, Outcome, MAX_SNAKES};
 
 pub const WIN: f64 = 10000.0;
 pub const DRAW: f64 = 0.0;
 pub const LOSS: f64 = -10000.0;
 
+/// Moves of all snakes of a turn, indexed by their id.
+pub type Actions = [Direction; MAX_SNAKES];
+
+/// Returns the actions of a turn, where the agent (id 0) moves in `dir`.
+pub(crate) fn first_move(dir: Direction) -> Actions {
+    let mut actions = [Direction::Up; MAX_SNAKES];
+    actions[0] = dir;
+    actions
+}
+
+/// Multi-player tree searches, which return the heuristic for each of the
+/// four moves of the agent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Algorithm {
+    /// [max_n], which searches all moves of the opponents
+    #[default]
+    MaxN,
+    /// [paranoid], alpha-beta search with the opponents as one coalition
+    Paranoid,
+    /// [best_reply], where only the strongest opponent replies
+    BestReply,
+    /// [simultaneous], which solves the simultaneous moves of the first turn
+    Simultaneous,
+}
+
+impl Algorithm {
+    /// Performs the parallel version of the search.
+    ///
+    /// The move `ordering` is kept between the iterations of iterative
+    /// deepening, so that the alpha-beta searches ([paranoid] and
+    /// [best_reply]) start with the principal variation of the previous
+    /// iteration.
+    /// [max_n] and [simultaneous] search all moves and do not use it.
+    ///
+    /// If the `heuristic` evaluates bitboards ([Heuristic::bitboard]), the
+    /// game is searched as [BitboardGame] or [WideBitboardGame] if it fits,
+    /// otherwise as [Game].
+    ///
+    /// The result is incomplete if the `budget` has been exhausted.
+    pub async fn search(
+        self,
+        game: &Game,
+        depth: usize,
+        heuristic: Arc<dyn Heuristic>,
+        tt: Option<Arc<TranspositionTable>>,
+        budget: Option<Arc<SearchBudget>>,
+        ordering: &mut MoveOrdering,
+    ) -> [f64; 4] {
+        if heuristic.bitboard() {
+            if let Some(game) = BitboardGame::<u128>::from_game(game) {
+                return self
+                    .search_game(&game, depth, heuristic, tt, budget, ordering)
+                    .await;
+            }
+            if let Some(game) = WideBitboardGame::from_game(game) {
+                return self
+                    .search_game(&game, depth, heuristic, tt, budget, ordering)
+                    .await;
+            }
+        }
+        self.search_game(game, depth, heuristic, tt, budget, ordering)
+            .await
+    }
+
+    async fn search_game<G: SearchGame>(
+        self,
+        game: &G,
+        depth: usize,
+        heuristic: Arc<dyn Heuristic>,
+        tt: Option<Arc<TranspositionTable>>,
+        budget: Option<Arc<SearchBudget>>,
+        ordering: &mut MoveOrdering,
+    ) -> [f64; 4] {
+        match self {
+            Algorithm::MaxN => async_max_n(game, depth, heuristic, tt, budget).await,
+            Algorithm::Paranoid => {
+                async_paranoid(game, depth, heuristic, tt, budget, ordering).await
+            }
+            Algorithm::BestReply => {
+                async_best_reply(game, depth, heuristic, tt, budget, ordering).await
+            }
+            Algorithm::Simultaneous => async_simultaneous(game, depth, heuristic, tt, budget).await,
+        }
+    }
+}
+
 /// A heuristic that evaluates the game state at the leafs of a tree search.
 pub trait Heuristic: Debug + Send + Sync + 'static {
     fn eval(&self, game: &Game) -> f64;
+
+    /// Evaluates a bitboard game state.
+    ///
+    /// Heuristics should override this to avoid the conversion into a [Game].
+    fn eval_bitboard(&self, game: &BitboardGame) -> f64 {
+        self.eval(&game.to_game())
+    }
+
+    /// Evaluates a wide bitboard game state, like [Heuristic::eval_bitboard].
+    fn eval_wide_bitboard(&self, game: &WideBitboardGame) -> f64 {
+        self.eval(&game.to_game())
+    }
+
+    /// Whether the searches should run on bitboards, which requires that
+    /// the bitboards are evaluated without converting them into a [Game].
+    fn bitboard(&self) -> bool {
+        false
+    }
+
+    /// Policy that orders the moves of the tree searches and provides the
+    /// priors of the Monte Carlo search.
+    ///
+    /// Without a policy, the moves are ordered by the searches alone and
+    /// all valid moves are equally likely.
+    fn policy(&self) -> Option<&dyn Policy> {
+        None
+    }
+}
+
+/// Game state that can be traversed by the tree searches.
+pub trait SearchGame: Clone + Send + Sync + 'static {
+    /// Data needed to undo a move.
+    type Delta: Send;
+
+    /// Number of snakes, including the dead ones.
+    fn snake_count(&self) -> usize;
+    fn move_is_valid(&self, snake: u8, dir: Direction) -> bool;
+    /// Cell index of the head of a living snake.
+    fn head_index(&self, snake: u8) -> usize;
+    fn make_move(&mut self, moves: &[Direction]) -> Self::Delta;
+    fn unmake_move(&mut self, delta: Self::Delta);
+    fn outcome(&self) -> Outcome;
+    fn eval(&self, heuristic: &dyn Heuristic) -> f64;
+    /// Move probabilities of the `snake`, see [Policy::policy].
+    fn policy(&self, policy: &dyn Policy, snake: u8) -> [f64; 4];
+    /// Zobrist hash of the game state.
+    fn zobrist(&self) -> u64;
+    /// Recomputes the hash if it is maintained incrementally.
+    fn rehash(&mut self) {}
+}
+
+impl SearchGame for Game {
+    type Delta = Delta;
+
+    fn snake_count(&self) -> usize {
+        self.snakes.len()
+    }
+    fn move_is_valid(&self, snake: u8, dir: Direction) -> bool {
+        Game::move_is_valid(self, snake, dir)
+    }
+    fn head_index(&self, snake: u8) -> usize {
+        match self.snakes.get(snake as usize) {
+            Some(s) if s.alive() => {
+                let head = s.head();
+                head.x as usize + head.y as usize * self.grid.width
+            }
+            _ => 0,
+        }
+    }
+    fn make_move(&mut self, moves: &[Direction]) -> Delta {
+        Game::make_move(self, moves)
+    }
+    fn unmake_move(&mut self, delta: Delta) {
+        Game::unmake_move(self, delta)
+    }
+    fn outcome(&self) -> Outcome {
+        Game::outcome(self)
+    }
+    fn eval(&self, heuristic: &dyn Heuristic) -> f64 {
+        heuristic.eval(self)
+    }
+    fn policy(&self, policy: &dyn Policy, snake: u8) -> [f64; 4] {
+        policy.policy(self, snake)
+    }
+    fn zobrist(&self) -> u64 {
+        Game::zobrist(self)
+    }
+    fn rehash(&mut self) {
+        Game::rehash(self)
+    }
+}
+
+impl<B: BitSet> SearchGame for BitboardGame<B> {
+    type Delta = BitDelta<B>;
+
+    fn snake_count(&self) -> usize {
+        self.snakes().len()
+    }
+    fn move_is_valid(&self, snake: u8, dir: Direction) -> bool {
+        BitboardGame::move_is_valid(self, snake, dir)
+    }
+    fn head_index(&self, snake: u8) -> usize {
+        self.snakes()
+            .get(snake as usize)
+            .map_or(0, |s| s.head as usize)
+    }
+    fn make_move(&mut self, moves: &[Direction]) -> BitDelta<B> {
+        BitboardGame::make_move(self, moves)
+    }
+    fn unmake_move(&mut self, delta: BitDelta<B>) {
+        BitboardGame::unmake_move(self, delta)
+    }
+    fn outcome(&self) -> Outcome {
+        BitboardGame::outcome(self)
+    }
+    fn eval(&self, heuristic: &dyn Heuristic) -> f64 {
+        match B::sized(self) {
+            SizedBitboard::Default(game) => heuristic.eval_bitboard(game),
+            SizedBitboard::Wide(game) => heuristic.eval_wide_bitboard(game),
+        }
+    }
+    fn policy(&self, policy: &dyn Policy, snake: u8) -> [f64; 4] {
+        policy.policy(&self.to_game(), snake)
+    }
+    fn zobrist(&self) -> u64 {
+        BitboardGame::zobrist(self)
+    }
 }
@@ -0,0 +1,225 @@
+use crate::env::Direction;
+use crate::game::MAX_SNAKES;
+use crate::zobrist;
+
+use super::SearchGame;
+
+/// Maximum number of plies (moves of single snakes) of a search.
+pub const MAX_PLY: usize = 128;
+/// Number of head positions that are distinguished by the history heuristic.
+const HISTORY_CELLS: usize = 32 * 32;
+/// Resolution of the prior probabilities, when they are compared.
+const PRIOR_SCALE: f64 = 1e6;
+
+/// Hash of a node within a turn, where the snakes have chosen the `actions`
+/// so far.
+pub fn node_hash(hash: u64, actions: &[Direction]) -> u64 {
+    actions
+        .iter()
+        .enumerate()
+        .fold(hash, |h, (i, &d)| h ^ zobrist::action(i as _, d))
+}
+
+/// Returns all moves, starting with `first` if present.
+pub fn ordered(first: Option<Direction>) -> [Direction; 4] {
+    let mut moves = Direction::all();
+    if let Some(first) = first {
+        moves[..=first as usize].rotate_right(1);
+    }
+    moves
+}
+
+/// Returns all moves, starting with `first` if present, followed by the
+/// others in the order of decreasing `prior` probability.
+pub fn ordered_by_prior(first: Option<Direction>, prior: &[f64; 4]) -> [Direction; 4] {
+    let mut moves = Direction::all();
+    moves.sort_by(|&a, &b| {
+        (first == Some(b))
+            .cmp(&(first == Some(a)))
+            .then(prior[b as usize].total_cmp(&prior[a as usize]))
+    });
+    moves
+}
+
+/// Move ordering for the alpha-beta search, which is kept between the
+/// iterations of iterative deepening.
+///
+/// The moves are ordered by:
+/// 1. Whether they are valid
+/// 2. The best move from the transposition table
+/// 3. The principal variation of the previous iteration
+/// 4. The killer moves, which recently caused cutoffs on the same ply
+/// 5. The prior probability of the policy, if available
+/// 6. The history heuristic, how often the move caused cutoffs for this
+///    snake and head position
+#[derive(Clone)]
+pub struct MoveOrdering {
+    killers: [[Option<Direction>; 2]; MAX_PLY],
+    /// Indexed by snake, head position and direction
+    history: Box<[[u32; 4]]>,
+    /// Triangular table, containing the principal variation of every ply
+    pv_table: Box<[[Direction; MAX_PLY]; MAX_PLY]>,
+    pv_len: [usize; MAX_PLY],
+    /// Principal variation of the previous iteration
+    pv: Vec<Direction>,
+    follow_pv: bool,
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveOrdering {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            killers: [[None; 2]; MAX_PLY],
+            history: vec![[0; 4]; MAX_SNAKES * HISTORY_CELLS].into_boxed_slice(),
+            pv_table: Box::new([[Direction::Up; MAX_PLY]; MAX_PLY]),
+            pv_len: [0; MAX_PLY],
+            pv: Vec::new(),
+            follow_pv: false,
+        }
+    }
+
+    /// Principal variation of the last completed iteration.
+    ///
+    /// It contains the moves of the snakes in the order they are searched.
+    pub fn pv(&self) -> &[Direction] {
+        &self.pv
+    }
+
+    /// Prepares the next iteration.
+    pub fn start_iteration(&mut self) {
+        self.follow_pv = !self.pv.is_empty();
+        self.pv_len = [0; MAX_PLY];
+    }
+
+    /// Keeps the principal variation of the completed iteration.
+    pub fn finish_iteration(&mut self) {
+        self.pv.clear();
+        self.pv
+            .extend_from_slice(&self.pv_table[0][..self.pv_len[0]]);
+        // Prefer recent cutoffs
+        for entry in self.history.iter_mut() {
+            entry.iter_mut().for_each(|v| *v /= 2);
+        }
+    }
+
+    fn history_index<G: SearchGame>(game: &G, snake: u8) -> usize {
+        snake as usize % MAX_SNAKES * HISTORY_CELLS + game.head_index(snake) % HISTORY_CELLS
+    }
+
+    /// Returns the moves of the `snake` on the ply `sply` in the order they
+    /// should be searched.
+    pub fn order<G: SearchGame>(
+        &mut self,
+        game: &G,
+        snake: u8,
+        sply: usize,
+        tt_move: Option<Direction>,
+        prior: Option<[f64; 4]>,
+    ) -> [Direction; 4] {
+        let pv_move = if self.follow_pv && sply < self.pv.len() {
+            Some(self.pv[sply])
+        } else {
+            self.follow_pv = false;
+            None
+        };
+        let killers = self.killers.get(sply).copied().unwrap_or_default();
+        let history = &self.history[Self::history_index(game, snake)];
+
+        let mut moves = Direction::all();
+        moves.sort_by_cached_key(|&d| {
+            std::cmp::Reverse((
+                game.move_is_valid(snake, d),
+                tt_move == Some(d),
+                pv_move == Some(d),
+                killers[0] == Some(d),
+                killers[1] == Some(d),
+                prior.map_or(0, |p| (p[d as usize] * PRIOR_SCALE) as u32),
+                history[d as usize],
+            ))
+        });
+        moves
+    }
+
+    /// Has to be called before searching the move `dir` on the ply `sply`.
+    pub fn enter(&mut self, sply: usize, dir: Direction) {
+        if self.follow_pv && self.pv.get(sply) != Some(&dir) {
+            self.follow_pv = false;
+        }
+    }
+
+    /// Ends the principal variation at this ply (leaf or cutoff).
+    pub fn leaf(&mut self, sply: usize) {
+        if sply < MAX_PLY {
+            self.pv_len[sply] = sply;
+        }
+    }
+
+    /// The move `dir` is the new best move on the ply `sply`.
+    pub fn update_pv(&mut self, sply: usize, dir: Direction) {
+        if sply >= MAX_PLY {
+            return;
+        }
+        let child_len = if sply + 1 < MAX_PLY {
+            self.pv_len[sply + 1].max(sply + 1)
+        } else {
+            sply + 1
+        };
+        let (table, rest) = self.pv_table[sply..].split_first_mut().unwrap();
+        table[sply] = dir;
+        if let Some(child) = rest.first() {
+            table[sply + 1..child_len].copy_from_slice(&child[sply + 1..child_len]);
+        }
+        self.pv_len[sply] = child_len;
+    }
+
+    /// The move `dir` of the `snake` caused a cutoff.
+    pub fn cutoff<G: SearchGame>(
+        &mut self,
+        game: &G,
+        snake: u8,
+        sply: usize,
+        depth: usize,
+        dir: Direction,
+    ) {
+        if let Some(killers) = self.killers.get_mut(sply) {
+            if killers[0] != Some(dir) {
+                killers[1] = killers[0];
+                killers[0] = Some(dir);
+            }
+        }
+        let history = &mut self.history[Self::history_index(game, snake)][dir as usize];
+        *history = history.saturating_add((depth * depth) as u32);
+    }
+}
+
+impl std::fmt::Debug for MoveOrdering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MoveOrdering")
+            .field("pv", &self.pv)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn ordered_moves() {
+        use super::*;
+        use Direction::*;
+
+        assert_eq!(ordered(None), [Up, Right, Down, Left]);
+        assert_eq!(ordered(Some(Up)), [Up, Right, Down, Left]);
+        assert_eq!(ordered(Some(Down)), [Down, Up, Right, Left]);
+        assert_eq!(ordered(Some(Left)), [Left, Up, Right, Down]);
+
+        let prior = [0.1, 0.2, 0.4, 0.3];
+        assert_eq!(ordered_by_prior(None, &prior), [Down, Left, Right, Up]);
+        assert_eq!(ordered_by_prior(Some(Up), &prior), [Up, Down, Left, Right]);
+    }
+}
@@ -0,0 +1,634 @@
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+
+use super::{
+    aborted, first_move, node_hash, prior, stopped, store_root, tt_cutoff, tt_store_bound, Actions,
+    Heuristic, MoveOrdering, SearchBudget, SearchGame, TranspositionTable, DRAW, LOSS, WIN,
+};
+use crate::env::Direction;
+use crate::game::{Outcome, MAX_SNAKES};
+use crate::util::argmax;
+
+/// State that is shared by all nodes of the search.
+struct Coalition<'a> {
+    heuristic: &'a dyn Heuristic,
+    tt: Option<&'a TranspositionTable>,
+    budget: Option<&'a SearchBudget>,
+    ordering: &'a mut MoveOrdering,
+    /// Depth of the root
+    depth: usize,
+}
+
+/// Searches the subtree of a move of the agent with a full window.
+type RootSearch<G> = fn(&mut Coalition, &mut G, usize, Direction) -> f64;
+
+/// Paranoid alpha-beta search for any number of snakes.
+///
+/// The opponents are treated as a single coalition, that minimizes the
+/// evaluation of the agent (id 0).
+/// They choose their moves one after another, each seeing the moves of the
+/// snakes before it, which is pessimistic for the agent.
+///
+/// The return value contains the heuristic for each of the four moves of the
+/// agent, which are the same as the results of [super::max_n].
+/// Every move is searched with a full window, so that their values are exact.
+///
+/// The bounds of all nodes are stored in the optional transposition table.
+/// The move `ordering` should be reused for iterative deepening, so that
+/// the principal variation of the previous iteration is searched first.
+/// The search is aborted if the optional `budget` is exhausted.
+pub fn paranoid<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+    ordering: &mut MoveOrdering,
+) -> [f64; 4] {
+    let mut search = Coalition {
+        heuristic,
+        tt,
+        budget,
+        ordering,
+        depth,
+    };
+    root(game, &mut search, paranoid_move)
+}
+
+/// Parallel [paranoid] search, where every move of the agent is searched on
+/// a separate thread.
+pub async fn async_paranoid<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+    ordering: &mut MoveOrdering,
+) -> [f64; 4] {
+    async_root(game, depth, heuristic, tt, budget, ordering, paranoid_move).await
+}
+
+/// Best-Reply Search (BRS) for any number of snakes.
+///
+/// Instead of searching all combinations of the opponents' moves, only a
+/// single opponent replies to the move of the agent, while the others play
+/// a default move (their first valid move).
+/// The strongest reply of all opponents is chosen, which considerably
+/// reduces the branching factor in games with many snakes.
+///
+/// For two snakes the results are the same as the ones of [paranoid].
+/// The principal variation of the `ordering` alternates between the moves of
+/// the agent and the ones of the replying opponents.
+///
+/// @see https://dke.maastrichtuniversity.nl/m.winands/documents/BestReplySearch.pdf
+pub fn best_reply<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+    ordering: &mut MoveOrdering,
+) -> [f64; 4] {
+    let mut search = Coalition {
+        heuristic,
+        tt,
+        budget,
+        ordering,
+        depth,
+    };
+    root(game, &mut search, best_reply_move)
+}
+
+/// Parallel [best_reply] search, where every move of the agent is searched
+/// on a separate thread.
+pub async fn async_best_reply<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+    ordering: &mut MoveOrdering,
+) -> [f64; 4] {
+    async_root(
+        game,
+        depth,
+        heuristic,
+        tt,
+        budget,
+        ordering,
+        best_reply_move,
+    )
+    .await
+}
+
+fn root<G: SearchGame>(game: &G, search: &mut Coalition, root_search: RootSearch<G>) -> [f64; 4] {
+    assert!(game.snake_count() <= MAX_SNAKES);
+
+    let mut game = game.clone();
+    game.rehash();
+    search.ordering.start_iteration();
+    search.ordering.leaf(0);
+
+    let depth = search.depth;
+    let mut result = [LOSS; 4];
+    let mut best = None;
+    for d in search.ordering.order(&game, 0, 0, None, None) {
+        if game.move_is_valid(0, d) {
+            search.ordering.enter(0, d);
+            let value = root_search(search, &mut game, depth, d);
+            result[d as usize] = value;
+            if best.is_none_or(|v| value > v) {
+                best = Some(value);
+                search.ordering.update_pv(0, d);
+            }
+        }
+    }
+    if !stopped(search.budget) {
+        store_root(search.tt, game.zobrist(), depth, &result);
+        search.ordering.finish_iteration();
+    }
+    result
+}
+
+/// Every move of the agent is searched with a copy of the `ordering`.
+/// The copy of the best move is kept for the next iteration.
+async fn async_root<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+    ordering: &mut MoveOrdering,
+    root_search: RootSearch<G>,
+) -> [f64; 4] {
+    assert!(game.snake_count() <= MAX_SNAKES);
+
+    let mut game = game.clone();
+    game.rehash();
+    ordering.start_iteration();
+
+    let mut set = JoinSet::new();
+    for d in ordering.order(&game, 0, 0, None, None) {
+        if !game.move_is_valid(0, d) {
+            continue;
+        }
+
+        let mut game = game.clone();
+        let heuristic = heuristic.clone();
+        let tt = tt.clone();
+        let budget = budget.clone();
+        let mut ordering = ordering.clone();
+        set.spawn_blocking(move || {
+            ordering.enter(0, d);
+            let mut search = Coalition {
+                heuristic: &*heuristic,
+                tt: tt.as_deref(),
+                budget: budget.as_deref(),
+                ordering: &mut ordering,
+                depth,
+            };
+            let value = root_search(&mut search, &mut game, depth, d);
+            (d, value, ordering)
+        });
+    }
+
+    let mut result = [LOSS; 4];
+    let mut orderings = [None, None, None, None];
+    while let Some(r) = set.join_next().await {
+        if let Ok((d, r, ordering)) = r {
+            result[d as usize] = r;
+            orderings[d as usize] = Some(ordering);
+        }
+    }
+    if !stopped(budget.as_deref()) {
+        store_root(tt.as_deref(), game.zobrist(), depth, &result);
+        if let Some(best) = argmax(result.iter().copied()) {
+            if let Some(best_ordering) = orderings[best].take() {
+                *ordering = best_ordering;
+                ordering.update_pv(0, Direction::from(best as u8));
+                ordering.finish_iteration();
+            }
+        }
+    }
+    result
+}
+
+/// Simulates the turn and evaluates the outcome or continues with `next`.
+fn step<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    actions: Actions,
+    depth: usize,
+    next: impl FnOnce(&mut Coalition, &mut G) -> f64,
+) -> f64 {
+    if aborted(search.budget) {
+        return DRAW;
+    }
+    let delta = game.make_move(&actions);
+    let value = match game.outcome() {
+        Outcome::Winner(0) => WIN + game.eval(search.heuristic),
+        Outcome::Winner(_) => LOSS,
+        Outcome::Match => DRAW,
+        Outcome::None if depth <= 1 => game.eval(search.heuristic),
+        Outcome::None => next(search, game),
+    };
+    game.unmake_move(delta);
+    value
+}
+
+fn paranoid_move<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    depth: usize,
+    dir: Direction,
+) -> f64 {
+    let actions = first_move(dir);
+    paranoid_rec(
+        search,
+        game,
+        depth,
+        1,
+        actions,
+        f64::NEG_INFINITY,
+        f64::INFINITY,
+    )
+}
+
+fn paranoid_rec<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    depth: usize,
+    ply: usize,
+    actions: Actions,
+    mut alpha: f64,
+    mut beta: f64,
+) -> f64 {
+    let snakes = game.snake_count();
+    // Number of moves from the root
+    let sply = (search.depth - depth) * snakes + ply;
+    search.ordering.leaf(sply);
+
+    if ply == snakes {
+        return step(search, game, actions, depth, |search, game| {
+            paranoid_rec(
+                search,
+                game,
+                depth - 1,
+                0,
+                [Direction::Up; MAX_SNAKES],
+                alpha,
+                beta,
+            )
+        });
+    }
+
+    let hash = node_hash(game.zobrist(), &actions[..ply]);
+    let (alpha_orig, beta_orig) = (alpha, beta);
+    if let Some((_, value)) = tt_cutoff(search.tt, hash, depth, &mut alpha, &mut beta) {
+        return value;
+    }
+    let tt_move = search.tt.and_then(|tt| tt.get(hash)).map(|e| e.best);
+
+    let prior = prior(search.heuristic, game, ply as u8, depth <= 1);
+    let mut best: Option<(Direction, f64)> = None;
+    for d in search.ordering.order(game, ply as u8, sply, tt_move, prior) {
+        if !game.move_is_valid(ply as u8, d) {
+            continue;
+        }
+        let mut actions = actions;
+        actions[ply] = d;
+        search.ordering.enter(sply, d);
+        let value = paranoid_rec(search, game, depth, ply + 1, actions, alpha, beta);
+
+        if ply == 0 {
+            // max
+            if best.is_none_or(|(_, v)| value > v) {
+                best = Some((d, value));
+                search.ordering.update_pv(sply, d);
+            }
+            alpha = alpha.max(value);
+        } else {
+            // min
+            if best.is_none_or(|(_, v)| value < v) {
+                best = Some((d, value));
+                search.ordering.update_pv(sply, d);
+            }
+            beta = beta.min(value);
+            // skip if already lowest possible outcome
+            if value <= LOSS {
+                break;
+            }
+        }
+        if alpha >= beta {
+            search.ordering.cutoff(game, ply as u8, sply, depth, d);
+            break;
+        }
+    }
+
+    let best = match best {
+        Some(best) => best,
+        // the agent is trapped
+        None if ply == 0 => (Direction::Up, LOSS),
+        // continue with next opponent
+        None => {
+            let value = paranoid_rec(search, game, depth, ply + 1, actions, alpha, beta);
+            // Keep the principal variation aligned with the plies
+            search.ordering.update_pv(sply, Direction::Up);
+            (Direction::Up, value)
+        }
+    };
+    if !stopped(search.budget) {
+        tt_store_bound(search.tt, hash, depth, best, alpha_orig, beta_orig);
+    }
+    best.1
+}
+
+fn best_reply_move<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    depth: usize,
+    dir: Direction,
+) -> f64 {
+    best_reply_min(search, game, depth, dir, f64::NEG_INFINITY, f64::INFINITY)
+}
+
+fn best_reply_max<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    depth: usize,
+    mut alpha: f64,
+    mut beta: f64,
+) -> f64 {
+    // Number of moves from the root, including the replies
+    let sply = 2 * (search.depth - depth);
+    search.ordering.leaf(sply);
+
+    let hash = game.zobrist();
+    let (alpha_orig, beta_orig) = (alpha, beta);
+    if let Some((_, value)) = tt_cutoff(search.tt, hash, depth, &mut alpha, &mut beta) {
+        return value;
+    }
+    let tt_move = search.tt.and_then(|tt| tt.get(hash)).map(|e| e.best);
+
+    let prior = prior(search.heuristic, game, 0, depth <= 1);
+    let mut best: Option<(Direction, f64)> = None;
+    for d in search.ordering.order(game, 0, sply, tt_move, prior) {
+        if !game.move_is_valid(0, d) {
+            continue;
+        }
+        search.ordering.enter(sply, d);
+        let value = best_reply_min(search, game, depth, d, alpha, beta);
+        if best.is_none_or(|(_, v)| value > v) {
+            best = Some((d, value));
+            search.ordering.update_pv(sply, d);
+        }
+        alpha = alpha.max(value);
+        if alpha >= beta {
+            search.ordering.cutoff(game, 0, sply, depth, d);
+            break;
+        }
+    }
+    // the agent is trapped
+    let best = best.unwrap_or((Direction::Up, LOSS));
+    if !stopped(search.budget) {
+        tt_store_bound(search.tt, hash, depth, best, alpha_orig, beta_orig);
+    }
+    best.1
+}
+
+/// The opponents reply to the move `own` of the agent.
+///
+/// The principal variation contains the move of the replying opponent,
+/// which is the first one for the default moves.
+fn best_reply_min<G: SearchGame>(
+    search: &mut Coalition,
+    game: &mut G,
+    depth: usize,
+    own: Direction,
+    alpha: f64,
+    mut beta: f64,
+) -> f64 {
+    let snakes = game.snake_count();
+    let sply = 2 * (search.depth - depth) + 1;
+    search.ordering.leaf(sply);
+
+    // Opponents that do not reply play their first valid move
+    let mut defaults = [Direction::Up; MAX_SNAKES];
+    defaults[0] = own;
+    for (i, default) in defaults.iter_mut().enumerate().take(snakes).skip(1) {
+        if let Some(d) = Direction::all()
+            .into_iter()
+            .find(|&d| game.move_is_valid(i as u8, d))
+        {
+            *default = d;
+        }
+    }
+
+    let reply = |search: &mut Coalition, game: &mut G, actions, alpha, beta| {
+        search.ordering.leaf(sply + 1);
+        step(search, game, actions, depth, |search, game| {
+            best_reply_max(search, game, depth - 1, alpha, beta)
+        })
+    };
+
+    search.ordering.enter(sply, defaults[1]);
+    let mut value = reply(search, game, defaults, alpha, beta);
+    search.ordering.update_pv(sply, defaults[1]);
+    beta = beta.min(value);
+    for i in 1..snakes {
+        for d in Direction::all() {
+            // skip if already lowest possible outcome
+            if alpha >= beta || value <= LOSS {
+                return value;
+            }
+            if d == defaults[i] || !game.move_is_valid(i as u8, d) {
+                continue;
+            }
+            let mut actions = defaults;
+            actions[i] = d;
+            search.ordering.enter(sply, d);
+            let reply = reply(search, game, actions, alpha, beta);
+            if reply < value {
+                value = reply;
+                search.ordering.update_pv(sply, d);
+            }
+            beta = beta.min(value);
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::floodfill::FloodFill;
+    use crate::game::Game;
+    use crate::logging;
+    use crate::search::{max_n, Heuristic};
+
+    #[derive(Debug, Clone, Default)]
+    struct TestH;
+    impl Heuristic for TestH {
+        fn eval(&self, game: &Game) -> f64 {
+            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+            if game.snake_is_alive(0) {
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0) as f64
+            } else {
+                0.0
+            }
+        }
+    }
+
+    fn fixtures() -> Vec<Game> {
+        [
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 0 . 1 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . 1 < < . . .
+            . . . . . . . . . . .
+            . . . . . 0 < < . . .
+            . . . . . . . . . . .
+            . . . . . 2 < < . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+            r#"
+            . . . . . . . . . . .
+            . 0 < < . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . 1 . .
+            . . . . . . . . ^ . .
+            . . . . . . . . ^ . .
+            . . . . . . . . . . .
+            . . . v . . . . . . .
+            . . . v . . . > > 3 .
+            . . . 2 . . . . . . .
+            . . . . . . . . . . ."#,
+        ]
+        .into_iter()
+        .map(|s| Game::parse(s).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn paranoid_max_n() {
+        use super::*;
+        logging();
+
+        for game in fixtures() {
+            info!("{game:?}");
+            // Reused for iterative deepening
+            let tt = TranspositionTable::new(1 << 12);
+            for depth in 1..=2 {
+                let expected = max_n(&game, depth, &TestH, None, None);
+                info!("depth={depth} {expected:?}");
+                assert_eq!(
+                    paranoid(&game, depth, &TestH, None, None, &mut MoveOrdering::new()),
+                    expected
+                );
+                assert_eq!(
+                    paranoid(
+                        &game,
+                        depth,
+                        &TestH,
+                        Some(&tt),
+                        None,
+                        &mut MoveOrdering::new()
+                    ),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn best_reply_bounds() {
+        use super::*;
+        logging();
+
+        for game in fixtures() {
+            info!("{game:?}");
+            let tt = TranspositionTable::new(1 << 12);
+            for depth in 1..=3 {
+                let paranoid = paranoid(&game, depth, &TestH, None, None, &mut MoveOrdering::new());
+                let result = best_reply(&game, depth, &TestH, None, None, &mut MoveOrdering::new());
+                info!("depth={depth} paranoid={paranoid:?} brs={result:?}");
+                assert_eq!(
+                    best_reply(
+                        &game,
+                        depth,
+                        &TestH,
+                        Some(&tt),
+                        None,
+                        &mut MoveOrdering::new()
+                    ),
+                    result
+                );
+
+                if game.snakes.len() == 2 {
+                    assert_eq!(result, paranoid);
+                } else {
+                    // Only a subset of the opponents' moves is searched
+                    for (brs, paranoid) in result.into_iter().zip(paranoid) {
+                        assert!(brs >= paranoid);
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_coalition() {
+        use super::*;
+        logging();
+
+        for game in fixtures() {
+            info!("{game:?}");
+            let tt = Arc::new(TranspositionTable::new(1 << 12));
+            for depth in 1..=2 {
+                let result = async_paranoid(
+                    &game,
+                    depth,
+                    Arc::new(TestH),
+                    Some(tt.clone()),
+                    None,
+                    &mut MoveOrdering::new(),
+                )
+                .await;
+                assert_eq!(
+                    result,
+                    paranoid(&game, depth, &TestH, None, None, &mut MoveOrdering::new())
+                );
+            }
+            let result = async_best_reply(
+                &game,
+                2,
+                Arc::new(TestH),
+                None,
+                None,
+                &mut MoveOrdering::new(),
+            )
+            .await;
+            assert_eq!(
+                result,
+                best_reply(&game, 2, &TestH, None, None, &mut MoveOrdering::new())
+            );
+        }
+    }
+}
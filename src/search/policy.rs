@@ -0,0 +1,101 @@
+use std::fmt::Debug;
+
+use crate::bitboard::{BitboardGame, WideBitboardGame};
+use crate::env::Direction;
+use crate::game::Game;
+
+use super::{Heuristic, SearchGame};
+
+/// Probability distribution over the moves of a snake.
+///
+/// Policies guide the tree searches: the alpha-beta searches
+/// ([super::alphabeta], [super::paranoid] and [super::best_reply]) search the
+/// likely moves first to cause more cutoffs and [super::Mcts] uses them as
+/// priors.
+pub trait Policy: Debug + Send + Sync + 'static {
+    /// Probabilities of the moves of the `snake`, indexed by [Direction].
+    ///
+    /// Invalid moves should have a probability of zero.
+    fn policy(&self, game: &Game, snake: u8) -> [f64; 4];
+}
+
+/// Move probabilities of the policy of the `heuristic` for ordering the moves
+/// of the `snake`.
+///
+/// They are not computed for the `last_turn` of a search, whose subtrees are
+/// only evaluated.
+pub(super) fn prior<G: SearchGame>(
+    heuristic: &dyn Heuristic,
+    game: &G,
+    snake: u8,
+    last_turn: bool,
+) -> Option<[f64; 4]> {
+    let policy = heuristic.policy().filter(|_| !last_turn)?;
+    Some(game.policy(policy, snake))
+}
+
+/// Uniform distribution over the valid moves, which is used if no policy
+/// is configured.
+///
+/// If there is no valid move, all moves are equally likely.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformPolicy;
+
+impl Policy for UniformPolicy {
+    fn policy(&self, game: &Game, snake: u8) -> [f64; 4] {
+        let valid = Direction::all().map(|d| game.move_is_valid(snake, d));
+        let count = valid.iter().filter(|v| **v).count();
+        if count == 0 {
+            return [0.25; 4];
+        }
+        valid.map(|v| if v { 1.0 / count as f64 } else { 0.0 })
+    }
+}
+
+/// Heuristic that additionally provides a policy for the searches.
+#[derive(Debug, Clone)]
+pub struct Guided<H, P> {
+    pub heuristic: H,
+    pub policy: P,
+}
+
+impl<H: Heuristic, P: Policy> Heuristic for Guided<H, P> {
+    fn eval(&self, game: &Game) -> f64 {
+        self.heuristic.eval(game)
+    }
+    fn eval_bitboard(&self, game: &BitboardGame) -> f64 {
+        self.heuristic.eval_bitboard(game)
+    }
+    fn eval_wide_bitboard(&self, game: &WideBitboardGame) -> f64 {
+        self.heuristic.eval_wide_bitboard(game)
+    }
+    fn policy(&self) -> Option<&dyn Policy> {
+        Some(&self.policy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn uniform_policy() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            0 . . . .
+            ^ . . . .
+            ^ < . . .
+            . . . 1 .
+            . . . ^ ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+        assert_eq!(UniformPolicy.policy(&game, 0), [0.0, 1.0, 0.0, 0.0]);
+        let third = 1.0 / 3.0;
+        assert_eq!(UniformPolicy.policy(&game, 1), [third, third, 0.0, third]);
+    }
+}
@@ -0,0 +1,479 @@
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+
+use super::{
+    max_n_rec, stopped, store_root, Actions, Heuristic, SearchBudget, SearchGame,
+    TranspositionTable, DRAW, LOSS, WIN,
+};
+use crate::env::Direction;
+use crate::game::{Outcome, MAX_SNAKES};
+
+/// Number of regret matching iterations used to solve the payoff matrix.
+pub const REGRET_ITERATIONS: usize = 1000;
+
+/// Payoff matrix of the simultaneous moves at the root.
+///
+/// The rows are the moves of the agent (id 0) and the columns the joint
+/// moves of all opponents, which are treated as a single player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoffMatrix {
+    /// Valid moves of the agent
+    pub moves: Vec<Direction>,
+    /// Joint moves of the opponents, the first direction is unused
+    pub replies: Vec<Actions>,
+    /// Payoffs in row-major order
+    pub payoffs: Vec<f64>,
+}
+
+/// Solution of the zero-sum matrix game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixedStrategy {
+    /// Probabilities of the moves of the agent
+    pub agent: [f64; 4],
+    /// Probabilities of the joint moves of the opponents
+    pub opponents: Vec<f64>,
+    /// Expected payoff of each move of the agent against the strategy of the
+    /// opponents or [LOSS] if the move is invalid
+    pub values: [f64; 4],
+    /// Expected payoff of both strategies
+    pub value: f64,
+}
+
+/// Search that considers the moves of the first turn as simultaneous.
+///
+/// All other searches sequentialize the moves, so that the opponents know
+/// our move in advance, which makes them too pessimistic, for example
+/// concerning head-to-heads.
+/// Here, every combination of moves is simulated and the resulting states are
+/// searched with [super::max_n] up to the given depth.
+/// The resulting payoff matrix is then solved with regret matching.
+///
+/// The return value contains the expected heuristic of each move of the
+/// agent against the opponents' mixed strategy, whose maximum is the best
+/// response.
+/// The search is aborted if the optional `budget` is exhausted.
+pub fn simultaneous<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+) -> [f64; 4] {
+    let mut game = game.clone();
+    game.rehash();
+    let matrix = payoff_matrix(&mut game, depth, heuristic, tt, budget);
+    let result = matrix.solve(REGRET_ITERATIONS).values;
+    if !stopped(budget) {
+        store_root(tt, game.zobrist(), depth, &result);
+    }
+    result
+}
+
+/// Parallel [simultaneous] search, where every row of the payoff matrix is
+/// computed on a separate thread.
+pub async fn async_simultaneous<G: SearchGame>(
+    game: &G,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    tt: Option<Arc<TranspositionTable>>,
+    budget: Option<Arc<SearchBudget>>,
+) -> [f64; 4] {
+    let mut game = game.clone();
+    game.rehash();
+
+    let moves = agent_moves(&game);
+    let replies = Arc::new(joint_replies(&game));
+
+    let mut set = JoinSet::new();
+    for (i, &d) in moves.iter().enumerate() {
+        let mut game = game.clone();
+        let heuristic = heuristic.clone();
+        let tt = tt.clone();
+        let budget = budget.clone();
+        let replies = replies.clone();
+        set.spawn_blocking(move || {
+            let row = payoff_row(
+                &mut game,
+                depth,
+                d,
+                &replies,
+                &*heuristic,
+                tt.as_deref(),
+                budget.as_deref(),
+            );
+            (i, row)
+        });
+    }
+
+    let mut payoffs = vec![LOSS; moves.len() * replies.len()];
+    while let Some(r) = set.join_next().await {
+        if let Ok((i, row)) = r {
+            payoffs[i * replies.len()..(i + 1) * replies.len()].copy_from_slice(&row);
+        }
+    }
+
+    let matrix = PayoffMatrix {
+        moves,
+        replies: replies.to_vec(),
+        payoffs,
+    };
+    let result = matrix.solve(REGRET_ITERATIONS).values;
+    if !stopped(budget.as_deref()) {
+        store_root(tt.as_deref(), game.zobrist(), depth, &result);
+    }
+    result
+}
+
+/// Simulates all combinations of moves and searches the resulting states.
+pub fn payoff_matrix<G: SearchGame>(
+    game: &mut G,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+) -> PayoffMatrix {
+    let moves = agent_moves(game);
+    let replies = joint_replies(game);
+
+    let mut payoffs = Vec::with_capacity(moves.len() * replies.len());
+    for &d in &moves {
+        payoffs.extend(payoff_row(game, depth, d, &replies, heuristic, tt, budget));
+    }
+    PayoffMatrix {
+        moves,
+        replies,
+        payoffs,
+    }
+}
+
+fn agent_moves<G: SearchGame>(game: &G) -> Vec<Direction> {
+    Direction::all()
+        .into_iter()
+        .filter(|&d| game.move_is_valid(0, d))
+        .collect()
+}
+
+/// All combinations of the valid moves of the opponents.
+///
+/// Opponents without valid moves are dead or trapped and move up.
+fn joint_replies<G: SearchGame>(game: &G) -> Vec<Actions> {
+    let mut replies = vec![[Direction::Up; MAX_SNAKES]];
+    for snake in 1..game.snake_count() {
+        let moves: Vec<Direction> = Direction::all()
+            .into_iter()
+            .filter(|&d| game.move_is_valid(snake as u8, d))
+            .collect();
+        if moves.is_empty() {
+            continue;
+        }
+        replies = replies
+            .into_iter()
+            .flat_map(|r| {
+                moves.iter().map(move |&d| {
+                    let mut r = r;
+                    r[snake] = d;
+                    r
+                })
+            })
+            .collect();
+    }
+    replies
+}
+
+fn payoff_row<G: SearchGame>(
+    game: &mut G,
+    depth: usize,
+    dir: Direction,
+    replies: &[Actions],
+    heuristic: &dyn Heuristic,
+    tt: Option<&TranspositionTable>,
+    budget: Option<&SearchBudget>,
+) -> Vec<f64> {
+    replies
+        .iter()
+        .map(|&reply| {
+            let mut actions = reply;
+            actions[0] = dir;
+
+            let delta = game.make_move(&actions);
+            let value = match game.outcome() {
+                Outcome::Winner(0) => WIN + game.eval(heuristic),
+                Outcome::Winner(_) => LOSS,
+                Outcome::Match => DRAW,
+                Outcome::None if depth <= 1 => game.eval(heuristic),
+                Outcome::None => max_n_rec(
+                    game,
+                    depth - 1,
+                    0,
+                    [Direction::Up; MAX_SNAKES],
+                    heuristic,
+                    tt,
+                    budget,
+                ),
+            };
+            game.unmake_move(delta);
+            value
+        })
+        .collect()
+}
+
+impl PayoffMatrix {
+    fn payoff(&self, row: usize, col: usize) -> f64 {
+        self.payoffs[row * self.replies.len() + col]
+    }
+
+    /// Approximates the Nash equilibrium of the zero-sum game, where the
+    /// agent maximizes and the opponents minimize the payoff.
+    ///
+    /// Uses regret matching+ with linearly weighted average strategies,
+    /// which converge to the equilibrium.
+    ///
+    /// @see http://modelai.gettysburg.edu/2013/cfr/cfr.pdf
+    pub fn solve(&self, iterations: usize) -> MixedStrategy {
+        let (rows, cols) = (self.moves.len(), self.replies.len());
+        if rows == 0 || cols == 0 {
+            return MixedStrategy {
+                agent: [0.0; 4],
+                opponents: vec![1.0 / cols.max(1) as f64; cols],
+                values: [LOSS; 4],
+                value: LOSS,
+            };
+        }
+
+        let mut row_regrets = vec![0.0; rows];
+        let mut col_regrets = vec![0.0; cols];
+        let mut row_sum = vec![0.0; rows];
+        let mut col_sum = vec![0.0; cols];
+        let mut row_strategy = vec![0.0; rows];
+        let mut col_strategy = vec![0.0; cols];
+        let mut row_utils = vec![0.0; rows];
+        let mut col_utils = vec![0.0; cols];
+
+        for t in 1..=iterations {
+            normalize(&row_regrets, &mut row_strategy);
+            normalize(&col_regrets, &mut col_strategy);
+
+            for (i, util) in row_utils.iter_mut().enumerate() {
+                *util = (0..cols).map(|j| self.payoff(i, j) * col_strategy[j]).sum();
+            }
+            for (j, util) in col_utils.iter_mut().enumerate() {
+                *util = -(0..rows)
+                    .map(|i| self.payoff(i, j) * row_strategy[i])
+                    .sum::<f64>();
+            }
+            let value: f64 = row_utils
+                .iter()
+                .zip(&row_strategy)
+                .map(|(u, p)| u * p)
+                .sum();
+
+            for (regret, util) in row_regrets.iter_mut().zip(&row_utils) {
+                *regret = (*regret + util - value).max(0.0);
+            }
+            for (regret, util) in col_regrets.iter_mut().zip(&col_utils) {
+                *regret = (*regret + util + value).max(0.0);
+            }
+            for (sum, p) in row_sum.iter_mut().zip(&row_strategy) {
+                *sum += t as f64 * p;
+            }
+            for (sum, p) in col_sum.iter_mut().zip(&col_strategy) {
+                *sum += t as f64 * p;
+            }
+        }
+
+        normalize(&row_sum, &mut row_strategy);
+        normalize(&col_sum, &mut col_strategy);
+
+        let mut agent = [0.0; 4];
+        let mut values = [LOSS; 4];
+        let mut value = 0.0;
+        for (i, &d) in self.moves.iter().enumerate() {
+            agent[d as usize] = row_strategy[i];
+            values[d as usize] = (0..cols).map(|j| self.payoff(i, j) * col_strategy[j]).sum();
+            value += row_strategy[i] * values[d as usize];
+        }
+        MixedStrategy {
+            agent,
+            opponents: col_strategy,
+            values,
+            value,
+        }
+    }
+}
+
+/// Normalizes the positive weights into a probability distribution, which is
+/// uniform if they are all zero.
+fn normalize(weights: &[f64], out: &mut [f64]) {
+    let sum: f64 = weights.iter().sum();
+    if sum > 0.0 {
+        for (o, w) in out.iter_mut().zip(weights) {
+            *o = w / sum;
+        }
+    } else {
+        out.fill(1.0 / weights.len() as f64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::floodfill::FloodFill;
+    use crate::game::Game;
+    use crate::logging;
+    use crate::search::{max_n, Heuristic};
+
+    #[derive(Debug, Clone, Default)]
+    struct TestH;
+    impl Heuristic for TestH {
+        fn eval(&self, game: &Game) -> f64 {
+            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+            if game.snake_is_alive(0) {
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0) as f64
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[test]
+    fn regret_matching() {
+        use super::*;
+        use crate::search::first_move;
+        use Direction::*;
+        logging();
+
+        let mut reply = first_move(Up);
+        reply[1] = Down;
+
+        // Matching pennies
+        let matrix = PayoffMatrix {
+            moves: vec![Up, Down],
+            replies: vec![first_move(Up), reply],
+            payoffs: vec![1.0, -1.0, -1.0, 1.0],
+        };
+        let solution = matrix.solve(REGRET_ITERATIONS);
+        info!("{solution:?}");
+        assert!((solution.agent[Up as usize] - 0.5).abs() < 0.05);
+        assert!((solution.agent[Down as usize] - 0.5).abs() < 0.05);
+        assert!((solution.opponents[0] - 0.5).abs() < 0.05);
+        assert!(solution.value.abs() < 0.05);
+        assert_eq!(solution.values[Left as usize], LOSS);
+
+        // Down is dominated, the opponent prefers the second column
+        let matrix = PayoffMatrix {
+            moves: vec![Up, Down],
+            replies: vec![first_move(Up), reply],
+            payoffs: vec![3.0, 1.0, 2.0, 0.0],
+        };
+        let solution = matrix.solve(REGRET_ITERATIONS);
+        info!("{solution:?}");
+        assert!(solution.agent[Up as usize] > 0.95);
+        assert!(solution.opponents[1] > 0.95);
+        assert!((solution.value - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn head_to_head() {
+        use super::*;
+        use crate::util::argmax;
+        logging();
+
+        // The opponent is longer and can win the head-to-head on the cell
+        // between the snakes
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            > > 0 . 1 < < < . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        info!("{game:?}");
+
+        for depth in 1..=2 {
+            let sequential = max_n(&game, depth, &TestH, None, None);
+            let result = simultaneous(&game, depth, &TestH, None, None);
+            info!("depth={depth} max_n={sequential:?} simultaneous={result:?}");
+
+            // The opponents do not know our move, which is less pessimistic
+            for (simultaneous, sequential) in result.into_iter().zip(sequential) {
+                assert!(simultaneous >= sequential - 1e-6);
+            }
+            // Moving into the head-to-head is the worst option
+            let best = argmax(result.iter().copied()).unwrap();
+            assert_ne!(Direction::from(best as u8), Direction::Right);
+            assert!(result[Direction::Right as usize] < result[best]);
+        }
+    }
+
+    #[test]
+    fn equilibrium_bounds() {
+        use super::*;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(11);
+        for snakes in [2, 3, 4] {
+            let mut game = init_game(11, 11, snakes, &mut rng);
+            info!("{game:?}");
+            let matrix = payoff_matrix(&mut game, 1, &TestH, None, None);
+            let solution = matrix.solve(REGRET_ITERATIONS);
+            info!("{solution:?}");
+
+            // maximin <= value <= minimax
+            let rows = 0..matrix.moves.len();
+            let cols = 0..matrix.replies.len();
+            let maximin = rows
+                .clone()
+                .map(|i| {
+                    cols.clone()
+                        .map(|j| matrix.payoff(i, j))
+                        .fold(f64::MAX, f64::min)
+                })
+                .fold(f64::MIN, f64::max);
+            let minimax = cols
+                .map(|j| {
+                    rows.clone()
+                        .map(|i| matrix.payoff(i, j))
+                        .fold(f64::MIN, f64::max)
+                })
+                .fold(f64::MAX, f64::min);
+            let eps = 0.02 * (minimax - maximin).abs().max(1.0);
+            assert!(maximin - eps <= solution.value && solution.value <= minimax + eps);
+
+            assert!((solution.agent.iter().sum::<f64>() - 1.0).abs() < 1e-6);
+            assert!((solution.opponents.iter().sum::<f64>() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_simultaneous_search() {
+        use super::*;
+        use crate::simulate::init_game;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(5);
+        for snakes in [2, 4] {
+            let game = init_game(11, 11, snakes, &mut rng);
+            info!("{game:?}");
+            let tt = Arc::new(TranspositionTable::new(1 << 12));
+            let expected = simultaneous(&game, 2, &TestH, None, None);
+            let result = async_simultaneous(&game, 2, Arc::new(TestH), Some(tt), None).await;
+            assert_eq!(result, expected);
+        }
+    }
+}
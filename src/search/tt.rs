@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::env::Direction;
+
+/// Relation of the stored value to the real value of the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The value is exact.
+    Exact,
+    /// The real value is at least the stored value (beta cutoff).
+    Lower,
+    /// The real value is at most the stored value (no move exceeded alpha).
+    Upper,
+}
+
+/// Result of a search from a position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TTEntry {
+    pub value: f64,
+    /// Remaining depth of the search.
+    pub depth: u8,
+    pub bound: Bound,
+    pub best: Direction,
+}
+
+impl TTEntry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        self.depth as u64 | bound << 8 | (self.best as u64) << 10
+    }
+
+    fn unpack(value: u64, meta: u64) -> Option<Self> {
+        let bound = match (meta >> 8) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+        Some(Self {
+            value: f64::from_bits(value),
+            depth: meta as u8,
+            bound,
+            best: Direction::from(((meta >> 10) & 3) as u8),
+        })
+    }
+}
+
+/// Fixed-size hash table for search results, which can be shared between
+/// threads.
+///
+/// The entries are stored without locks.
+/// To detect entries that have been torn by concurrent writes, the key is
+/// stored xor-ed with the data, so that these entries are simply missed.
+pub struct TranspositionTable {
+    /// Key ^ value ^ meta, value, meta
+    entries: Box<[[AtomicU64; 3]]>,
+    mask: usize,
+}
+
+impl TranspositionTable {
+    /// Creates a table with at least `capacity` entries (rounded up to a
+    /// power of two).
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            entries: (0..capacity).map(|_| Default::default()).collect(),
+            mask: capacity - 1,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the entry for the given Zobrist hash.
+    pub fn get(&self, hash: u64) -> Option<TTEntry> {
+        let [check, value, meta] = &self.entries[hash as usize & self.mask];
+        let (check, value, meta) = (
+            check.load(Ordering::Relaxed),
+            value.load(Ordering::Relaxed),
+            meta.load(Ordering::Relaxed),
+        );
+        if check ^ value ^ meta == hash {
+            TTEntry::unpack(value, meta)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the entry for the given Zobrist hash.
+    ///
+    /// Entries of other positions are always replaced, entries of the same
+    /// position only by deeper or equally deep searches.
+    pub fn insert(&self, hash: u64, entry: TTEntry) {
+        if let Some(old) = self.get(hash) {
+            if old.depth > entry.depth {
+                return;
+            }
+        }
+        let [check, value, meta] = &self.entries[hash as usize & self.mask];
+        let (v, m) = (entry.value.to_bits(), entry.pack());
+        check.store(hash ^ v ^ m, Ordering::Relaxed);
+        value.store(v, Ordering::Relaxed);
+        meta.store(m, Ordering::Relaxed);
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) {
+        for entry in self.entries.iter() {
+            for v in entry {
+                v.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranspositionTable")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn tt_insert_get() {
+        use super::*;
+
+        let tt = TranspositionTable::new(1000);
+        assert_eq!(tt.capacity(), 1024);
+
+        let entry = TTEntry {
+            value: -1.5,
+            depth: 3,
+            bound: Bound::Lower,
+            best: Direction::Left,
+        };
+        assert_eq!(tt.get(42), None);
+        tt.insert(42, entry);
+        assert_eq!(tt.get(42), Some(entry));
+        // Same slot, but another position
+        assert_eq!(tt.get(42 + 1024), None);
+
+        // Shallower searches do not replace deeper ones
+        tt.insert(42, TTEntry { depth: 2, ..entry });
+        assert_eq!(tt.get(42), Some(entry));
+        let deeper = TTEntry {
+            depth: 4,
+            bound: Bound::Exact,
+            ..entry
+        };
+        tt.insert(42, deeper);
+        assert_eq!(tt.get(42), Some(deeper));
+
+        // Other positions are always replaced
+        tt.insert(42 + 1024, entry);
+        assert_eq!(tt.get(42), None);
+        assert_eq!(tt.get(42 + 1024), Some(entry));
+
+        tt.clear();
+        assert_eq!(tt.get(42 + 1024), None);
+    }
+}
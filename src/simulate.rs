@@ -1,103 +1,535 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
 use log::{debug, warn};
-use rand::{rngs::SmallRng, seq::IteratorRandom, Rng};
+use rand::rngs::SmallRng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     agents::Agent,
-    env::{v2, Direction},
-    game::{Game, Outcome},
+    env::{v2, Direction, Vec2D},
+    game::{Event, Game, GameSettings, Outcome, Snake, MAX_SNAKES},
     grid::CellT,
+    rules::GameMode,
+    search::{max_n, Actions, Heuristic},
 };
 
 pub use snork_engine::simulate::init_game;
 
+/// Plays a game with the rules of its game mode until it has ended.
 pub async fn play_game(
     agents: &[Agent],
     game: &mut Game,
     timeout: u64,
-    food_rate: f64,
-    shrink_turns: usize,
     rng: &mut SmallRng,
 ) -> Outcome {
-    let mut food_count = 4;
+    play(agents, game, timeout, rng, |_, _| {}).await
+}
 
-    debug!("init: {game:?}");
+/// Plays a game like [play_game] and records every position.
+///
+/// The moves of the snakes are labeled with the results of a [max_n] search
+/// with the given `heuristic` and `depth`.
+pub async fn record_game(
+    agents: &[Agent],
+    game: &mut Game,
+    timeout: u64,
+    rng: &mut SmallRng,
+    heuristic: &dyn Heuristic,
+    depth: usize,
+) -> (Outcome, Vec<Position>) {
+    let mut positions = Vec::new();
+    let outcome = play(agents, game, timeout, rng, |game, moves| {
+        positions.push(Position::new(game, moves, heuristic, depth));
+    })
+    .await;
 
-    let mut hazard_insets = [0; 4];
+    let winner = match outcome {
+        Outcome::Winner(winner) => Some(winner),
+        _ => None,
+    };
+    for position in &mut positions {
+        position.winner = winner;
+    }
+    (outcome, positions)
+}
+
+/// Calls `on_turn` with the state and moves of every turn.
+async fn play(
+    agents: &[Agent],
+    game: &mut Game,
+    timeout: u64,
+    rng: &mut SmallRng,
+    mut on_turn: impl FnMut(&Game, &Actions),
+) -> Outcome {
+    debug!("init: {game:?}");
 
+    let mut deaths = Vec::new();
     for turn in game.turn.. {
-        let mut moves = [Direction::Up; 4];
+        let mut moves = [Direction::Up; MAX_SNAKES];
         for i in 0..game.snakes.len() {
             if game.snakes[i].alive() {
-                // Agents assume player 0 is you.
-                game.snakes.swap(0, i);
-
-                let response = agents[i].step_internal(timeout, game).await;
+                let view = perspective(game, i);
+                let response = agents[i].step_internal(timeout, &view).await;
                 moves[i] = response.r#move;
-
-                game.snakes.swap(0, i);
             }
         }
         debug!("Moves: {moves:?}");
+        on_turn(game, &moves);
 
-        game.step(&moves);
+        let events = game.step_with_events(&moves);
+        debug!("Events: {events:?}");
+        deaths.extend(events.into_iter().filter_map(|e| match e {
+            Event::Died { snake, cause } => Some((snake, cause)),
+            _ => None,
+        }));
 
         debug!("{}: {:?}", turn, game);
 
         let outcome = game.outcome();
         if outcome != Outcome::None {
-            warn!("game: {outcome:?} after {turn} turns");
+            warn!("game: {outcome:?} after {turn} turns, deaths: {deaths:?}");
             return outcome;
         }
 
-        // Check if snakes have consumed food
-        for snake in &game.snakes {
-            if snake.alive() && snake.health == 100 {
-                food_count -= 1;
+        // Spawn food and hazards
+        game.rules().update_board(game, rng);
+        game.rehash();
+    }
+    Outcome::Match
+}
+
+/// Returns the `game` from the perspective of the snake `i`.
+///
+/// Agents and searches assume player 0 is you, so the snake is swapped
+/// with the first one and the game is rehashed, as the hash depends on the
+/// snake ids.
+fn perspective(game: &Game, i: usize) -> Game {
+    let mut game = game.clone();
+    game.snakes.swap(0, i);
+    game.rehash();
+    game
+}
+
+/// Recorded position of a game, with the moves of all snakes and the final
+/// result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub turn: usize,
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub mode: GameMode,
+    #[serde(default)]
+    pub settings: GameSettings,
+    pub food: Vec<Vec2D>,
+    pub hazards: Vec<Vec2D>,
+    /// Cells of the snakes that are not simulated, see [Game::obstacles]
+    #[serde(default)]
+    pub obstacles: Vec<Vec2D>,
+    pub snakes: Vec<SnakeRecord>,
+    /// Winner of the game or none for a draw
+    pub winner: Option<u8>,
+}
+
+/// State and move of a snake in a [Position].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnakeRecord {
+    pub health: u8,
+    /// Body from head to tail, empty if the snake is dead
+    pub body: Vec<Vec2D>,
+    /// Chosen move, none if the snake is dead
+    pub r#move: Option<Direction>,
+    /// Search results for each move from the perspective of this snake
+    pub values: [f32; 4],
+}
+
+impl Position {
+    /// Records the `game` and searches the moves of every living snake.
+    #[must_use]
+    pub fn new(game: &Game, moves: &Actions, heuristic: &dyn Heuristic, depth: usize) -> Self {
+        let cells = (0..game.grid.height)
+            .flat_map(|y| (0..game.grid.width).map(move |x| v2(x as _, y as _)));
+        let food = cells
+            .clone()
+            .filter(|&p| game.grid[p].t == CellT::Food)
+            .collect();
+        let hazards = cells.filter(|&p| game.grid[p].hazard).collect();
+
+        let mut snakes = Vec::with_capacity(game.snakes.len());
+        for (i, &dir) in moves.iter().enumerate().take(game.snakes.len()) {
+            let snake = &game.snakes[i];
+            snakes.push(if snake.alive() {
+                let view = perspective(game, i);
+                let values = max_n(&view, depth, heuristic, None, None).map(|v| v as f32);
+                SnakeRecord {
+                    health: snake.health,
+                    body: snake.body.iter().rev().copied().collect(),
+                    r#move: Some(dir),
+                    values,
+                }
+            } else {
+                SnakeRecord {
+                    health: 0,
+                    body: Vec::new(),
+                    r#move: None,
+                    values: [0.0; 4],
+                }
+            });
+        }
+
+        Self {
+            turn: game.turn,
+            width: game.grid.width,
+            height: game.grid.height,
+            mode: game.mode,
+            settings: game.settings,
+            food,
+            hazards,
+            obstacles: game.obstacles.clone(),
+            snakes,
+            winner: None,
+        }
+    }
+
+    /// Restores the game state of the position.
+    #[must_use]
+    pub fn to_game(&self) -> Game {
+        let snakes = self
+            .snakes
+            .iter()
+            .map(|s| {
+                Snake::new(
+                    s.body.iter().rev().copied().collect::<VecDeque<_>>(),
+                    s.health,
+                )
+            })
+            .collect();
+        let mut game = Game::new(
+            self.turn,
+            self.width,
+            self.height,
+            snakes,
+            &self.food,
+            &self.hazards,
+        );
+        game.set_mode(self.mode);
+        game.settings = self.settings;
+        for &p in &self.obstacles {
+            game.grid[p].t = CellT::Owned;
+        }
+        game.obstacles = self.obstacles.clone();
+        game.rehash();
+        game
+    }
+
+    /// Writes the position in the compact binary format.
+    ///
+    /// All numbers are little endian and the coordinates single bytes:
+    /// `turn: u16, width: u8, height: u8, winner: u8, snakes: u8, mode: u8,
+    /// hazard_damage: u8, food_spawn_chance: u8, minimum_food: u16,
+    /// shrink_turns: u16, food: u16 + [x, y], hazards: u16 + [x, y],
+    /// obstacles: u16 + [x, y]`, followed by the snakes
+    /// `health: u8, move: u8, values: [f32; 4], body: u16 + [x, y]`.
+    /// A missing winner or move is stored as `u8::MAX`, the mode as its
+    /// index in [GameMode].
+    pub fn write_binary(&self, w: &mut impl Write) -> io::Result<()> {
+        fn points(w: &mut impl Write, points: &[Vec2D]) -> io::Result<()> {
+            w.write_all(&(points.len() as u16).to_le_bytes())?;
+            for p in points {
+                w.write_all(&[p.x as u8, p.y as u8])?;
             }
+            Ok(())
         }
 
-        // Spawn food
-        if food_count == 0 || rng.gen::<f64>() < food_rate {
-            if let Some(cell) = game
-                .grid
-                .cells
-                .iter_mut()
-                .filter(|c| c.t == CellT::Free)
-                .choose(rng)
-            {
-                cell.t = CellT::Food;
-                food_count += 1;
+        w.write_all(&(self.turn as u16).to_le_bytes())?;
+        w.write_all(&[
+            self.width as u8,
+            self.height as u8,
+            self.winner.unwrap_or(u8::MAX),
+            self.snakes.len() as u8,
+            self.mode as u8,
+            self.settings.hazard_damage,
+            self.settings.food_spawn_chance,
+        ])?;
+        w.write_all(&(self.settings.minimum_food as u16).to_le_bytes())?;
+        w.write_all(&(self.settings.shrink_turns as u16).to_le_bytes())?;
+        points(w, &self.food)?;
+        points(w, &self.hazards)?;
+        points(w, &self.obstacles)?;
+        for snake in &self.snakes {
+            w.write_all(&[snake.health, snake.r#move.map_or(u8::MAX, |d| d as u8)])?;
+            for value in snake.values {
+                w.write_all(&value.to_le_bytes())?;
             }
+            points(w, &snake.body)?;
         }
+        Ok(())
+    }
 
-        // Hazards
-        if turn > 0
-            && turn % shrink_turns == 0
-            && hazard_insets[0] + hazard_insets[2] < game.grid.height
-            && hazard_insets[1] + hazard_insets[3] < game.grid.width
-        {
-            let dir = rng.gen_range(0..4);
-            hazard_insets[dir] += 1;
-            if dir % 2 == 0 {
-                let y = if dir == 0 {
-                    hazard_insets[dir] - 1
-                } else {
-                    game.grid.height - hazard_insets[dir]
-                };
-                for x in 0..game.grid.width {
-                    game.grid[v2(x as _, y as _)].hazard = true;
+    /// Reads a position of the binary format, returns none at the end of
+    /// the input.
+    pub fn read_binary(r: &mut impl Read) -> io::Result<Option<Self>> {
+        fn bytes<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+            let mut buf = [0; N];
+            r.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+        fn points(r: &mut impl Read) -> io::Result<Vec<Vec2D>> {
+            let len = u16::from_le_bytes(bytes(r)?);
+            (0..len)
+                .map(|_| bytes(r).map(|[x, y]| v2(x as _, y as _)))
+                .collect()
+        }
+
+        let turn = match bytes(r) {
+            Ok(turn) => u16::from_le_bytes(turn) as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let [width, height, winner, len, mode, hazard_damage, food_spawn_chance] = bytes(r)?;
+        let mode = match mode {
+            0 => GameMode::Standard,
+            1 => GameMode::Royale,
+            2 => GameMode::Constrictor,
+            3 => GameMode::Wrapped,
+            4 => GameMode::Solo,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown mode")),
+        };
+        let settings = GameSettings {
+            hazard_damage,
+            food_spawn_chance,
+            minimum_food: u16::from_le_bytes(bytes(r)?) as _,
+            shrink_turns: u16::from_le_bytes(bytes(r)?) as _,
+        };
+        let food = points(r)?;
+        let hazards = points(r)?;
+        let obstacles = points(r)?;
+        let mut snakes = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let [health, dir] = bytes(r)?;
+            let mut values = [0.0; 4];
+            for value in &mut values {
+                *value = f32::from_le_bytes(bytes(r)?);
+            }
+            snakes.push(SnakeRecord {
+                health,
+                body: points(r)?,
+                r#move: (dir != u8::MAX).then(|| Direction::from(dir)),
+                values,
+            });
+        }
+        Ok(Some(Self {
+            turn,
+            width: width as _,
+            height: height as _,
+            mode,
+            settings,
+            food,
+            hazards,
+            obstacles,
+            snakes,
+            winner: (winner != u8::MAX).then_some(winner),
+        }))
+    }
+}
+
+/// Format of the recorded positions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DataFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// See [Position::write_binary]
+    Binary,
+}
+
+impl FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!("unknown data format: {s}")),
+        }
+    }
+}
+
+impl DataFormat {
+    pub fn write(self, w: &mut impl Write, positions: &[Position]) -> io::Result<()> {
+        for position in positions {
+            match self {
+                DataFormat::Jsonl => {
+                    serde_json::to_writer(&mut *w, position)?;
+                    w.write_all(b"\n")?;
                 }
-            } else {
-                let x = if dir == 1 {
-                    hazard_insets[dir] - 1
-                } else {
-                    game.grid.width - hazard_insets[dir]
-                };
-                for y in 0..game.grid.height {
-                    game.grid[v2(x as _, y as _)].hazard = true;
+                DataFormat::Binary => position.write_binary(w)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[tokio::test]
+    async fn record() {
+        use super::*;
+        use crate::agents::{FloodHeuristic, MobilityAgent};
+        use rand::SeedableRng;
+        logging();
+
+        let agents = [
+            Agent::Mobility(MobilityAgent::default()),
+            Agent::Mobility(MobilityAgent::default()),
+        ];
+        let heuristic = FloodHeuristic::default();
+
+        let record = |seed| {
+            let agents = &agents;
+            let heuristic = &heuristic;
+            async move {
+                let mut rng = SmallRng::seed_from_u64(seed);
+                let mut game = init_game(7, 7, 2, &mut rng);
+                record_game(agents, &mut game, 0, &mut rng, heuristic, 1).await
+            }
+        };
+
+        let (outcome, positions) = record(3).await;
+        info!("{outcome:?} after {} turns", positions.len());
+        assert!(!positions.is_empty());
+        // Same seed, same game
+        assert_eq!(record(3).await.1, positions);
+
+        for (turn, position) in positions.iter().enumerate() {
+            assert_eq!(position.turn, turn);
+            if let Outcome::Winner(winner) = outcome {
+                assert_eq!(position.winner, Some(winner));
+            }
+            let game = position.to_game();
+            for (i, snake) in position.snakes.iter().enumerate() {
+                if snake.r#move.is_some() {
+                    assert_eq!(game.snakes[i].head(), snake.body[0]);
+                    for d in Direction::all() {
+                        if !game.move_is_valid(i as u8, d) {
+                            assert_eq!(snake.values[d as usize] as f64, crate::search::LOSS);
+                        }
+                    }
                 }
             }
         }
+
+        // Both formats can be read again
+        let mut binary = Vec::new();
+        DataFormat::Binary.write(&mut binary, &positions).unwrap();
+        let mut reader = &binary[..];
+        let mut read = Vec::new();
+        while let Some(position) = Position::read_binary(&mut reader).unwrap() {
+            read.push(position);
+        }
+        assert_eq!(read, positions);
+
+        let mut jsonl = Vec::new();
+        DataFormat::Jsonl.write(&mut jsonl, &positions).unwrap();
+        info!("binary: {}B, jsonl: {}B", binary.len(), jsonl.len());
+        let read: Vec<Position> = std::str::from_utf8(&jsonl)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(read, positions);
+    }
+
+    #[test]
+    fn round_trip() {
+        use super::*;
+        use crate::agents::FloodHeuristic;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . . 1 .
+            . ^ . o . ^ .
+            . . . . . ^ .
+            . . . . . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Wrapped);
+        game.settings.hazard_damage = 100;
+        game.settings.minimum_food = 3;
+        game.obstacles.push(v2(4, 4));
+        game.grid[v2(4, 4)].t = CellT::Owned;
+        game.rehash();
+
+        let moves = [Direction::Up; MAX_SNAKES];
+        let position = Position::new(&game, &moves, &FloodHeuristic::default(), 1);
+        let restored = position.to_game();
+        info!("{restored:?}");
+        assert_eq!(restored.mode, GameMode::Wrapped);
+        assert!(restored.grid.wrapped);
+        assert_eq!(restored.settings, game.settings);
+        assert_eq!(restored.obstacles, game.obstacles);
+        assert!(restored.grid[v2(4, 4)].t == CellT::Owned);
+        assert_eq!(restored.zobrist(), game.compute_zobrist());
+
+        let mut binary = Vec::new();
+        position.write_binary(&mut binary).unwrap();
+        let read = Position::read_binary(&mut &binary[..]).unwrap();
+        assert_eq!(read.as_ref(), Some(&position));
+        let json = serde_json::to_string(&position).unwrap();
+        assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), position);
+    }
+
+    #[test]
+    fn perspective() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . 0 . . .
+            . ^ . 1 .
+            . ^ . ^ .
+            . . . . ."#,
+        )
+        .unwrap();
+        let view = super::perspective(&game, 1);
+        assert_eq!(view.snakes[0].head(), game.snakes[1].head());
+        assert_eq!(view.snakes[1].head(), game.snakes[0].head());
+        // The hash matches the swapped position
+        assert_eq!(view.zobrist(), view.compute_zobrist());
+        assert_ne!(view.zobrist(), game.zobrist());
+    }
+
+    #[tokio::test]
+    async fn many_snakes() {
+        use super::*;
+        use crate::agents::RandomAgent;
+        use crate::game::MAX_SNAKES;
+        use rand::SeedableRng;
+        logging();
+
+        let agents = vec![Agent::Random(RandomAgent); MAX_SNAKES];
+        let mut rng = SmallRng::seed_from_u64(8);
+        let mut game = init_game(11, 11, MAX_SNAKES, &mut rng);
+        info!("{game:?}");
+        // Every snake starts on a separate cell
+        let mut heads: Vec<Vec2D> = game.snakes.iter().map(|s| s.head()).collect();
+        heads.sort_by_key(|p| (p.x, p.y));
+        heads.dedup();
+        assert_eq!(heads.len(), MAX_SNAKES);
+
+        let outcome = play_game(&agents, &mut game, 0, &mut rng).await;
+        info!("{outcome:?} after {} turns", game.turn);
+        assert_ne!(outcome, Outcome::None);
     }
-    Outcome::Match
 }
@@ -0,0 +1,234 @@
+//! # Territory
+//!
+//! Statistics about the regions that the snakes control, see
+//! [FloodFill::report], which allow heuristics to reason about the food
+//! access and chokepoints of the opponents, not just our own.
+
+use crate::env::{Direction, Vec2D};
+use crate::floodfill::{FCell, FloodFill};
+use crate::game::Game;
+use crate::grid::{CellT, Grid};
+
+/// Territory of a single snake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnakeReport {
+    /// Number of owned cells
+    pub space: usize,
+    /// Reachable food with the number of moves to reach it, nearest first
+    ///
+    /// The food is reachable, if the snake could get there without the
+    /// other snakes competing for the cells (see [FloodFill::flood_alone]).
+    pub food: Vec<(Vec2D, u16)>,
+    /// Number of owned hazard cells
+    pub hazards: usize,
+    /// Number of cells that another snake reached at the same distance,
+    /// regardless of who won them
+    pub contested: usize,
+    /// Owned cells that disconnect a part of the territory from the head
+    /// (articulation points)
+    pub chokepoints: Vec<Vec2D>,
+}
+
+/// Territories of all snakes, indexed by their id.
+///
+/// Dead snakes have an empty territory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FloodReport {
+    pub snakes: Vec<SnakeReport>,
+}
+
+impl FloodReport {
+    /// Collects the territories, the `flood_fill` has to be filled by
+    /// [FloodFill::report] for the `game`.
+    #[must_use]
+    pub(crate) fn new(flood_fill: &FloodFill, game: &Game) -> Self {
+        let mut snakes = vec![SnakeReport::default(); game.snakes.len()];
+
+        for y in 0..flood_fill.height {
+            for x in 0..flood_fill.width {
+                let p = Vec2D::new(x as _, y as _);
+                let contested = flood_fill.contested(p);
+                for (id, report) in snakes.iter_mut().enumerate().take(16) {
+                    if contested & (1 << id) != 0 {
+                        report.contested += 1;
+                    }
+                }
+
+                if let FCell::Owned { id, .. } = flood_fill[p] {
+                    let report = &mut snakes[id as usize];
+                    report.space += 1;
+                    if game.grid[p].hazard {
+                        report.hazards += 1;
+                    }
+                }
+            }
+        }
+
+        let (width, height) = (flood_fill.width, flood_fill.height);
+        FloodFill::with_workspace(width, height, |reachable| {
+            for (id, (report, snake)) in snakes.iter_mut().zip(&game.snakes).enumerate() {
+                if !snake.alive() {
+                    continue;
+                }
+                let id = id as u8;
+                reachable.flood_alone(game, id);
+                report.food = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| Vec2D::new(x as _, y as _)))
+                    .filter(|&p| game.grid[p].t == CellT::Food)
+                    .filter_map(|p| match reachable[p] {
+                        FCell::Owned {
+                            id: i, distance, ..
+                        } if i == id => Some((p, distance + 1)),
+                        _ => None,
+                    })
+                    .collect();
+                report.food.sort_by_key(|&(_, d)| d);
+
+                report.chokepoints = chokepoints(flood_fill, &game.grid, id, snake.head());
+            }
+        });
+        Self { snakes }
+    }
+}
+
+/// Finds the articulation points of the territory of the snake `id`,
+/// which is searched from its `head` (Tarjan's algorithm).
+fn chokepoints(flood_fill: &FloodFill, grid: &Grid, id: u8, head: Vec2D) -> Vec<Vec2D> {
+    let width = flood_fill.width;
+    let index = |p: Vec2D| p.x as usize + p.y as usize * width;
+    // The head is included to find cycles through it
+    let owned = |p: Vec2D| {
+        p == head
+            || flood_fill.has(p) && matches!(flood_fill[p], FCell::Owned { id: i, .. } if i == id)
+    };
+
+    // Discovery time and lowest reachable discovery time, zero if unvisited
+    let area = flood_fill.width * flood_fill.height;
+    let mut discovered = vec![0u32; area];
+    let mut low = vec![0u32; area];
+    let mut is_chokepoint = vec![false; area];
+
+    let mut time = 1;
+    discovered[index(head)] = time;
+    low[index(head)] = time;
+    // Cells on the path from the head with the next direction to search
+    let mut stack = vec![(head, 0)];
+    while let Some((u, next)) = stack.last_mut() {
+        let u = *u;
+        if *next < 4 {
+            let v = grid.apply(u, Direction::from(*next as u8));
+            *next += 1;
+            if !owned(v) {
+                continue;
+            }
+            if discovered[index(v)] == 0 {
+                time += 1;
+                discovered[index(v)] = time;
+                low[index(v)] = time;
+                stack.push((v, 0));
+            } else {
+                low[index(u)] = low[index(u)].min(discovered[index(v)]);
+            }
+        } else {
+            stack.pop();
+            if let Some(&(parent, _)) = stack.last() {
+                low[index(parent)] = low[index(parent)].min(low[index(u)]);
+                // The head is not part of the territory
+                if parent != head && low[index(u)] >= discovered[index(parent)] {
+                    is_chokepoint[index(parent)] = true;
+                }
+            }
+        }
+    }
+
+    (0..area)
+        .filter(|&i| is_chokepoint[i])
+        .map(|i| Vec2D::new((i % width) as _, (i / width) as _))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn report() {
+        use super::*;
+        use crate::env::v2;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            o . . . . . .
+            . . . . . . .
+            . 0 . . . 1 .
+            . ^ . . . ^ .
+            . ^ . o . ^ ."#,
+        )
+        .unwrap();
+        game.grid[v2(6, 4)].hazard = true;
+        game.grid[v2(6, 3)].hazard = true;
+        info!("{game:?}");
+
+        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+        let report = flood_fill.report(&game);
+        info!("{flood_fill:?}");
+        info!("{report:?}");
+
+        let own = &report.snakes[0];
+        assert_eq!(own.space, flood_fill.count_space(0));
+        // Reached at the same time as the enemy, who wins the tie
+        assert!(matches!(flood_fill[v2(3, 0)], FCell::Owned { id: 1, .. }));
+        assert_eq!(own.food, vec![(v2(0, 4), 3), (v2(3, 0), 4)]);
+        assert_eq!(own.hazards, 0);
+        let enemy = &report.snakes[1];
+        assert_eq!(enemy.space, flood_fill.count_space(1));
+        assert_eq!(enemy.food, vec![(v2(3, 0), 4), (v2(0, 4), 7)]);
+        assert_eq!(enemy.hazards, 2);
+
+        // The middle column is reached by both snakes at the same time
+        for y in 0..5 {
+            assert_eq!(flood_fill.contested(v2(3, y)), 0b11);
+        }
+        assert_eq!(flood_fill.contested(v2(2, 2)), 0);
+        assert_eq!(own.contested, 5);
+        assert_eq!(enemy.contested, 5);
+    }
+
+    #[test]
+    fn chokepoints() {
+        use super::*;
+        use crate::env::v2;
+        use crate::rules::GameMode;
+        logging();
+
+        // The top is only reachable through the right column
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            > > > > > v .
+            . . . . . v .
+            . . . . . v .
+            . . . . . 0 .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        // Bodies are permanent walls
+        game.set_mode(GameMode::Constrictor);
+        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+        let report = flood_fill.report(&game);
+        info!("{flood_fill:?}");
+        info!("{report:?}");
+        assert_eq!(
+            report.snakes[0].chokepoints,
+            vec![v2(6, 1), v2(6, 2), v2(6, 3), v2(6, 4), v2(6, 5)]
+        );
+
+        // Without the wall, there are no chokepoints
+        game.set_mode(GameMode::Standard);
+        let report = flood_fill.report(&game);
+        assert!(report.snakes[0].chokepoints.is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! # Traps
+//!
+//! Detects snakes that are enclosed in a region that is smaller than their
+//! body, and moves of other snakes that would enclose them.
+//!
+//! The regions are derived from a [FloodFill::flood_snakes]: All cells that
+//! are owned by any snake are passable, which accounts for tails that are
+//! vacating in time, and bodies that are left over are walls.
+
+use crate::env::{Direction, Vec2D};
+use crate::floodfill::{FCell, FloodFill};
+use crate::game::Game;
+
+/// Trap status of a single snake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnakeTrap {
+    /// Size of the largest region the snake can enter, if it is smaller
+    /// than the body and the snake cannot follow its tail (forced loss)
+    pub trapped: Option<usize>,
+    /// Moves of other snakes that would trap this snake,
+    /// as their id and the cell they move to (forced kill)
+    pub threats: Vec<(u8, Vec2D)>,
+}
+
+/// Trap status of all snakes, indexed by their id.
+///
+/// Dead snakes are neither trapped nor threatened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrapReport {
+    pub snakes: Vec<SnakeTrap>,
+}
+
+impl TrapReport {
+    /// Detects the traps, the `flood_fill` has to be filled with
+    /// [FloodFill::flood_snakes] for the `game`.
+    #[must_use]
+    pub fn new(flood_fill: &FloodFill, game: &Game) -> Self {
+        let mut regions = Regions::new(flood_fill, game);
+        let mut snakes = vec![SnakeTrap::default(); game.snakes.len()];
+
+        for (id, snake) in game.snakes.iter().enumerate() {
+            if snake.alive() {
+                let (space, tail) = regions.escape(id as u8, snake.head(), None);
+                if !tail && space < snake.body.len() {
+                    snakes[id].trapped = Some(space);
+                }
+            }
+        }
+
+        for (attacker, snake) in game.snakes.iter().enumerate() {
+            if !snake.alive() {
+                continue;
+            }
+            for d in Direction::all() {
+                let p = game.grid.apply(snake.head(), d);
+                if !regions.passable(p) {
+                    continue;
+                }
+
+                for (victim, target) in game.snakes.iter().enumerate() {
+                    if victim == attacker || !target.alive() || snakes[victim].trapped.is_some() {
+                        continue;
+                    }
+                    // The victim could win the head-to-head
+                    if game.grid.distance(target.head(), p) == 1
+                        && target.body.len() >= snake.body.len()
+                    {
+                        continue;
+                    }
+                    let (space, tail) = regions.escape(victim as u8, target.head(), Some(p));
+                    if !tail && space < target.body.len() {
+                        snakes[victim].threats.push((attacker as u8, p));
+                    }
+                }
+            }
+        }
+
+        Self { snakes }
+    }
+
+    /// Returns if the snake `id` is trapped and going to die.
+    pub fn trapped(&self, id: u8) -> bool {
+        self.snakes
+            .get(id as usize)
+            .is_some_and(|s| s.trapped.is_some())
+    }
+
+    /// Returns the number of snakes that the snake `id` can trap with its
+    /// next move.
+    pub fn threatened_by(&self, id: u8) -> usize {
+        self.snakes
+            .iter()
+            .filter(|s| s.threats.iter().any(|&(attacker, _)| attacker == id))
+            .count()
+    }
+
+    /// Features for the heuristics of the snake `id`.
+    ///
+    /// The first is the number of trapped enemies, minus one if the snake
+    /// is trapped itself. The second is the number of enemies the snake can
+    /// trap, minus the number of enemies that can trap the snake.
+    pub fn features(&self, id: u8) -> (f64, f64) {
+        let enemies_trapped = (0..self.snakes.len() as u8)
+            .filter(|&i| i != id && self.trapped(i))
+            .count();
+        let traps = enemies_trapped as f64 - self.trapped(id) as u8 as f64;
+
+        let mut attackers = self.snakes.get(id as usize).map_or(Vec::new(), |s| {
+            s.threats.iter().map(|&(attacker, _)| attacker).collect()
+        });
+        // The threats are ordered by attacker
+        attackers.dedup();
+        let threats = self.threatened_by(id) as f64 - attackers.len() as f64;
+        (traps, threats)
+    }
+}
+
+/// Connected regions of the passable cells of a floodfill.
+struct Regions<'a> {
+    flood_fill: &'a FloodFill,
+    game: &'a Game,
+    /// Id + 1 of the snake that occupied the cell before the floodfill
+    bodies: Vec<u8>,
+    /// Search in which the cell has been visited last
+    visited: Vec<u32>,
+    search: u32,
+    queue: Vec<Vec2D>,
+}
+
+impl<'a> Regions<'a> {
+    fn new(flood_fill: &'a FloodFill, game: &'a Game) -> Self {
+        let area = flood_fill.width * flood_fill.height;
+        let mut regions = Self {
+            flood_fill,
+            game,
+            bodies: vec![0; area],
+            visited: vec![0; area],
+            search: 0,
+            queue: Vec::with_capacity(area),
+        };
+        for (id, snake) in game.snakes.iter().enumerate() {
+            for &p in &snake.body {
+                let i = regions.index(p);
+                regions.bodies[i] = id as u8 + 1;
+            }
+        }
+        regions
+    }
+
+    fn index(&self, p: Vec2D) -> usize {
+        p.x as usize + p.y as usize * self.flood_fill.width
+    }
+
+    fn passable(&self, p: Vec2D) -> bool {
+        self.flood_fill.has(p) && matches!(self.flood_fill[p], FCell::Owned { .. })
+    }
+
+    /// Searches the regions next to the `head` of the snake `id`, without
+    /// entering the `blocked` cell.
+    ///
+    /// Returns the size of the largest region and if the snake can follow
+    /// its tail, meaning that it reaches one of its former body cells.
+    fn escape(&mut self, id: u8, head: Vec2D, blocked: Option<Vec2D>) -> (usize, bool) {
+        self.search += 1;
+        let mut largest = 0;
+        let mut tail = false;
+
+        for d in Direction::all() {
+            let start = self.game.grid.apply(head, d);
+            if Some(start) == blocked || !self.passable(start) {
+                continue;
+            }
+            let i = self.index(start);
+            if self.visited[i] == self.search {
+                continue;
+            }
+            self.visited[i] = self.search;
+
+            let mut size = 0;
+            self.queue.clear();
+            self.queue.push(start);
+            while let Some(p) = self.queue.pop() {
+                size += 1;
+                tail |= self.bodies[self.index(p)] == id + 1;
+                for d in Direction::all() {
+                    let next = self.game.grid.apply(p, d);
+                    if Some(next) == blocked || !self.passable(next) {
+                        continue;
+                    }
+                    let i = self.index(next);
+                    if self.visited[i] != self.search {
+                        self.visited[i] = self.search;
+                        self.queue.push(next);
+                    }
+                }
+            }
+            largest = largest.max(size);
+        }
+        (largest, tail)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn tail_chase() {
+        use super::*;
+        use crate::rules::GameMode;
+        logging();
+
+        // Enclosed by the own body
+        let mut game = Game::parse(
+            r#"
+            > > > v .
+            ^ . . v .
+            ^ 0 . v .
+            . ^ < < .
+            . . . . ."#,
+        )
+        .unwrap();
+        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+        flood_fill.flood_snakes(&game);
+        info!("{flood_fill:?}");
+        let traps = TrapReport::new(&flood_fill, &game);
+        info!("{traps:?}");
+        // The tail is vacating in time
+        assert!(!traps.trapped(0));
+
+        // Bodies are permanent walls
+        game.set_mode(GameMode::Constrictor);
+        flood_fill.flood_snakes(&game);
+        info!("{flood_fill:?}");
+        let traps = TrapReport::new(&flood_fill, &game);
+        info!("{traps:?}");
+        assert_eq!(traps.snakes[0].trapped, Some(3));
+    }
+
+    #[test]
+    fn threats() {
+        use super::*;
+        use crate::env::v2;
+        use crate::rules::GameMode;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . .
+            > > > > > > v .
+            . > 1 . . . 0 ."#,
+        )
+        .unwrap();
+        game.set_mode(GameMode::Constrictor);
+        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
+        flood_fill.flood_snakes(&game);
+        info!("{flood_fill:?}");
+        let traps = TrapReport::new(&flood_fill, &game);
+        info!("{traps:?}");
+
+        assert!(!traps.trapped(0));
+        assert!(!traps.trapped(1));
+        // Moving left leaves only two cells for the enemy
+        assert_eq!(traps.snakes[1].threats, vec![(0, v2(5, 0))]);
+        assert!(traps.snakes[0].threats.is_empty());
+        assert_eq!(traps.threatened_by(0), 1);
+        assert_eq!(traps.threatened_by(1), 0);
+        assert_eq!(traps.features(0), (0.0, 1.0));
+        assert_eq!(traps.features(1), (0.0, -1.0));
+    }
+}